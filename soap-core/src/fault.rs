@@ -0,0 +1,290 @@
+use alloc::{borrow::Cow, string::String, vec::Vec};
+use core::fmt::{self, Write};
+
+use crate::{message::Message, version::SoapVersion, xml::escape};
+
+/// Class of a SOAP fault, named after the SOAP 1.2 codes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FaultCode {
+    VersionMismatch,
+    MustUnderstand,
+    DataEncodingUnknown,
+    Sender,
+    Receiver,
+}
+
+impl fmt::Display for FaultCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FaultCode::VersionMismatch => "VersionMismatch",
+            FaultCode::MustUnderstand => "MustUnderstand",
+            FaultCode::DataEncodingUnknown => "DataEncodingUnknown",
+            FaultCode::Sender => "Sender",
+            FaultCode::Receiver => "Receiver",
+        })
+    }
+}
+
+impl FaultCode {
+    /// SOAP 1.1 name of the code
+    fn soap11_name(&self) -> &'static str {
+        match self {
+            FaultCode::VersionMismatch => "VersionMismatch",
+            FaultCode::MustUnderstand => "MustUnderstand",
+            FaultCode::DataEncodingUnknown | FaultCode::Sender => "Client",
+            FaultCode::Receiver => "Server",
+        }
+    }
+}
+
+/// SOAP fault borrowing its static subcodes and reasons.
+///
+/// Reasons are keyed by language tag, the detail is kept as the serialized
+/// content of the `Detail` element.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fault {
+    code: FaultCode,
+    sub_codes: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+    reasons: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+    detail: Option<String>,
+}
+
+impl Fault {
+    pub fn new(
+        code: FaultCode,
+        language: impl Into<Cow<'static, str>>,
+        reason: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        Self {
+            code,
+            sub_codes: Vec::new(),
+            reasons: alloc::vec![(language.into(), reason.into())],
+            detail: None,
+        }
+    }
+
+    /// Add a subcode nested in the previous ones
+    pub fn with_subcode(
+        mut self,
+        namespace: impl Into<Cow<'static, str>>,
+        name: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        self.sub_codes.push((namespace.into(), name.into()));
+        self
+    }
+
+    pub fn with_reason(
+        mut self,
+        language: impl Into<Cow<'static, str>>,
+        reason: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        self.reasons.push((language.into(), reason.into()));
+        self
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn code(&self) -> FaultCode {
+        self.code
+    }
+
+    /// Namespace and local name of the subcodes, outermost first
+    pub fn sub_codes(&self) -> &[(Cow<'static, str>, Cow<'static, str>)] {
+        &self.sub_codes
+    }
+
+    /// Language tag and text of the reasons
+    pub fn reasons(&self) -> &[(Cow<'static, str>, Cow<'static, str>)] {
+        &self.reasons
+    }
+
+    pub fn detail(&self) -> Option<&str> {
+        self.detail.as_deref()
+    }
+
+    /// Reason in the first of the preferred languages available, matched on
+    /// the primary subtag, falling back to English then to the first reason.
+    pub fn reason(&self, preferred: &[&str]) -> &str {
+        self.best_reason(preferred).1
+    }
+
+    fn best_reason(&self, preferred: &[&str]) -> (&str, &str) {
+        fn primary(tag: &str) -> &str {
+            tag.split('-').next().unwrap_or_default()
+        }
+        preferred
+            .iter()
+            .chain(core::iter::once(&"en"))
+            .find_map(|wanted| {
+                self.reasons
+                    .iter()
+                    .find(|(lang, _)| primary(lang).eq_ignore_ascii_case(primary(wanted)))
+            })
+            .or(self.reasons.first())
+            .map(|(lang, text)| (lang.as_ref(), text.as_ref()))
+            .unwrap_or(("en", ""))
+    }
+
+    /// Write the `Fault` element, SOAP 1.1 only carries one reason so the
+    /// best match for the preferred languages is used and the subcodes are
+    /// appended to the code with dots.
+    pub fn write_to<W: Write>(
+        &self,
+        version: SoapVersion,
+        preferred: &[&str],
+        out: &mut W,
+    ) -> fmt::Result {
+        let namespace = version.namespace();
+        match version {
+            SoapVersion::Soap11 => {
+                write!(
+                    out,
+                    r#"<env:Fault xmlns:env="{}"><faultcode>env:{}"#,
+                    namespace,
+                    self.code.soap11_name()
+                )?;
+                for (_, name) in &self.sub_codes {
+                    out.write_char('.')?;
+                    escape(out, name)?;
+                }
+                let (lang, reason) = self.best_reason(preferred);
+                out.write_str(r#"</faultcode><faultstring xml:lang=""#)?;
+                escape(out, lang)?;
+                out.write_str(r#"">"#)?;
+                escape(out, reason)?;
+                out.write_str("</faultstring>")?;
+                if let Some(detail) = &self.detail {
+                    write!(out, "<detail>{}</detail>", detail)?;
+                }
+            }
+            SoapVersion::Soap12 => {
+                write!(
+                    out,
+                    r#"<env:Fault xmlns:env="{}"><env:Code><env:Value>env:{}</env:Value>"#,
+                    namespace, self.code
+                )?;
+                // Each subcode value declares the prefix it uses
+                for (namespace, name) in &self.sub_codes {
+                    out.write_str(r#"<env:Subcode><env:Value xmlns:c=""#)?;
+                    escape(out, namespace)?;
+                    out.write_str(r#"">c:"#)?;
+                    escape(out, name)?;
+                    out.write_str("</env:Value>")?;
+                }
+                for _ in &self.sub_codes {
+                    out.write_str("</env:Subcode>")?;
+                }
+                out.write_str("</env:Code><env:Reason>")?;
+                for (lang, text) in &self.reasons {
+                    out.write_str(r#"<env:Text xml:lang=""#)?;
+                    escape(out, lang)?;
+                    out.write_str(r#"">"#)?;
+                    escape(out, text)?;
+                    out.write_str("</env:Text>")?;
+                }
+                out.write_str("</env:Reason>")?;
+                if let Some(detail) = &self.detail {
+                    write!(out, "<env:Detail>{}</env:Detail>", detail)?;
+                }
+            }
+        }
+        out.write_str("</env:Fault>")
+    }
+
+    /// Message holding the fault in its body
+    pub fn into_message(self, version: SoapVersion, preferred: &[&str]) -> Message {
+        let mut fault = String::new();
+        // Writing to a String cannot fail
+        let _ = self.write_to(version, preferred, &mut fault);
+        let mut msg = Message::new(version);
+        msg.push_body(fault);
+        msg
+    }
+}
+
+#[cfg(feature = "isolang")]
+impl Fault {
+    /// Same as [`Fault::reason`] with languages as ISO 639 codes
+    pub fn reason_for(&self, preferred: &[isolang::Language]) -> &str {
+        let tags: Vec<&str> = preferred
+            .iter()
+            .map(|l| l.to_639_1().unwrap_or(l.to_639_3()))
+            .collect();
+        self.reason(&tags)
+    }
+
+    /// Reasons keyed by language, tags that aren't ISO 639 codes are left
+    /// out
+    pub fn reason_languages(&self) -> impl Iterator<Item = (isolang::Language, &str)> {
+        self.reasons.iter().filter_map(|(lang, text)| {
+            let lang = lang.split('-').next().unwrap_or_default();
+            isolang::Language::from_639_1(lang)
+                .or(isolang::Language::from_639_3(lang))
+                .map(|l| (l, text.as_ref()))
+        })
+    }
+}
+
+#[cfg(feature = "url")]
+impl Fault {
+    /// Subcodes whose namespace is a valid URL
+    pub fn sub_code_urls(&self) -> impl Iterator<Item = (url::Url, &str)> {
+        self.sub_codes
+            .iter()
+            .filter_map(|(ns, name)| Some((url::Url::parse(ns).ok()?, name.as_ref())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    fn fault() -> Fault {
+        Fault::new(FaultCode::Sender, "en", "Invalid <argument>")
+            .with_reason("fr-FR", "Argument invalide")
+            .with_subcode("http://www.onvif.org/ver10/error", "InvalidArgVal")
+            .with_subcode("http://www.example.org", "NoProfile")
+    }
+
+    #[test]
+    fn test_reason() {
+        let fault = fault();
+        assert_eq!(fault.reason(&[]), "Invalid <argument>");
+        assert_eq!(fault.reason(&["de", "fr"]), "Argument invalide");
+        assert_eq!(
+            Fault::new(FaultCode::Receiver, "fr", "Occupé").reason(&["en"]),
+            "Occupé"
+        );
+        assert_eq!(FaultCode::Receiver.to_string(), "Receiver");
+    }
+
+    #[test]
+    fn test_write() {
+        let fault = fault();
+        let mut out = String::new();
+        fault
+            .write_to(SoapVersion::Soap11, &["fr"], &mut out)
+            .unwrap();
+        assert_eq!(
+            out,
+            r#"<env:Fault xmlns:env="http://schemas.xmlsoap.org/soap/envelope/"><faultcode>env:Client.InvalidArgVal.NoProfile</faultcode><faultstring xml:lang="fr-FR">Argument invalide</faultstring></env:Fault>"#
+        );
+
+        let msg = fault
+            .with_detail(r#"<m:Token xmlns:m="urn:m">main</m:Token>"#)
+            .into_message(SoapVersion::Soap12, &[]);
+        let body = &msg.body()[0];
+        assert!(body.contains(
+            r#"<env:Subcode><env:Value xmlns:c="http://www.onvif.org/ver10/error">c:InvalidArgVal</env:Value><env:Subcode><env:Value xmlns:c="http://www.example.org">c:NoProfile</env:Value></env:Subcode></env:Subcode>"#
+        ));
+        assert!(body.contains(r#"<env:Text xml:lang="en">Invalid &lt;argument&gt;</env:Text>"#));
+        assert!(
+            body.contains(r#"<env:Detail><m:Token xmlns:m="urn:m">main</m:Token></env:Detail>"#)
+        );
+    }
+}
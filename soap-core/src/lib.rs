@@ -0,0 +1,19 @@
+//! Message model of the SOAP router without the router: SOAP versions,
+//! faults and messages made of serialized header blocks and body entries.
+//!
+//! Only `alloc` is required, `url`, `isolang` and `xmltree` based accessors
+//! are behind the features of the same name.
+#![no_std]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod fault;
+pub mod message;
+pub mod version;
+mod xml;
+
+pub use fault::{Fault, FaultCode};
+pub use message::Message;
+pub use version::{SoapVersion, SOAP11_NAMESPACE, SOAP12_NAMESPACE};
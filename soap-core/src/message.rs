@@ -0,0 +1,164 @@
+use alloc::{string::String, vec::Vec};
+use core::fmt::{self, Write};
+
+use crate::version::SoapVersion;
+
+/// SOAP message held as serialized header blocks and body entries, each
+/// entry being a standalone XML fragment declaring the namespaces it uses.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Message {
+    version: SoapVersion,
+    headers: Vec<String>,
+    body: Vec<String>,
+    no_content: bool,
+}
+
+impl Message {
+    pub fn new(version: SoapVersion) -> Self {
+        Self {
+            version,
+            ..Default::default()
+        }
+    }
+
+    /// Empty message of a one-way operation, answered with no body
+    pub fn no_content(version: SoapVersion) -> Self {
+        Self {
+            no_content: true,
+            ..Self::new(version)
+        }
+    }
+
+    pub fn is_no_content(&self) -> bool {
+        self.no_content
+    }
+
+    pub fn version(&self) -> SoapVersion {
+        self.version
+    }
+
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    pub fn body(&self) -> &[String] {
+        &self.body
+    }
+
+    pub fn push_header(&mut self, block: impl Into<String>) {
+        self.headers.push(block.into());
+    }
+
+    pub fn push_body(&mut self, entry: impl Into<String>) {
+        self.body.push(entry.into());
+    }
+
+    /// Write the envelope, the `Header` element is left out when there is no
+    /// header block.
+    pub fn write_to<W: Write>(&self, out: &mut W) -> fmt::Result {
+        write!(
+            out,
+            r#"<env:Envelope xmlns:env="{}">"#,
+            self.version.namespace()
+        )?;
+        if !self.headers.is_empty() {
+            out.write_str("<env:Header>")?;
+            self.headers.iter().try_for_each(|h| out.write_str(h))?;
+            out.write_str("</env:Header>")?;
+        }
+        out.write_str("<env:Body>")?;
+        self.body.iter().try_for_each(|b| out.write_str(b))?;
+        out.write_str("</env:Body></env:Envelope>")
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut out = String::new();
+        // Writing to a String cannot fail
+        let _ = self.write_to(&mut out);
+        out
+    }
+}
+
+#[cfg(feature = "xmltree")]
+impl Message {
+    /// Read a parsed envelope, its header blocks and body entries are
+    /// written back out as fragments.
+    pub fn from_envelope(envelope: &xmltree::Element) -> Option<Self> {
+        let version = SoapVersion::from_namespace(envelope.namespace.as_deref()?)?;
+        if envelope.name != "Envelope" {
+            return None;
+        }
+        let entries = |name: &str| -> Option<Vec<String>> {
+            let Some(parent) = envelope.get_child((name, version.namespace())) else {
+                return Some(Vec::new());
+            };
+            parent
+                .children
+                .iter()
+                .filter_map(|c| c.as_element())
+                .map(|e| {
+                    let mut buf = Vec::new();
+                    let config = xmltree::EmitterConfig::new().write_document_declaration(false);
+                    e.write_with_config(&mut buf, config).ok()?;
+                    String::from_utf8(buf).ok()
+                })
+                .collect()
+        };
+        Some(Self {
+            version,
+            headers: entries("Header")?,
+            body: entries("Body")?,
+            no_content: false,
+        })
+    }
+
+    /// Parse the envelope of the message
+    pub fn to_envelope(&self) -> Result<xmltree::Element, xmltree::ParseError> {
+        xmltree::Element::parse(self.to_xml().as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let mut msg = Message::new(SoapVersion::Soap11);
+        msg.push_body(r#"<m:Get xmlns:m="urn:m"/>"#);
+        assert_eq!(
+            msg.to_xml(),
+            r#"<env:Envelope xmlns:env="http://schemas.xmlsoap.org/soap/envelope/"><env:Body><m:Get xmlns:m="urn:m"/></env:Body></env:Envelope>"#
+        );
+
+        msg.push_header(r#"<m:H xmlns:m="urn:m">1</m:H>"#);
+        assert!(msg
+            .to_xml()
+            .contains(r#"<env:Header><m:H xmlns:m="urn:m">1</m:H></env:Header><env:Body>"#));
+        assert!(Message::no_content(SoapVersion::Soap12).is_no_content());
+    }
+
+    #[cfg(feature = "xmltree")]
+    #[test]
+    fn test_envelope_round_trip() {
+        let raw = r#"<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:m="urn:m">
+            <s:Header><m:H>1</m:H></s:Header>
+            <s:Body><m:Get><m:Name>a &amp; b</m:Name></m:Get></s:Body>
+        </s:Envelope>"#;
+        let msg =
+            Message::from_envelope(&xmltree::Element::parse(raw.as_bytes()).unwrap()).unwrap();
+        assert_eq!(msg.version(), SoapVersion::Soap12);
+        assert_eq!(msg.headers().len(), 1);
+
+        let envelope = msg.to_envelope().unwrap();
+        let name = envelope
+            .get_child(("Body", crate::SOAP12_NAMESPACE))
+            .and_then(|b| b.get_child(("Get", "urn:m")))
+            .and_then(|g| g.get_child(("Name", "urn:m")))
+            .and_then(|n| n.get_text())
+            .unwrap();
+        assert_eq!(name, "a & b");
+
+        assert!(Message::from_envelope(&xmltree::Element::new("Envelope")).is_none());
+    }
+}
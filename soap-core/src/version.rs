@@ -0,0 +1,36 @@
+pub const SOAP11_NAMESPACE: &str = "http://schemas.xmlsoap.org/soap/envelope/";
+pub const SOAP12_NAMESPACE: &str = "http://www.w3.org/2003/05/soap-envelope";
+
+/// Version of the SOAP envelope
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum SoapVersion {
+    Soap11,
+    #[default]
+    Soap12,
+}
+
+impl SoapVersion {
+    pub fn from_namespace(namespace: &str) -> Option<Self> {
+        match namespace {
+            SOAP11_NAMESPACE => Some(SoapVersion::Soap11),
+            SOAP12_NAMESPACE => Some(SoapVersion::Soap12),
+            _ => None,
+        }
+    }
+
+    pub fn namespace(&self) -> &'static str {
+        match self {
+            SoapVersion::Soap11 => SOAP11_NAMESPACE,
+            SoapVersion::Soap12 => SOAP12_NAMESPACE,
+        }
+    }
+
+    /// Media type of the messages for this version
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            SoapVersion::Soap11 => "text/xml",
+            SoapVersion::Soap12 => "application/soap+xml",
+        }
+    }
+}
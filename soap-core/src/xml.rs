@@ -0,0 +1,18 @@
+use core::fmt::{self, Write};
+
+/// Write text or an attribute value with the XML special characters escaped
+pub(crate) fn escape<W: Write>(out: &mut W, value: &str) -> fmt::Result {
+    let mut rest = value;
+    while let Some(index) = rest.find(['&', '<', '>', '"', '\'']) {
+        out.write_str(&rest[..index])?;
+        out.write_str(match rest.as_bytes()[index] {
+            b'&' => "&amp;",
+            b'<' => "&lt;",
+            b'>' => "&gt;",
+            b'"' => "&quot;",
+            _ => "&apos;",
+        })?;
+        rest = &rest[index + 1..];
+    }
+    out.write_str(rest)
+}
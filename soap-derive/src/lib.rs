@@ -1,6 +1,6 @@
 use proc_macro::TokenStream;
 use quote::quote;
-use syn;
+use syn::parse::Parse;
 
 #[proc_macro_derive(SoapBody)]
 pub fn derive_soap_boady_fn(input: TokenStream) -> TokenStream {
@@ -22,30 +22,108 @@ pub fn derive_soap_header_fn(input: TokenStream) -> TokenStream {
     impl_derive_soap_header(&ast)
 }
 
+#[proc_macro_derive(SoapFaultDetail)]
+pub fn derive_soap_fault_detail_fn(input: TokenStream) -> TokenStream {
+    let ast = syn::parse(input).unwrap();
+
+    impl_derive_soap_fault_detail(&ast)
+}
+
+/// Qualified element name of the struct, as given by the `rename`, `prefix`
+/// and `namespaces` yaserde attributes
+struct ElementName {
+    name: String,
+    namespace: Option<String>,
+}
+
+/// One `"prefix" = "uri"` entry of `#[yaserde(namespaces = { ... })]`
+struct NamespaceEntry {
+    prefix: syn::LitStr,
+    uri: syn::LitStr,
+}
+
+impl Parse for NamespaceEntry {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let prefix = input.parse()?;
+        input.parse::<syn::Token![=]>()?;
+        let uri = input.parse()?;
+        Ok(NamespaceEntry { prefix, uri })
+    }
+}
+
+fn element_name(ast: &syn::DeriveInput) -> ElementName {
+    let mut name = ast.ident.to_string();
+    let mut prefix = None;
+    let mut namespaces = Vec::new();
+    for attr in ast.attrs.iter().filter(|a| a.path().is_ident("yaserde")) {
+        let _ = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                let value: syn::LitStr = meta.value()?.parse()?;
+                name = value.value();
+            } else if meta.path.is_ident("prefix") {
+                let value: syn::LitStr = meta.value()?.parse()?;
+                prefix = Some(value.value());
+            } else if meta.path.is_ident("namespaces") {
+                let value = meta.value()?;
+                let content;
+                syn::braced!(content in value);
+                let entries = content.parse_terminated(NamespaceEntry::parse, syn::Token![,])?;
+                namespaces.extend(entries);
+            } else if meta.input.peek(syn::Token![=]) {
+                // Skip values of other yaserde attributes
+                meta.value()?;
+                while !meta.input.is_empty() && !meta.input.peek(syn::Token![,]) {
+                    meta.input.parse::<proc_macro2::TokenTree>()?;
+                }
+            }
+            Ok(())
+        });
+    }
+    let namespace = prefix.and_then(|prefix| {
+        namespaces
+            .iter()
+            .find(|entry| entry.prefix.value() == prefix)
+            .map(|entry| entry.uri.value())
+    });
+    ElementName { name, namespace }
+}
+
+fn option_tokens(value: Option<String>) -> proc_macro2::TokenStream {
+    match value {
+        Some(value) => quote!(::std::option::Option::Some(#value)),
+        None => quote!(::std::option::Option::None),
+    }
+}
+
 fn impl_derive_soap_header(ast: &syn::DeriveInput) -> TokenStream {
     let struct_name = &ast.ident;
-    
+    let ElementName { name, namespace } = element_name(ast);
+    let namespace = option_tokens(namespace);
+
     let gen = quote! {
-        impl std::convert::TryFrom<soap_router::router::SoapRequest> for #struct_name {
-            type Error = soap_router::fault::SoapFault;
-
-            fn try_from(value: soap_router::router::SoapRequest) -> std::Result<Self, Self::Error> {
-                let buf = vec![].writer();
-                value.body.write(buf.by_ref()).unwrap();
-                let buf = buf.into_inner();
-                yaserde::de::from_reader(buf)
+        impl<S> ::soap_router::extract::FromSoapRequest<S> for #struct_name {
+            fn from_soap_request(
+                req: &::soap_router::router::SoapRequest,
+                _state: &S,
+            ) -> ::std::result::Result<Self, ::soap_router::fault::SoapFault> {
+                match req
+                    .headers
+                    .children
+                    .iter()
+                    .filter_map(|c| c.as_element())
+                    .find(|e| e.name == #name && e.namespace.as_deref() == #namespace)
+                {
+                    Some(elem) => ::soap_router::codec::from_element(elem),
+                    None => Err(::soap_router::codec::missing_header()),
+                }
             }
         }
 
-        impl std::convert::Into<soap_router::router::SoapMessage> for #struct_name {
-            fn into(self) -> soap_router::router::SoapMessage {
-                let buf = vec![].writer();
-                let buf = yaserde::ser::serialize_with_writer(&value, buf, Default::default()).unwrap();
-                let buf = buf.into_inner();
-                let elem = xmltree::Element::parse(buf).unwrap();
-                let mut msg = soap_router::router::SoapMessage::new();
-                msg.get_mut_headers().children.push(xmltree::XMLNode::Element(elem));
-                msg
+        impl ::std::convert::TryFrom<#struct_name> for ::soap_router::router::SoapMessage {
+            type Error = ::soap_router::fault::SoapFault;
+
+            fn try_from(value: #struct_name) -> ::std::result::Result<Self, Self::Error> {
+                ::soap_router::codec::header_message(&value)
             }
         }
     };
@@ -54,30 +132,38 @@ fn impl_derive_soap_header(ast: &syn::DeriveInput) -> TokenStream {
 
 fn impl_derive_soap_body(ast: &syn::DeriveInput) -> TokenStream {
     let struct_name = &ast.ident;
-    
+
     let gen = quote! {
-        impl std::convert::TryFrom<soap_router::router::SoapRequest> for #struct_name {
-            type Error = soap_router::fault::SoapFault;
-
-            fn try_from(value: soap_router::router::SoapRequest) -> std::Result<Self, Self::Error> {
-                let buf = vec![].writer();
-                value.body.write(buf.by_ref()).unwrap();
-                let buf = buf.into_inner();
-                yaserde::de::from_reader(buf)
+        impl<S> ::soap_router::extract::FromSoapRequest<S> for #struct_name {
+            fn from_soap_request(
+                req: &::soap_router::router::SoapRequest,
+                _state: &S,
+            ) -> ::std::result::Result<Self, ::soap_router::fault::SoapFault> {
+                ::soap_router::codec::from_request_body(req)
             }
         }
 
-        impl std::convert::Into<soap_router::router::SoapMessage> for #struct_name {
-            fn into(self) -> soap_router::router::SoapMessage {
-                let buf = vec![].writer();
-                let buf = yaserde::ser::serialize_with_writer(&value, buf, Default::default()).unwrap();
-                let buf = buf.into_inner();
-                let elem = xmltree::Element::parse(buf).unwrap();
-                let mut msg = soap_router::router::SoapMessage::new();
-                msg.get_mut_body().children.push(xmltree::XMLNode::Element(elem));
-                msg
+        impl ::std::convert::TryFrom<#struct_name> for ::soap_router::router::SoapMessage {
+            type Error = ::soap_router::fault::SoapFault;
+
+            fn try_from(value: #struct_name) -> ::std::result::Result<Self, Self::Error> {
+                ::soap_router::codec::body_message(&value)
             }
         }
     };
     gen.into()
 }
+
+fn impl_derive_soap_fault_detail(ast: &syn::DeriveInput) -> TokenStream {
+    let struct_name = &ast.ident;
+    let ElementName { name, namespace } = element_name(ast);
+    let namespace = option_tokens(namespace);
+
+    let gen = quote! {
+        impl ::soap_router::fault::FaultDetail for #struct_name {
+            const ELEMENT_NAME: &'static str = #name;
+            const NAMESPACE: ::std::option::Option<&'static str> = #namespace;
+        }
+    };
+    gen.into()
+}
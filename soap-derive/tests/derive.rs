@@ -0,0 +1,266 @@
+use axum::{body::Body, http::Request};
+use soap_derive::{SoapBody, SoapFaultDetail, SoapHeader};
+use soap_router::{
+    extract::State,
+    fault::SoapFault,
+    router::{SoapMessage, SoapRouter},
+    soap_client::{ClientError, SoapClient},
+};
+use tower::Service;
+use xmltree::Element;
+use yaserde::{YaDeserialize, YaSerialize};
+
+#[derive(Default, Debug, YaSerialize, YaDeserialize, SoapBody)]
+#[yaserde(
+    rename = "GetStockPrice",
+    prefix = "m",
+    namespaces = { "m" = "http://www.example.org" }
+)]
+struct GetStockPrice {
+    #[yaserde(rename = "StockName", prefix = "m")]
+    stock_name: String,
+}
+
+#[derive(Default, Debug, YaSerialize, YaDeserialize, SoapBody)]
+#[yaserde(
+    rename = "GetStockPriceResponse",
+    prefix = "m",
+    namespaces = { "m" = "http://www.example.org" }
+)]
+struct GetStockPriceResponse {
+    #[yaserde(rename = "StockPrice", prefix = "m")]
+    stock_price: String,
+}
+
+#[derive(Default, Debug, YaSerialize, YaDeserialize, SoapHeader)]
+#[yaserde(
+    rename = "Currency",
+    prefix = "m",
+    namespaces = { "m" = "http://www.example.org" }
+)]
+struct Currency {
+    #[yaserde(text = true)]
+    code: String,
+}
+
+#[derive(Default, Debug, PartialEq, YaSerialize, YaDeserialize, SoapFaultDetail)]
+#[yaserde(
+    rename = "UnknownStock",
+    prefix = "m",
+    namespaces = { "m" = "http://www.example.org" }
+)]
+struct UnknownStock {
+    #[yaserde(rename = "StockName", prefix = "m")]
+    stock_name: String,
+}
+
+#[derive(Default, Debug, PartialEq, YaSerialize, YaDeserialize, SoapFaultDetail)]
+#[yaserde(
+    rename = "UnknownStock",
+    prefix = "o",
+    namespaces = { "o" = "urn:other" }
+)]
+struct OtherUnknownStock {
+    #[yaserde(rename = "StockName", prefix = "o")]
+    stock_name: String,
+}
+
+#[derive(Clone)]
+struct AppState {
+    price: String,
+}
+
+async fn get_stock_price(
+    State(app): State<AppState>,
+    currency: Option<Currency>,
+    body: GetStockPrice,
+) -> Result<GetStockPriceResponse, SoapFault> {
+    if body.stock_name == "NONE" {
+        return Err(
+            SoapFault::sender("Unknown stock").with_detail(&UnknownStock {
+                stock_name: body.stock_name,
+            }),
+        );
+    }
+    let currency = currency.map(|c| c.code).unwrap_or("USD".to_string());
+    Ok(GetStockPriceResponse {
+        stock_price: format!("{} {} {}", body.stock_name, app.price, currency),
+    })
+}
+
+async fn call(router: &mut SoapRouter<AppState>, raw: &str) -> SoapMessage {
+    let req: Request<Body> = Request::builder()
+        .uri("/")
+        .body(raw.to_string().into())
+        .unwrap();
+    let resp = router.call(req).await.unwrap();
+    let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    SoapMessage::from(Element::parse(body.as_ref()).unwrap())
+}
+
+#[tokio::test]
+async fn test_typed_handler() {
+    let mut router = SoapRouter::new(AppState {
+        price: "3.60".to_string(),
+    })
+    .add_operation(
+        "http://www.example.org".to_string(),
+        "GetStockPrice".to_string(),
+        get_stock_price,
+    );
+
+    let msg = call(
+        &mut router,
+        r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+            <soap:Header><m:Currency>EUR</m:Currency></soap:Header>
+            <soap:Body><m:GetStockPrice><m:StockName>T</m:StockName></m:GetStockPrice></soap:Body>
+        </soap:Envelope>"#,
+    )
+    .await;
+    let price = msg
+        .get_body()
+        .get_child(("GetStockPriceResponse", "http://www.example.org"))
+        .and_then(|r| r.get_child(("StockPrice", "http://www.example.org")))
+        .and_then(|p| p.get_text())
+        .unwrap();
+    assert_eq!(price, "T 3.60 EUR");
+
+    let msg = call(
+        &mut router,
+        r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+            <soap:Body><m:GetStockPrice><m:StockName>Y</m:StockName></m:GetStockPrice></soap:Body>
+        </soap:Envelope>"#,
+    )
+    .await;
+    let price = msg
+        .get_body()
+        .get_child(("GetStockPriceResponse", "http://www.example.org"))
+        .and_then(|r| r.get_child(("StockPrice", "http://www.example.org")))
+        .and_then(|p| p.get_text())
+        .unwrap();
+    assert_eq!(price, "Y 3.60 USD");
+
+    let msg = call(
+        &mut router,
+        r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+            <soap:Header><o:Currency xmlns:o="urn:other">EUR</o:Currency></soap:Header>
+            <soap:Body><m:GetStockPrice><m:StockName>Z</m:StockName></m:GetStockPrice></soap:Body>
+        </soap:Envelope>"#,
+    )
+    .await;
+    let price = msg
+        .get_body()
+        .get_child(("GetStockPriceResponse", "http://www.example.org"))
+        .and_then(|r| r.get_child(("StockPrice", "http://www.example.org")))
+        .and_then(|p| p.get_text())
+        .unwrap();
+    assert_eq!(price, "Z 3.60 USD");
+}
+
+#[tokio::test]
+async fn test_typed_multiple_bodies() {
+    let mut router = SoapRouter::new(AppState {
+        price: "2.10".to_string(),
+    })
+    .add_operation(
+        "http://www.example.org".to_string(),
+        "GetStockPrice".to_string(),
+        get_stock_price,
+    );
+
+    let msg = call(
+        &mut router,
+        r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+            <soap:Header><m:Currency>EUR</m:Currency></soap:Header>
+            <soap:Body>
+                <m:GetStockPrice><m:StockName>A</m:StockName></m:GetStockPrice>
+                <m:GetStockPrice><m:StockName>B</m:StockName></m:GetStockPrice>
+            </soap:Body>
+        </soap:Envelope>"#,
+    )
+    .await;
+    let prices: Vec<_> = msg
+        .get_body()
+        .children
+        .iter()
+        .filter_map(|c| c.as_element())
+        .filter_map(|r| r.get_child(("StockPrice", "http://www.example.org")))
+        .filter_map(|p| p.get_text())
+        .collect();
+    assert_eq!(prices, ["A 2.10 EUR", "B 2.10 EUR"]);
+}
+
+#[tokio::test]
+async fn test_typed_client() {
+    let router = SoapRouter::new(AppState {
+        price: "1.20".to_string(),
+    })
+    .add_operation(
+        "http://www.example.org".to_string(),
+        "GetStockPrice".to_string(),
+        get_stock_price,
+    );
+    let app = router.axum_route("/stock");
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service()),
+    );
+
+    let client = SoapClient::new();
+    let resp: GetStockPriceResponse = client
+        .call(
+            &format!("http://{}/stock", addr),
+            "http://www.example.org/GetStockPrice",
+            &GetStockPrice {
+                stock_name: "X".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(resp.stock_price, "X 1.20 USD");
+}
+
+#[tokio::test]
+async fn test_fault_detail() {
+    let router = SoapRouter::new(AppState {
+        price: "1.20".to_string(),
+    })
+    .add_operation(
+        "http://www.example.org".to_string(),
+        "GetStockPrice".to_string(),
+        get_stock_price,
+    );
+    let app = axum::Router::new().route_service("/stock", router);
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service()),
+    );
+
+    let err = SoapClient::new()
+        .call::<_, GetStockPriceResponse>(
+            &format!("http://{}/stock", addr),
+            "http://www.example.org/GetStockPrice",
+            &GetStockPrice {
+                stock_name: "NONE".to_string(),
+            },
+        )
+        .await
+        .unwrap_err();
+    let ClientError::Fault(fault) = err else {
+        panic!("expected a fault, got {}", err)
+    };
+    assert_eq!(fault.reason(&[]), "Unknown stock");
+    assert_eq!(
+        fault.detail_as::<UnknownStock>(),
+        Some(UnknownStock {
+            stock_name: "NONE".to_string()
+        })
+    );
+    assert_eq!(fault.detail_as::<OtherUnknownStock>(), None);
+}
@@ -0,0 +1,328 @@
+//! Exclusive XML Canonicalization 1.0, without comments, as required to
+//! sign and verify parts of a message. It works on the serialized message
+//! rather than on [`xmltree::Element`], which doesn't keep the namespace of
+//! attributes such as `wsu:Id`.
+
+use std::collections::BTreeMap;
+
+use xml::{
+    attribute::OwnedAttribute,
+    name::OwnedName,
+    namespace::{Namespace, NS_XMLNS_PREFIX, NS_XML_PREFIX, NS_XML_URI},
+    reader::{EventReader, ParserConfig, XmlEvent},
+};
+
+use crate::{pretty::InvalidDocument, soap_security::WSU_NAMESPACE};
+
+/// Algorithm URI of the exclusive canonicalization, as found in
+/// `ds:CanonicalizationMethod` and `ds:Transform`
+pub const EXC_C14N_ALGORITHM: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+
+/// Canonicalize a whole document. The prefixes of `inclusive_prefixes`, coming
+/// from the `InclusiveNamespaces` `PrefixList`, are rendered whenever they are
+/// in scope, `#default` standing for the default namespace.
+pub fn canonicalize(doc: &[u8], inclusive_prefixes: &[&str]) -> Result<String, InvalidDocument> {
+    Canonicalizer::new(inclusive_prefixes)
+        .run(doc, None::<fn(&OwnedName, &[OwnedAttribute]) -> bool>)
+        .map(|c| c.unwrap_or_default())
+}
+
+/// Canonicalize the element whose `wsu:Id`, `xml:id` or unqualified `Id`
+/// attribute is `id`, as referenced by `#id` URIs. `None` when there is no
+/// such element, or when the identifier isn't unique.
+pub fn canonicalize_id(
+    doc: &[u8],
+    id: &str,
+    inclusive_prefixes: &[&str],
+) -> Result<Option<String>, InvalidDocument> {
+    Canonicalizer::new(inclusive_prefixes).run(
+        doc,
+        Some(|_: &OwnedName, attributes: &[OwnedAttribute]| {
+            attributes.iter().any(|a| {
+                a.value == id
+                    && matches!(
+                        (a.name.namespace.as_deref(), a.name.local_name.as_str()),
+                        (Some(WSU_NAMESPACE) | None, "Id") | (Some(NS_XML_URI), "id")
+                    )
+            })
+        }),
+    )
+}
+
+/// Canonicalize the element with the given name, `None` when there is no
+/// such element or several of them.
+pub(crate) fn canonicalize_element(
+    doc: &[u8],
+    namespace: &str,
+    name: &str,
+    inclusive_prefixes: &[&str],
+) -> Result<Option<String>, InvalidDocument> {
+    Canonicalizer::new(inclusive_prefixes).run(
+        doc,
+        Some(|n: &OwnedName, _: &[OwnedAttribute]| {
+            n.local_name == name && n.namespace.as_deref() == Some(namespace)
+        }),
+    )
+}
+
+struct Canonicalizer<'a> {
+    inclusive_prefixes: &'a [&'a str],
+    out: String,
+    /// Namespaces rendered by each output element, innermost last
+    rendered: Vec<BTreeMap<String, String>>,
+}
+
+impl<'a> Canonicalizer<'a> {
+    fn new(inclusive_prefixes: &'a [&'a str]) -> Self {
+        Self {
+            inclusive_prefixes,
+            out: String::new(),
+            rendered: vec![],
+        }
+    }
+
+    /// Render the single element matching `apex` along with its content,
+    /// the whole document without apex.
+    fn run(
+        mut self,
+        doc: &[u8],
+        apex: Option<impl Fn(&OwnedName, &[OwnedAttribute]) -> bool>,
+    ) -> Result<Option<String>, InvalidDocument> {
+        let reader = EventReader::new_with_config(
+            doc,
+            ParserConfig::new()
+                .trim_whitespace(false)
+                .whitespace_to_characters(true)
+                .cdata_to_characters(true)
+                .coalesce_characters(true)
+                .ignore_comments(true),
+        );
+        let mut depth = 0;
+        // Depth of the apex once found
+        let mut start = None;
+        let mut rendering = false;
+        let mut duplicate = false;
+        // Processing instructions around the root element
+        let mut before = String::new();
+        let mut after = String::new();
+        for event in reader {
+            let event = event.map_err(|e| InvalidDocument(e.to_string()))?;
+            match event {
+                XmlEvent::StartElement {
+                    name,
+                    attributes,
+                    namespace,
+                } => {
+                    let is_apex = match &apex {
+                        Some(apex) => apex(&name, &attributes),
+                        None => depth == 0,
+                    };
+                    if is_apex {
+                        duplicate |= start.is_some();
+                        start = Some(depth);
+                        rendering = true;
+                    }
+                    depth += 1;
+                    if rendering && !duplicate {
+                        self.start_element(&name, attributes, &namespace);
+                    }
+                }
+                XmlEvent::EndElement { name } => {
+                    depth -= 1;
+                    if rendering && !duplicate {
+                        self.out.push_str("</");
+                        push_name(&mut self.out, &name);
+                        self.out.push('>');
+                        self.rendered.pop();
+                    }
+                    if start == Some(depth) {
+                        rendering = false;
+                    }
+                }
+                XmlEvent::Characters(text) if rendering => push_text(&mut self.out, &text),
+                XmlEvent::ProcessingInstruction { name, data } => {
+                    let mut pi = format!("<?{}", name);
+                    if let Some(data) = data.filter(|d| !d.is_empty()) {
+                        pi.push(' ');
+                        pi.push_str(&data);
+                    }
+                    pi.push_str("?>");
+                    match depth {
+                        0 if start.is_none() => before.push_str(&(pi + "\n")),
+                        0 => after.push_str(&("\n".to_string() + &pi)),
+                        _ if rendering => self.out.push_str(&pi),
+                        _ => (),
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(match start {
+            _ if duplicate => None,
+            Some(0) if apex.is_none() => Some(before + &self.out + &after),
+            Some(_) => Some(self.out),
+            None => None,
+        })
+    }
+
+    fn start_element(
+        &mut self,
+        name: &OwnedName,
+        mut attributes: Vec<OwnedAttribute>,
+        scope: &Namespace,
+    ) {
+        // Prefixes visibly utilized by the element and its attributes,
+        // along with the inclusive ones in scope
+        let mut prefixes = vec![name.prefix.clone().unwrap_or_default()];
+        prefixes.extend(attributes.iter().filter_map(|a| a.name.prefix.clone()));
+        for prefix in self.inclusive_prefixes {
+            match *prefix {
+                "#default" => prefixes.push(String::new()),
+                p if scope.get(p).is_some() => prefixes.push(p.to_string()),
+                _ => (),
+            }
+        }
+        let parent = self.rendered.last().cloned().unwrap_or_default();
+        let mut rendered = parent.clone();
+        let mut declarations = BTreeMap::new();
+        for prefix in prefixes {
+            if prefix == NS_XML_PREFIX || prefix == NS_XMLNS_PREFIX {
+                continue;
+            }
+            let uri = scope.get(&prefix).unwrap_or_default().to_string();
+            let previous = parent.get(&prefix).map(String::as_str).unwrap_or_default();
+            if uri != previous {
+                declarations.insert(prefix.clone(), uri.clone());
+                rendered.insert(prefix, uri);
+            }
+        }
+        self.rendered.push(rendered);
+
+        self.out.push('<');
+        push_name(&mut self.out, name);
+        for (prefix, uri) in declarations {
+            match prefix.as_str() {
+                "" => self.out.push_str(" xmlns=\""),
+                p => self.out.push_str(&format!(" xmlns:{}=\"", p)),
+            }
+            push_attribute_value(&mut self.out, &uri);
+            self.out.push('"');
+        }
+        attributes.sort_by(|a, b| {
+            (
+                a.name.namespace.as_deref().unwrap_or_default(),
+                &a.name.local_name,
+            )
+                .cmp(&(
+                    b.name.namespace.as_deref().unwrap_or_default(),
+                    &b.name.local_name,
+                ))
+        });
+        for attribute in attributes {
+            self.out.push(' ');
+            push_name(&mut self.out, &attribute.name);
+            self.out.push_str("=\"");
+            push_attribute_value(&mut self.out, &attribute.value);
+            self.out.push('"');
+        }
+        self.out.push('>');
+    }
+}
+
+fn push_name(out: &mut String, name: &OwnedName) {
+    if let Some(prefix) = &name.prefix {
+        out.push_str(prefix);
+        out.push(':');
+    }
+    out.push_str(&name.local_name);
+}
+
+fn push_text(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn push_attribute_value(out: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '"' => out.push_str("&quot;"),
+            '\t' => out.push_str("&#x9;"),
+            '\n' => out.push_str("&#xA;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonicalize() {
+        let doc = r#"<?xml version="1.0"?>
+<?xml-stylesheet href="doc.xsl"?>
+<doc b="2" a="1&#10;x" xmlns:unused="urn:unused" xmlns="urn:doc"><e2   /><e>a &lt; b &gt; c &amp; "d"</e><!-- comment --><![CDATA[<cdata>]]><f xmlns=""><g/></f></doc>
+<?end?>"#;
+        assert_eq!(
+            canonicalize(doc.as_bytes(), &[]).unwrap(),
+            "<?xml-stylesheet href=\"doc.xsl\"?>\n<doc xmlns=\"urn:doc\" a=\"1&#xA;x\" b=\"2\"><e2></e2><e>a &lt; b &gt; c &amp; \"d\"</e>&lt;cdata&gt;<f xmlns=\"\"><g></g></f></doc>\n<?end?>"
+        );
+        assert_eq!(
+            canonicalize(doc.as_bytes(), &["unused"]).unwrap(),
+            "<?xml-stylesheet href=\"doc.xsl\"?>\n<doc xmlns=\"urn:doc\" xmlns:unused=\"urn:unused\" a=\"1&#xA;x\" b=\"2\"><e2></e2><e>a &lt; b &gt; c &amp; \"d\"</e>&lt;cdata&gt;<f xmlns=\"\"><g></g></f></doc>\n<?end?>"
+        );
+        assert!(canonicalize(b"<a><b></a>", &[]).is_err());
+    }
+
+    #[test]
+    fn test_canonicalize_id() {
+        // Example of the exclusive canonicalization specification
+        let doc = r#"<n0:local xmlns:n0="foo:bar" xmlns:n3="ftp://example.org">
+  <n1:elem2 xmlns:n1="http://example.net" xml:lang="en" Id="elem2">
+    <n3:stuff xmlns:n3="ftp://example.org"/>
+  </n1:elem2>
+</n0:local>"#;
+        assert_eq!(
+            canonicalize_id(doc.as_bytes(), "elem2", &[])
+                .unwrap()
+                .unwrap(),
+            r#"<n1:elem2 xmlns:n1="http://example.net" Id="elem2" xml:lang="en">
+    <n3:stuff xmlns:n3="ftp://example.org"></n3:stuff>
+  </n1:elem2>"#
+        );
+        assert_eq!(
+            canonicalize_id(doc.as_bytes(), "elem2", &["n0", "#default"])
+                .unwrap()
+                .unwrap(),
+            r#"<n1:elem2 xmlns:n0="foo:bar" xmlns:n1="http://example.net" Id="elem2" xml:lang="en">
+    <n3:stuff xmlns:n3="ftp://example.org"></n3:stuff>
+  </n1:elem2>"#
+        );
+
+        let envelope = format!(
+            r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:wsu="{WSU_NAMESPACE}" xmlns:tds="http://www.onvif.org/ver10/device/wsdl"><env:Header/><env:Body wsu:Id="body"><tds:GetDeviceInformation/></env:Body></env:Envelope>"#
+        );
+        assert_eq!(
+            canonicalize_id(envelope.as_bytes(), "body", &[])
+                .unwrap()
+                .unwrap(),
+            format!(
+                r#"<env:Body xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:wsu="{WSU_NAMESPACE}" wsu:Id="body"><tds:GetDeviceInformation xmlns:tds="http://www.onvif.org/ver10/device/wsdl"></tds:GetDeviceInformation></env:Body>"#
+            )
+        );
+        assert_eq!(
+            canonicalize_id(envelope.as_bytes(), "header", &[]).unwrap(),
+            None
+        );
+    }
+}
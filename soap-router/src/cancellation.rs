@@ -0,0 +1,129 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use tokio::{sync::Notify, time::Instant};
+
+use crate::{extract::FromSoapRequest, fault::SoapFault, router::SoapRequest};
+
+#[derive(Debug, Default)]
+struct Shared {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+/// Deadline and cancellation of the request being handled, meant to be
+/// passed down to the backends so they can give up on expensive work the
+/// client won't wait for.
+///
+/// A request is cancelled once it is over or abandoned: when the client
+/// closes the connection while the handler runs, the server drops the
+/// request future which cancels the token, so work spawned outside of the
+/// handler future can stop too.
+#[derive(Clone, Debug, Default)]
+pub struct Cancellation {
+    deadline: Option<Instant>,
+    shared: Arc<Shared>,
+}
+
+impl Cancellation {
+    pub(crate) fn new(deadline: Option<Instant>) -> Self {
+        Self {
+            deadline,
+            shared: Default::default(),
+        }
+    }
+
+    /// Same token with the earliest of the current and given deadlines
+    pub(crate) fn with_deadline(&self, deadline: Instant) -> Self {
+        Self {
+            deadline: Some(self.deadline.map_or(deadline, |d| d.min(deadline))),
+            shared: self.shared.clone(),
+        }
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.shared.cancelled.load(Ordering::Acquire)
+            || self.deadline.is_some_and(|d| d <= Instant::now())
+    }
+
+    /// Resolve once the request is cancelled or its deadline passed
+    pub async fn cancelled(&self) {
+        let notified = self.shared.notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        if self.is_cancelled() {
+            return;
+        }
+        match self.deadline {
+            Some(deadline) => {
+                tokio::select! {
+                    _ = notified => (),
+                    _ = tokio::time::sleep_until(deadline) => (),
+                }
+            }
+            None => notified.await,
+        }
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.shared.cancelled.store(true, Ordering::Release);
+        self.shared.notify.notify_waiters();
+    }
+
+    /// Cancel the request when the returned guard is dropped
+    pub(crate) fn guard(&self) -> CancelOnDrop {
+        CancelOnDrop(self.clone())
+    }
+}
+
+pub(crate) struct CancelOnDrop(Cancellation);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+impl<S> FromSoapRequest<S> for Cancellation {
+    fn from_soap_request(req: &SoapRequest, _state: &S) -> Result<Self, SoapFault> {
+        Ok(req
+            .extensions
+            .get::<Cancellation>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancellation() {
+        let cancellation = Cancellation::new(None);
+        let guard = cancellation.guard();
+        let waiter = tokio::spawn({
+            let cancellation = cancellation.clone();
+            async move { cancellation.cancelled().await }
+        });
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(!waiter.is_finished());
+        drop(guard);
+        waiter.await.unwrap();
+        assert!(cancellation.is_cancelled());
+
+        let cancellation = Cancellation::new(Some(Instant::now() + Duration::from_secs(5)));
+        assert!(!cancellation.is_cancelled());
+        cancellation.cancelled().await;
+        assert!(cancellation.is_cancelled());
+        assert_eq!(cancellation.deadline().unwrap().elapsed(), Duration::ZERO);
+    }
+}
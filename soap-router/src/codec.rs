@@ -0,0 +1,120 @@
+use xmltree::{Element, XMLNode};
+use yaserde::{YaDeserialize, YaSerialize};
+
+use crate::{
+    fault::{SoapFault, SoapFaultCode},
+    router::{SoapMessage, SoapRequest},
+};
+
+/// Deserialize a typed value from an XML element, failing with a Sender fault.
+///
+/// Used by the `SoapHeader` derive.
+pub fn from_element<T: YaDeserialize>(elem: &Element) -> Result<T, SoapFault> {
+    let mut buf = vec![];
+    elem.write(&mut buf)
+        .map_err(|_| invalid_message("InvalidBody"))?;
+    yaserde::de::from_reader(buf.as_slice()).map_err(|_| invalid_message("InvalidBody"))
+}
+
+/// Deserialize a typed value from the body of a request, reading the raw
+/// request bytes when available.
+///
+/// Used by the `SoapBody` derive.
+pub fn from_request_body<T: YaDeserialize>(req: &SoapRequest) -> Result<T, SoapFault> {
+    match &req.body_source {
+        Some(source) if source.matches(&req.body) => {
+            yaserde::de::from_reader(source.reader()).map_err(|_| invalid_message("InvalidBody"))
+        }
+        _ => from_element(&req.body),
+    }
+}
+
+/// Serialize a typed value as an XML element, failing with a Receiver fault
+pub fn to_element<T: YaSerialize>(value: &T) -> Result<Element, SoapFault> {
+    let buf = yaserde::ser::to_string(value).map_err(|_| serialization_failed())?;
+    Element::parse(buf.as_bytes()).map_err(|_| serialization_failed())
+}
+
+/// Build a message holding the serialized value in its body
+pub fn body_message<T: YaSerialize>(value: &T) -> Result<SoapMessage, SoapFault> {
+    let mut msg = SoapMessage::new();
+    msg.get_mut_body()
+        .children
+        .push(XMLNode::Element(to_element(value)?));
+    Ok(msg)
+}
+
+/// Build a message holding the serialized value as a header block
+pub fn header_message<T: YaSerialize>(value: &T) -> Result<SoapMessage, SoapFault> {
+    let mut msg = SoapMessage::new();
+    msg.get_mut_headers()
+        .children
+        .push(XMLNode::Element(to_element(value)?));
+    Ok(msg)
+}
+
+pub fn missing_header() -> SoapFault {
+    invalid_message("MissingHeader")
+}
+
+fn serialization_failed() -> SoapFault {
+    SoapFault::builder(SoapFaultCode::Receiver)
+        .builtin_reason("SerializationFailed")
+        .build()
+}
+
+pub(crate) fn invalid_message(reason: &str) -> SoapFault {
+    SoapFault::builder(SoapFaultCode::Sender)
+        .builtin_reason(reason)
+        .build()
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum DocumentError {
+    TooDeep,
+    Invalid,
+}
+
+/// Check a document can safely be turned into a tree: it must be well
+/// formed, have no document type declaration, so no entity definitions, as
+/// forbidden by SOAP, and its elements must not nest deeper than `max_depth`.
+pub(crate) fn check_document(doc: &[u8], max_depth: usize) -> Result<(), DocumentError> {
+    if has_doctype(doc) {
+        return Err(DocumentError::Invalid);
+    }
+    let mut depth = 0;
+    for event in xml::reader::EventReader::new(doc) {
+        match event.map_err(|_| DocumentError::Invalid)? {
+            xml::reader::XmlEvent::StartElement { .. } => {
+                depth += 1;
+                if depth > max_depth {
+                    return Err(DocumentError::TooDeep);
+                }
+            }
+            xml::reader::XmlEvent::EndElement { .. } => depth -= 1,
+            xml::reader::XmlEvent::EndDocument => break,
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// A doctype may only come in the prolog, after the XML declaration,
+/// processing instructions, comments and whitespace.
+fn has_doctype(doc: &[u8]) -> bool {
+    let mut rest = doc.strip_prefix(b"\xef\xbb\xbf").unwrap_or(doc);
+    loop {
+        rest = rest.trim_ascii_start();
+        let end = if rest.starts_with(b"<?") {
+            b"?>".as_slice()
+        } else if rest.starts_with(b"<!--") {
+            b"-->".as_slice()
+        } else {
+            return rest.starts_with(b"<!");
+        };
+        match rest.windows(end.len()).position(|w| w == end) {
+            Some(i) => rest = &rest[i + end.len()..],
+            None => return false,
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::io::{Read, Write};
+
+use flate2::{
+    read::{DeflateDecoder, GzDecoder, ZlibDecoder},
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+
+/// Responses smaller than this are not worth compressing
+pub(crate) const MIN_COMPRESSED_SIZE: usize = 1024;
+
+/// Content codings understood by the router, `deflate` being the zlib
+/// format of RFC 1950.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ContentEncoding {
+    Identity,
+    Gzip,
+    Deflate,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum DecodeError {
+    TooLarge,
+    Invalid,
+}
+
+impl ContentEncoding {
+    /// Parse a `Content-Encoding` header value, stacked codings are not
+    /// supported.
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => Some(ContentEncoding::Identity),
+            "gzip" | "x-gzip" => Some(ContentEncoding::Gzip),
+            "deflate" => Some(ContentEncoding::Deflate),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Identity => "identity",
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+        }
+    }
+
+    /// Pick the preferred coding of an `Accept-Encoding` header value,
+    /// gzip winning ties. `None` when the identity should be used.
+    pub(crate) fn negotiate(accept: &str) -> Option<Self> {
+        let mut gzip = None;
+        let mut deflate = None;
+        let mut any = None;
+        for item in accept.split(',') {
+            let mut parts = item.split(';');
+            let coding = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+            let quality = parts
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            match coding.as_str() {
+                "gzip" | "x-gzip" => gzip = Some(quality),
+                "deflate" => deflate = Some(quality),
+                "*" => any = Some(quality),
+                _ => (),
+            }
+        }
+        let gzip = gzip.or(any).unwrap_or(0.0);
+        let deflate = deflate.or(any).unwrap_or(0.0);
+        match (gzip, deflate) {
+            (g, d) if g > 0.0 && g >= d => Some(ContentEncoding::Gzip),
+            (_, d) if d > 0.0 => Some(ContentEncoding::Deflate),
+            _ => None,
+        }
+    }
+
+    /// Decode a request body, failing when the decoded data is larger than
+    /// `limit` bytes.
+    pub(crate) fn decode(&self, data: &[u8], limit: usize) -> Result<Vec<u8>, DecodeError> {
+        let mut decoded = vec![];
+        let read = |reader: &mut dyn Read, decoded: &mut Vec<u8>| {
+            reader
+                .take(limit as u64 + 1)
+                .read_to_end(decoded)
+                .map_err(|_| DecodeError::Invalid)
+        };
+        match self {
+            ContentEncoding::Identity => decoded.extend_from_slice(data),
+            ContentEncoding::Gzip => {
+                read(&mut GzDecoder::new(data), &mut decoded)?;
+            }
+            // Some clients send raw deflate data instead of the zlib format
+            ContentEncoding::Deflate => {
+                if read(&mut ZlibDecoder::new(data), &mut decoded).is_err() {
+                    decoded.clear();
+                    read(&mut DeflateDecoder::new(data), &mut decoded)?;
+                }
+            }
+        }
+        match decoded.len() > limit {
+            true => Err(DecodeError::TooLarge),
+            false => Ok(decoded),
+        }
+    }
+
+    pub(crate) fn encode(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            ContentEncoding::Identity => data.to_vec(),
+            ContentEncoding::Gzip => {
+                let mut encoder = GzEncoder::new(vec![], Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            ContentEncoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_encoding() {
+        assert_eq!(
+            ContentEncoding::negotiate("gzip, deflate"),
+            Some(ContentEncoding::Gzip)
+        );
+        assert_eq!(
+            ContentEncoding::negotiate("gzip;q=0.5, deflate"),
+            Some(ContentEncoding::Deflate)
+        );
+        assert_eq!(
+            ContentEncoding::negotiate("*;q=0.1, gzip;q=0"),
+            Some(ContentEncoding::Deflate)
+        );
+        assert_eq!(ContentEncoding::negotiate("br, identity"), None);
+        assert_eq!(ContentEncoding::parse(" GZIP"), Some(ContentEncoding::Gzip));
+        assert_eq!(ContentEncoding::parse("gzip, br"), None);
+
+        let data = "<env:Envelope/>".repeat(100);
+        for encoding in [ContentEncoding::Gzip, ContentEncoding::Deflate] {
+            let encoded = encoding.encode(data.as_bytes());
+            assert!(encoded.len() < data.len());
+            assert_eq!(encoding.decode(&encoded, 4096).unwrap(), data.as_bytes());
+            assert_eq!(encoding.decode(&encoded, 1000), Err(DecodeError::TooLarge));
+            assert_eq!(encoding.decode(b"garbage", 4096), Err(DecodeError::Invalid));
+        }
+        let mut raw = flate2::write::DeflateEncoder::new(vec![], Compression::default());
+        raw.write_all(data.as_bytes()).unwrap();
+        assert_eq!(
+            ContentEncoding::Deflate
+                .decode(&raw.finish().unwrap(), 4096)
+                .unwrap(),
+            data.as_bytes()
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use serde::Serialize;
+
+use crate::{interceptor::QName, router::RouteInfo, version::SoapVersion};
+
+/// Machine readable description of what a router exposes, built from the
+/// routes it dispatches on.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct Description {
+    /// Operations dispatched on their body element, grouped by namespace
+    pub services: Vec<ServiceDescription>,
+    /// Operations dispatched on their WS-Addressing action only
+    pub actions: Vec<RouteInfo>,
+    pub capabilities: Capabilities,
+}
+
+/// Description of every service mounted in a
+/// [`ServiceRegistry`](crate::registry::ServiceRegistry)
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct DeviceDescription {
+    pub services: Vec<MountedService>,
+}
+
+/// Service of a registry along with the description of its router
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct MountedService {
+    pub namespace: String,
+    pub path: String,
+    pub description: Description,
+}
+
+impl DeviceDescription {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+/// Operations of a namespace
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct ServiceDescription {
+    pub namespace: String,
+    pub operations: Vec<RouteInfo>,
+}
+
+/// Protocol features of a router
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct Capabilities {
+    pub soap_versions: Vec<SoapVersion>,
+    /// Whether a WSDL document is served on `?wsdl`
+    pub wsdl: bool,
+    /// Whether unknown operations are handed to a fallback handler
+    pub fallback: bool,
+    /// Header blocks honoured when `mustUnderstand` is set
+    pub understood_headers: Vec<QName>,
+}
+
+impl Description {
+    /// Group routes as returned by [`crate::router::SoapRouter::routes`]
+    pub(crate) fn new(routes: Vec<RouteInfo>, capabilities: Capabilities) -> Self {
+        let mut services: Vec<ServiceDescription> = vec![];
+        let mut actions = vec![];
+        for route in routes {
+            let Some(operation) = &route.operation else {
+                actions.push(route);
+                continue;
+            };
+            match services.last_mut() {
+                Some(service) if service.namespace == operation.namespace => {
+                    service.operations.push(route)
+                }
+                _ => services.push(ServiceDescription {
+                    namespace: operation.namespace.clone(),
+                    operations: vec![route],
+                }),
+            }
+        }
+        Description {
+            services,
+            actions,
+            capabilities,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
@@ -0,0 +1,766 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::Body,
+    http::{
+        header::{AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE, WWW_AUTHENTICATE},
+        request::Parts,
+        HeaderValue, Request, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use hyper::body::{Bytes, HttpBody};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use tower::Layer;
+use tower_service::Service;
+use xmltree::Element;
+
+use crate::{
+    entropy::{default_source, EntropySource},
+    interceptor::QName,
+    soap_security::{
+        constant_time_eq, is_pre_auth, AuthenticatedUser, Clock, CredentialStore, PreAuthOnly,
+    },
+    version::{ContentType, SOAP11_NAMESPACE, SOAP12_NAMESPACE},
+    ws_addressing::WSA_NAMESPACE,
+};
+
+/// Hash algorithms of RFC 7616 supported by [`DigestAuthLayer`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+    Md5,
+    Sha256,
+}
+
+impl DigestAlgorithm {
+    fn name(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Md5 => "MD5",
+            DigestAlgorithm::Sha256 => "SHA-256",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "MD5" => Some(DigestAlgorithm::Md5),
+            "SHA-256" => Some(DigestAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+
+    fn hash(&self, data: &str) -> String {
+        let bytes = match self {
+            DigestAlgorithm::Md5 => Md5::digest(data).to_vec(),
+            DigestAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+        };
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+struct DigestState<C> {
+    store: Arc<C>,
+    realm: String,
+    secret: [u8; 32],
+    nonce_lifetime: Duration,
+    required: bool,
+    // Nonce counts seen for each nonce, to reject replays
+    counts: Arc<Mutex<NonceTable>>,
+    clock: Arc<Clock>,
+}
+
+impl<C> Clone for DigestState<C> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            realm: self.realm.clone(),
+            secret: self.secret,
+            nonce_lifetime: self.nonce_lifetime,
+            required: self.required,
+            counts: self.counts.clone(),
+            clock: self.clock.clone(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct NonceTable {
+    nonces: HashMap<String, NonceCounts>,
+    // Time of the next removal of the expired nonces
+    next_prune: u64,
+}
+
+/// Nonce counts seen for one nonce, in a sliding window like the anti-replay
+/// one of IPsec so that pipelined requests may arrive out of order
+#[derive(Default)]
+struct NonceCounts {
+    expires: u64,
+    highest: u32,
+    // Bit n is set once count `highest - n` was seen
+    seen: u64,
+}
+
+impl NonceCounts {
+    /// Record a count, false when it was already seen or is too old
+    fn record(&mut self, count: u32) -> bool {
+        if count > self.highest {
+            let shift = count - self.highest;
+            self.seen = self.seen.checked_shl(shift).unwrap_or(0) | 1;
+            self.highest = count;
+            return true;
+        }
+        let offset = self.highest - count;
+        if count == 0 || offset >= u64::BITS || self.seen & (1 << offset) != 0 {
+            return false;
+        }
+        self.seen |= 1 << offset;
+        true
+    }
+}
+
+/// Layer implementing HTTP Digest authentication (RFC 7616) in front of a
+/// [`SoapRouter`](crate::router::SoapRouter), or any axum service.
+///
+/// Authenticated requests carry an [`AuthenticatedUser`] in their extensions,
+/// the same one the WS-Security header processor adds. Requests without an
+/// `Authorization` header are passed through so that they can authenticate
+/// with WS-Security instead, unless the layer is [required](Self::required).
+///
+/// Requests which would be challenged are still passed through, anonymously
+/// and marked [`PreAuthOnly`], when they only call one of the
+/// [`PRE_AUTH_OPERATIONS`](crate::soap_security::PRE_AUTH_OPERATIONS).
+#[derive(Clone)]
+pub struct DigestAuthLayer<C> {
+    state: DigestState<C>,
+}
+
+impl<C: CredentialStore> DigestAuthLayer<C> {
+    pub fn new(realm: &str, store: C) -> Self {
+        Self::from_shared(realm, Arc::new(store))
+    }
+
+    /// Use a credential store shared with other authentication mechanisms
+    pub fn from_shared(realm: &str, store: Arc<C>) -> Self {
+        let mut secret = [0; 32];
+        default_source().fill_bytes(&mut secret);
+        Self {
+            state: DigestState {
+                store,
+                realm: realm.to_string(),
+                secret,
+                nonce_lifetime: Duration::from_secs(300),
+                required: false,
+                counts: Default::default(),
+                clock: Arc::new(SystemTime::now),
+            },
+        }
+    }
+
+    /// Answer requests without credentials with a 401 challenge
+    pub fn required(mut self, required: bool) -> Self {
+        self.state.required = required;
+        self
+    }
+
+    /// Time after which nonces are reported as stale, 5 minutes by default
+    pub fn with_nonce_lifetime(mut self, lifetime: Duration) -> Self {
+        self.state.nonce_lifetime = lifetime;
+        self
+    }
+
+    /// Replace the source of the secret nonces are derived from
+    pub fn with_entropy_source<E: EntropySource>(mut self, source: E) -> Self {
+        source.fill_bytes(&mut self.state.secret);
+        self
+    }
+
+    /// Replace the clock nonces are checked against
+    pub fn with_clock<F>(mut self, clock: F) -> Self
+    where
+        F: Fn() -> SystemTime + Send + Sync + 'static,
+    {
+        self.state.clock = Arc::new(clock);
+        self
+    }
+}
+
+impl<S, C> Layer<S> for DigestAuthLayer<C> {
+    type Service = DigestAuth<S, C>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        DigestAuth {
+            inner,
+            state: Arc::new(self.state.clone()),
+        }
+    }
+}
+
+/// Service created by [`DigestAuthLayer`]
+pub struct DigestAuth<S, C> {
+    inner: S,
+    state: Arc<DigestState<C>>,
+}
+
+impl<S: Clone, C> Clone for DigestAuth<S, C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+enum Outcome {
+    Authenticated(String),
+    Anonymous,
+    Challenge { stale: bool },
+}
+
+impl<C: CredentialStore> DigestState<C> {
+    fn now(&self) -> u64 {
+        (self.clock)()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+
+    fn nonce_mac(&self, timestamp: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.secret);
+        hasher.update(timestamp);
+        hasher.update(&self.realm);
+        hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    fn nonce(&self) -> String {
+        let timestamp = format!("{:016x}", self.now());
+        let mac = self.nonce_mac(&timestamp);
+        format!("{}{}", timestamp, mac)
+    }
+
+    /// Issue time of the nonce, if it was issued by us
+    fn nonce_issued(&self, nonce: &str) -> Option<u64> {
+        let timestamp = nonce.get(..16)?;
+        if !constant_time_eq(
+            nonce.get(16..)?.as_bytes(),
+            self.nonce_mac(timestamp).as_bytes(),
+        ) {
+            return None;
+        }
+        u64::from_str_radix(timestamp, 16).ok()
+    }
+
+    fn challenge(&self, stale: bool) -> Response {
+        let nonce = self.nonce();
+        let mut resp = StatusCode::UNAUTHORIZED.into_response();
+        for algorithm in [DigestAlgorithm::Sha256, DigestAlgorithm::Md5] {
+            let value = format!(
+                "Digest realm=\"{}\", qop=\"auth\", algorithm={}, nonce=\"{}\"{}",
+                self.realm,
+                algorithm.name(),
+                nonce,
+                if stale { ", stale=true" } else { "" }
+            );
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                resp.headers_mut().append(WWW_AUTHENTICATE, value);
+            }
+        }
+        resp
+    }
+
+    fn authenticate(&self, req: &Request<Body>) -> Outcome {
+        let Some(header) = req.headers().get(AUTHORIZATION) else {
+            return match self.required {
+                true => Outcome::Challenge { stale: false },
+                false => Outcome::Anonymous,
+            };
+        };
+        let params = match header.to_str().ok().and_then(parse_digest_header) {
+            Some(params) => params,
+            None => return Outcome::Challenge { stale: false },
+        };
+        self.verify(req, &params)
+            .unwrap_or(Outcome::Challenge { stale: false })
+    }
+
+    fn verify(&self, req: &Request<Body>, params: &HashMap<String, String>) -> Option<Outcome> {
+        let param = |name: &str| params.get(name).map(String::as_str);
+        let algorithm = DigestAlgorithm::from_name(param("algorithm").unwrap_or("MD5"))?;
+        let username = param("username")?;
+        let nonce = param("nonce")?;
+        let uri = param("uri")?;
+        if param("realm")? != self.realm {
+            return None;
+        }
+        let request_uri = req
+            .uri()
+            .path_and_query()
+            .map(|p| p.as_str())
+            .unwrap_or("/");
+        if uri != request_uri && uri != *req.uri() {
+            return None;
+        }
+        let issued = self.nonce_issued(nonce)?;
+        let password = self.store.password(username)?;
+
+        // Without qop there is no nonce count to detect replays with
+        if param("qop")? != "auth" {
+            return None;
+        }
+        let nc = param("nc")?;
+        let cnonce = param("cnonce")?;
+        let count = u32::from_str_radix(nc, 16).ok()?;
+        let ha1 = algorithm.hash(&format!("{}:{}:{}", username, self.realm, password));
+        let ha2 = algorithm.hash(&format!("{}:{}", req.method(), uri));
+        let expected = algorithm.hash(&format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc, cnonce, ha2));
+        if !constant_time_eq(expected.as_bytes(), param("response")?.as_bytes()) {
+            return None;
+        }
+        let now = self.now();
+        let lifetime = self.nonce_lifetime.as_secs();
+        if now.saturating_sub(issued) > lifetime {
+            return Some(Outcome::Challenge { stale: true });
+        }
+
+        let mut table = self.counts.lock().unwrap();
+        if now >= table.next_prune {
+            table.nonces.retain(|_, counts| counts.expires >= now);
+            table.next_prune = now + lifetime;
+        }
+        let counts = table
+            .nonces
+            .entry(nonce.to_string())
+            .or_insert_with(|| NonceCounts {
+                expires: issued + lifetime,
+                ..Default::default()
+            });
+        if !counts.record(count) {
+            return None;
+        }
+        Some(Outcome::Authenticated(username.to_string()))
+    }
+}
+
+/// Largest body inspected for a pre-auth operation, those requests are tiny
+const PRE_AUTH_BODY_LIMIT: usize = 16 * 1024;
+
+/// Buffer the body of a request, returning it when the request only calls a
+/// pre-auth operation
+async fn pre_auth_body(parts: &Parts, mut body: Body) -> Option<Bytes> {
+    // Compressed and MTOM requests never qualify
+    if parts.headers.contains_key(CONTENT_ENCODING) {
+        return None;
+    }
+    let mut actions = vec![];
+    if let Some(content_type) = parts.headers.get(CONTENT_TYPE) {
+        let content_type = ContentType::parse(content_type.to_str().ok()?)?;
+        actions.extend(content_type.action);
+    }
+    if let Some(action) = parts.headers.get("SOAPAction") {
+        actions.push(action.to_str().ok()?.trim().trim_matches('"').to_string());
+    }
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.ok()?;
+        if buf.len() + chunk.len() > PRE_AUTH_BODY_LIMIT {
+            return None;
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    let envelope = Element::parse(buf.as_slice()).ok()?;
+    let namespace = envelope.namespace.as_deref()?;
+    if envelope.name != "Envelope" || ![SOAP11_NAMESPACE, SOAP12_NAMESPACE].contains(&namespace) {
+        return None;
+    }
+    let child = |name: &str| {
+        envelope
+            .children
+            .iter()
+            .filter_map(|c| c.as_element())
+            .find(|c| c.name == name && c.namespace.as_deref() == Some(namespace))
+    };
+    if let Some(header) = child("Header") {
+        actions.extend(
+            header
+                .children
+                .iter()
+                .filter_map(|c| c.as_element())
+                .filter(|c| c.name == "Action" && c.namespace.as_deref() == Some(WSA_NAMESPACE))
+                .map(|c| c.get_text().unwrap_or_default().trim().to_string()),
+        );
+    }
+    // A single operation, which the actions can't route elsewhere
+    let mut elements = child("Body")?
+        .children
+        .iter()
+        .filter_map(|c| c.as_element());
+    let (Some(operation), None) = (elements.next(), elements.next()) else {
+        return None;
+    };
+    let operation = QName {
+        namespace: operation.namespace.clone().unwrap_or_default(),
+        name: operation.name.clone(),
+    };
+    let action = format!("{}/{}", operation.namespace, operation.name);
+    (is_pre_auth(&operation) && actions.iter().all(|a| a.is_empty() || *a == action))
+        .then(|| buf.into())
+}
+
+/// Parse the parameters of a `Digest` Authorization header
+fn parse_digest_header(value: &str) -> Option<HashMap<String, String>> {
+    let (scheme, mut rest) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Digest") {
+        return None;
+    }
+    let mut params = HashMap::new();
+    loop {
+        rest = rest.trim_start_matches([' ', ',']);
+        if rest.is_empty() {
+            return Some(params);
+        }
+        let (name, after) = rest.split_once('=')?;
+        let after = after.trim_start();
+        let (value, remaining) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                let end = loop {
+                    match chars.next()? {
+                        (_, '\\') => value.push(chars.next()?.1),
+                        (i, '"') => break i,
+                        (_, c) => value.push(c),
+                    }
+                };
+                (value, &quoted[end + 1..])
+            }
+            None => {
+                let end = after.find(',').unwrap_or(after.len());
+                (after[..end].trim().to_string(), &after[end..])
+            }
+        };
+        params.insert(name.trim().to_ascii_lowercase(), value);
+        rest = remaining;
+    }
+}
+
+impl<S, C> Service<Request<Body>> for DigestAuth<S, C>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send,
+    C: CredentialStore,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        match self.state.authenticate(&req) {
+            Outcome::Authenticated(user) => {
+                req.extensions_mut().insert(AuthenticatedUser(user));
+            }
+            Outcome::Anonymous => (),
+            Outcome::Challenge { stale } => {
+                let resp = self.state.challenge(stale);
+                // Keep the service which was polled ready
+                let clone = self.inner.clone();
+                let mut inner = std::mem::replace(&mut self.inner, clone);
+                return Box::pin(async move {
+                    let (mut parts, body) = req.into_parts();
+                    match pre_auth_body(&parts, body).await {
+                        Some(body) => {
+                            parts.extensions.insert(PreAuthOnly);
+                            inner.call(Request::from_parts(parts, body.into())).await
+                        }
+                        None => Ok(resp),
+                    }
+                });
+            }
+        }
+        let fut = self.inner.call(req);
+        Box::pin(fut)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        extract::Extension,
+        router::{SoapMessage, SoapRouter},
+    };
+    use xmltree::{Element, XMLNode};
+
+    const BODY: &str = r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+        <env:Body><m:WhoAmI/></env:Body>
+    </env:Envelope>"#;
+
+    fn challenge_nonce(resp: &Response) -> (String, bool) {
+        let value = resp.headers()[WWW_AUTHENTICATE].to_str().unwrap();
+        let params = parse_digest_header(value).unwrap();
+        (params["nonce"].clone(), params.contains_key("stale"))
+    }
+
+    fn authorization(algorithm: DigestAlgorithm, nonce: &str, nc: u32, password: &str) -> String {
+        let ha1 = algorithm.hash(&format!("admin:device:{}", password));
+        let ha2 = algorithm.hash("POST:/onvif/device_service");
+        let nc = format!("{:08x}", nc);
+        let response = algorithm.hash(&format!("{}:{}:{}:abcdef:auth:{}", ha1, nonce, nc, ha2));
+        format!(
+            r#"Digest username="admin", realm="device", nonce="{}", uri="/onvif/device_service", algorithm={}, qop=auth, nc={}, cnonce="abcdef", response="{}""#,
+            nonce,
+            algorithm.name(),
+            nc,
+            response
+        )
+    }
+
+    async fn call<S>(service: &mut S, auth: Option<&str>) -> Response
+    where
+        S: Service<Request<Body>, Response = Response>,
+        S::Error: std::fmt::Debug,
+    {
+        let mut req = Request::post("/onvif/device_service");
+        if let Some(auth) = auth {
+            req = req.header(AUTHORIZATION, auth);
+        }
+        service
+            .call(req.body(BODY.to_string().into()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_digest_header() {
+        let params =
+            parse_digest_header(r#"Digest username="a\"b", qop=auth, nc=00000001,uri="/x,y""#)
+                .unwrap();
+        assert_eq!(params["username"], "a\"b");
+        assert_eq!(params["qop"], "auth");
+        assert_eq!(params["nc"], "00000001");
+        assert_eq!(params["uri"], "/x,y");
+        assert!(parse_digest_header("Basic YWRtaW4=").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_digest_auth() {
+        let now = Arc::new(Mutex::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000)));
+        let clock = now.clone();
+        let users = HashMap::from([("admin".to_string(), "secret".to_string())]);
+        let router = SoapRouter::new(()).add_operation(
+            "http://www.example.org".to_string(),
+            "WhoAmI".to_string(),
+            |Extension(user): Extension<AuthenticatedUser>| async move {
+                let mut msg = SoapMessage::new();
+                let mut elem = Element::new("User");
+                elem.children.push(XMLNode::Text(user.0));
+                msg.get_mut_body().children.push(XMLNode::Element(elem));
+                Ok(msg)
+            },
+        );
+        let mut service = DigestAuthLayer::new("device", users)
+            .required(true)
+            .with_clock(move || *clock.lock().unwrap())
+            .layer(router);
+
+        let resp = call(&mut service, None).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(resp.headers().get_all(WWW_AUTHENTICATE).iter().count(), 2);
+        let (nonce, stale) = challenge_nonce(&resp);
+        assert!(!stale);
+
+        for (algorithm, nc) in [(DigestAlgorithm::Sha256, 1), (DigestAlgorithm::Md5, 2)] {
+            let auth = authorization(algorithm, &nonce, nc, "secret");
+            let resp = call(&mut service, Some(&auth)).await;
+            assert!(resp.status().is_success());
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let msg = SoapMessage::from(Element::parse(body.as_ref()).unwrap());
+            assert_eq!(
+                msg.get_body()
+                    .get_child("User")
+                    .and_then(|u| u.get_text())
+                    .as_deref(),
+                Some("admin")
+            );
+        }
+
+        // Replayed nonce count, wrong password and forged nonce
+        for auth in [
+            authorization(DigestAlgorithm::Md5, &nonce, 2, "secret"),
+            authorization(DigestAlgorithm::Md5, &nonce, 3, "guess"),
+            authorization(DigestAlgorithm::Md5, &format!("{:0>80}", 1), 1, "secret"),
+        ] {
+            let resp = call(&mut service, Some(&auth)).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        // Pipelined requests may arrive out of order, but only once
+        for (nc, status) in [
+            (5, StatusCode::OK),
+            (4, StatusCode::OK),
+            (4, StatusCode::UNAUTHORIZED),
+            (0, StatusCode::UNAUTHORIZED),
+        ] {
+            let auth = authorization(DigestAlgorithm::Sha256, &nonce, nc, "secret");
+            assert_eq!(call(&mut service, Some(&auth)).await.status(), status);
+        }
+
+        // RFC 2069 responses, without qop, can't be checked for replays
+        let ha1 = DigestAlgorithm::Md5.hash("admin:device:secret");
+        let ha2 = DigestAlgorithm::Md5.hash("POST:/onvif/device_service");
+        let response = DigestAlgorithm::Md5.hash(&format!("{}:{}:{}", ha1, nonce, ha2));
+        let auth = format!(
+            r#"Digest username="admin", realm="device", nonce="{}", uri="/onvif/device_service", response="{}""#,
+            nonce, response
+        );
+        let resp = call(&mut service, Some(&auth)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        *now.lock().unwrap() += Duration::from_secs(600);
+        let auth = authorization(DigestAlgorithm::Sha256, &nonce, 6, "secret");
+        let resp = call(&mut service, Some(&auth)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let (fresh, stale) = challenge_nonce(&resp);
+        assert!(stale);
+        let auth = authorization(DigestAlgorithm::Sha256, &fresh, 1, "secret");
+        assert!(call(&mut service, Some(&auth)).await.status().is_success());
+    }
+
+    #[tokio::test]
+    async fn test_digest_pre_auth() {
+        let users = HashMap::from([("admin".to_string(), "secret".to_string())]);
+        let ns = crate::soap_security::DEVICE_NAMESPACE;
+        let ok = || async move { Ok(SoapMessage::new()) };
+        let router = SoapRouter::new(())
+            .add_operation(ns.to_string(), "GetSystemDateAndTime".to_string(), ok)
+            .add_operation(ns.to_string(), "GetUsers".to_string(), ok)
+            .add_operation(ns.to_string(), "SetUser".to_string(), ok)
+            .add_action_route(format!("{ns}/SetUser"), ok);
+        let mut service = DigestAuthLayer::new("device", users)
+            .required(true)
+            .layer(router);
+
+        let forged = authorization(DigestAlgorithm::Md5, &format!("{:0>80}", 1), 1, "secret");
+        let soap12 = "application/soap+xml; charset=utf-8";
+        let own_action = format!(r#"{soap12}; action="{ns}/GetSystemDateAndTime""#);
+        let other_action = format!(r#"{soap12}; action="{ns}/SetUser""#);
+        let wsa_action =
+            format!(r#"<wsa:Action xmlns:wsa="{WSA_NAMESPACE}">{ns}/SetUser</wsa:Action>"#);
+        for (header, body, content_type, auth, status) in [
+            (
+                "",
+                "<tds:GetSystemDateAndTime/>",
+                soap12,
+                None,
+                StatusCode::OK,
+            ),
+            (
+                "",
+                "<tds:GetSystemDateAndTime/>",
+                soap12,
+                Some(forged.as_str()),
+                StatusCode::OK,
+            ),
+            (
+                "",
+                "<tds:GetSystemDateAndTime/>",
+                &own_action,
+                None,
+                StatusCode::OK,
+            ),
+            (
+                "",
+                "<tds:GetUsers/>",
+                soap12,
+                None,
+                StatusCode::UNAUTHORIZED,
+            ),
+            // Only the first operation is pre-auth
+            (
+                "",
+                "<tds:GetSystemDateAndTime/><tds:SetUser/>",
+                soap12,
+                None,
+                StatusCode::UNAUTHORIZED,
+            ),
+            // Actions routing to other operations
+            (
+                "",
+                "<tds:GetSystemDateAndTime/>",
+                &other_action,
+                None,
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                &wsa_action,
+                "<tds:GetSystemDateAndTime/>",
+                soap12,
+                None,
+                StatusCode::UNAUTHORIZED,
+            ),
+        ] {
+            let envelope = format!(
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:tds="{ns}">
+                    <env:Header>{header}</env:Header>
+                    <env:Body>{body}</env:Body>
+                </env:Envelope>"#
+            );
+            let mut req = Request::post("/onvif/device_service").header(CONTENT_TYPE, content_type);
+            if let Some(auth) = auth {
+                req = req.header(AUTHORIZATION, auth);
+            }
+            let resp = service
+                .call(req.body(envelope.into()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(
+                resp.status(),
+                status,
+                "{} {} {}",
+                header,
+                body,
+                content_type
+            );
+        }
+
+        // Not in the SOAP envelope namespace
+        let body = format!(
+            r#"<env:Envelope xmlns:env="http://www.example.org" xmlns:tds="{ns}">
+                <env:Body><tds:GetSystemDateAndTime/></env:Body>
+            </env:Envelope>"#
+        );
+        let req = Request::post("/onvif/device_service")
+            .body(body.into())
+            .unwrap();
+        let resp = service.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // Too large to be inspected
+        let body = format!(
+            r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:tds="{ns}">
+                <env:Header>{}</env:Header>
+                <env:Body><tds:GetSystemDateAndTime/></env:Body>
+            </env:Envelope>"#,
+            " ".repeat(PRE_AUTH_BODY_LIMIT)
+        );
+        let req = Request::post("/onvif/device_service")
+            .body(body.into())
+            .unwrap();
+        let resp = service.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+/// Source of the random data used for the identifiers generated by the crate
+/// (message IDs, nonces, MIME boundaries...).
+///
+/// The default source uses the operating system RNG, embedded targets can
+/// plug a hardware RNG and tests a deterministic source.
+pub trait EntropySource: Send + Sync + 'static {
+    fn fill_bytes(&self, dest: &mut [u8]);
+}
+
+/// Entropy source backed by the operating system RNG
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OsEntropy;
+
+impl EntropySource for OsEntropy {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        getrandom::getrandom(dest).expect("OS random number generator unavailable")
+    }
+}
+
+pub fn default_source() -> Arc<dyn EntropySource> {
+    Arc::new(OsEntropy)
+}
+
+/// Generate a random (version 4) UUID in its hyphenated form
+pub fn uuid(source: &dyn EntropySource) -> String {
+    let mut b = [0u8; 16];
+    source.fill_bytes(&mut b);
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    let hex: String = b.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Zeroes;
+
+    impl EntropySource for Zeroes {
+        fn fill_bytes(&self, dest: &mut [u8]) {
+            dest.fill(0);
+        }
+    }
+
+    #[test]
+    fn test_uuid() {
+        assert_eq!(uuid(&Zeroes), "00000000-0000-4000-8000-000000000000");
+
+        let id = uuid(&OsEntropy);
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "4");
+        assert_ne!(id, uuid(&OsEntropy));
+    }
+}
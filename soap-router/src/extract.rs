@@ -0,0 +1,229 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    fault::{SoapFault, SoapFaultCode},
+    router::SoapRequest,
+};
+
+/// Types that can be created from a SOAP request and the router state, used as
+/// arguments of SOAP handlers.
+pub trait FromSoapRequest<S>: Sized {
+    fn from_soap_request(req: &SoapRequest, state: &S) -> Result<Self, SoapFault>;
+}
+
+impl<S, T> FromSoapRequest<S> for Option<T>
+where
+    T: FromSoapRequest<S>,
+{
+    fn from_soap_request(req: &SoapRequest, state: &S) -> Result<Self, SoapFault> {
+        Ok(T::from_soap_request(req, state).ok())
+    }
+}
+
+/// Used to extract a substate from the router state, see [`State`].
+pub trait FromRef<T> {
+    fn from_ref(input: &T) -> Self;
+}
+
+impl<T: Clone> FromRef<T> for T {
+    fn from_ref(input: &T) -> Self {
+        input.clone()
+    }
+}
+
+/// Extractor for the router state, or any part of it implementing [`FromRef`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct State<T>(pub T);
+
+impl<S, T> FromSoapRequest<S> for State<T>
+where
+    T: FromRef<S>,
+{
+    fn from_soap_request(_req: &SoapRequest, state: &S) -> Result<Self, SoapFault> {
+        Ok(State(T::from_ref(state)))
+    }
+}
+
+/// Extractor for the raw XML element of the operation, as found in the
+/// request body.
+#[derive(Debug, Clone)]
+pub struct RawBody(pub xmltree::Element);
+
+impl<S> FromSoapRequest<S> for RawBody {
+    fn from_soap_request(req: &SoapRequest, _state: &S) -> Result<Self, SoapFault> {
+        Ok(RawBody(req.body.clone()))
+    }
+}
+
+/// Envelope as received, once decoded, added to the request extensions by
+/// the router. Needed when the exact bytes matter, such as to verify
+/// signatures.
+#[derive(Debug, Clone)]
+pub struct RawEnvelope(pub bytes::Bytes);
+
+/// Extractor giving access to the header blocks of the response, the router
+/// merges them into the final envelope.
+#[derive(Debug, Clone, Default)]
+pub struct ResponseHeaders(Arc<Mutex<Vec<xmltree::Element>>>);
+
+impl ResponseHeaders {
+    pub fn push(&self, header: impl Into<xmltree::Element>) {
+        self.0.lock().unwrap().push(header.into());
+    }
+
+    pub(crate) fn take(&self) -> Vec<xmltree::Element> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
+impl<S> FromSoapRequest<S> for ResponseHeaders {
+    fn from_soap_request(req: &SoapRequest, _state: &S) -> Result<Self, SoapFault> {
+        Ok(req.response_headers.clone())
+    }
+}
+
+trait ExtensionValue: Any + Send + Sync {
+    fn clone_box(&self) -> Box<dyn ExtensionValue>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Clone + Send + Sync + 'static> ExtensionValue for T {
+    fn clone_box(&self) -> Box<dyn ExtensionValue> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// Per-request values stored by type, populated by header processors and
+/// layers and read by handlers through [`Extension`].
+#[derive(Default)]
+pub struct Extensions(HashMap<TypeId, Box<dyn ExtensionValue>>);
+
+impl Clone for Extensions {
+    fn clone(&self) -> Self {
+        Self(
+            self.0
+                .iter()
+                .map(|(id, value)| (*id, (**value).clone_box()))
+                .collect(),
+        )
+    }
+}
+
+impl std::fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Extensions").finish_non_exhaustive()
+    }
+}
+
+impl Extensions {
+    /// Insert a value, returning the previous one of the same type
+    pub fn insert<T: Clone + Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.0
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|prev| prev.into_any().downcast().ok())
+            .map(|prev| *prev)
+    }
+
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.0
+            .get(&TypeId::of::<T>())
+            .and_then(|v| (**v).as_any().downcast_ref())
+    }
+
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.0
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|v| (**v).as_any_mut().downcast_mut())
+    }
+
+    pub fn remove<T: 'static>(&mut self) -> Option<T> {
+        self.0
+            .remove(&TypeId::of::<T>())
+            .and_then(|v| v.into_any().downcast().ok())
+            .map(|v| *v)
+    }
+}
+
+/// Extractor for a value of the request [`Extensions`], failing with a
+/// Receiver fault when it is missing.
+#[derive(Debug, Clone, Copy)]
+pub struct Extension<T>(pub T);
+
+impl<S, T> FromSoapRequest<S> for Extension<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    fn from_soap_request(req: &SoapRequest, _state: &S) -> Result<Self, SoapFault> {
+        req.extensions
+            .get::<T>()
+            .cloned()
+            .map(Extension)
+            .ok_or_else(|| {
+                SoapFault::builder(SoapFaultCode::Receiver)
+                    .builtin_reason("InternalError")
+                    .build()
+            })
+    }
+}
+
+impl<T> Deref for Extension<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> Deref for State<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for State<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extensions() {
+        let mut ext = Extensions::default();
+        assert_eq!(ext.insert(1u32), None);
+        assert_eq!(ext.insert("user".to_string()), None);
+        assert_eq!(ext.insert(2u32), Some(1));
+        *ext.get_mut::<u32>().unwrap() += 1;
+
+        let cloned = ext.clone();
+        assert_eq!(ext.remove::<u32>(), Some(3));
+        assert_eq!(ext.get::<u32>(), None);
+        assert_eq!(cloned.get::<u32>(), Some(&3));
+        assert_eq!(cloned.get::<String>().map(String::as_str), Some("user"));
+        assert_eq!(cloned.get::<u64>(), None);
+    }
+}
@@ -1,51 +1,243 @@
 use std::{
     collections::{HashMap, HashSet},
     io::Write,
+    marker::PhantomData,
 };
 
-use axum::response::IntoResponse;
+use axum::{
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::IntoResponse,
+};
 use bytes::BufMut;
 use url::Url;
 use xmltree::Element;
+use yaserde::{YaDeserialize, YaSerialize};
 
-use crate::router::SoapMessage;
+use crate::{
+    router::SoapMessage,
+    version::{ContentType, SoapVersion, SOAP11_NAMESPACE, SOAP12_NAMESPACE},
+};
 
 #[derive(Debug)]
 pub struct SoapFault {
     code: SoapFaultCode,
     sub_codes: Vec<(url::Url, String)>,
     reason: HashMap<isolang::Language, String>,
-    detail: Option<xmltree::Element>,
+    detail: Option<Box<xmltree::Element>>,
+    status: Option<StatusCode>,
 }
 
-#[derive(strum_macros::Display, Debug)]
-pub enum SoapFaultCode {
-    VersionMismatch,
-    MustUnderstand,
-    DataEncodingUnknown,
-    Sender,
-    Receiver,
+/// Type usable as a fault detail entry, see the `SoapFaultDetail` derive
+pub trait FaultDetail: YaSerialize + YaDeserialize {
+    /// Local name of the serialized element
+    const ELEMENT_NAME: &'static str;
+    /// Namespace of the serialized element
+    const NAMESPACE: Option<&'static str>;
 }
 
+pub use soap_core::FaultCode as SoapFaultCode;
+
 impl std::error::Error for SoapFault {}
 
 impl SoapFault {
+    /// Falls back to the name of the code when `reason` is empty
+    #[deprecated(note = "use SoapFault::builder, which requires a reason")]
     pub fn new(
         code: SoapFaultCode,
         sub_codes: Vec<(url::Url, String)>,
         reason: HashMap<isolang::Language, String>,
         detail: Option<xmltree::Element>,
     ) -> Self {
-        // Reason should not be empty
-        if reason.is_empty() {
-            panic!("Given an empty Soap Fault Reason")
+        Self::from_parts(code, sub_codes, reason, detail)
+    }
+
+    fn from_parts(
+        code: SoapFaultCode,
+        sub_codes: Vec<(url::Url, String)>,
+        reason: HashMap<isolang::Language, String>,
+        detail: Option<xmltree::Element>,
+    ) -> Self {
+        let fallback = code.to_string();
+        let mut builder = sub_codes
+            .into_iter()
+            .fold(Self::builder(code), |b, (ns, name)| b.subcode(ns, &name));
+        if let Some(detail) = detail {
+            builder = builder.detail(detail);
         }
+        let mut reason = reason.into_iter();
+        let builder = match reason.next() {
+            Some((language, text)) => builder.reason(language, &text),
+            None => builder.reason_en(&fallback),
+        };
+        builder.reasons(reason.collect()).build()
+    }
+
+    pub fn code(&self) -> &SoapFaultCode {
+        &self.code
+    }
+
+    /// Subcodes, from the outermost to the innermost one
+    pub fn sub_codes(&self) -> &[(Url, String)] {
+        &self.sub_codes
+    }
+
+    /// `env:Detail` element of the fault
+    pub fn detail(&self) -> Option<&Element> {
+        self.detail.as_deref()
+    }
+
+    /// Set the detail of the fault to the serialized value, wrapped in an
+    /// `env:Detail` element. The fault is kept without detail when the value
+    /// can't be serialized.
+    pub fn with_detail<T: YaSerialize>(mut self, detail: &T) -> Self {
+        let Ok(entry) = crate::codec::to_element(detail) else {
+            tracing::warn!("Fault detail could not be serialized, leaving it out");
+            return self;
+        };
+        let mut elem = soap_element("Detail");
+        elem.children.push(xmltree::XMLNode::Element(entry));
+        self.detail = Some(Box::new(elem));
+        self
+    }
+
+    /// Deserialize the first detail entry named after `T`, if any
+    pub fn detail_as<T: FaultDetail>(&self) -> Option<T> {
+        let detail = self.detail.as_deref()?;
+        let matches =
+            |e: &Element| e.name == T::ELEMENT_NAME && e.namespace.as_deref() == T::NAMESPACE;
+        let entry = if matches(detail) {
+            detail
+        } else {
+            detail
+                .children
+                .iter()
+                .filter_map(|c| c.as_element())
+                .find(|e| matches(e))?
+        };
+        crate::codec::from_element(entry).ok()
+    }
+
+    /// Override the HTTP status code of the response carrying this fault
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = Some(status);
+        self
+    }
 
-        Self {
+    /// HTTP status code of the response carrying this fault: the SOAP 1.2
+    /// binding answers 400 for Sender faults and 500 otherwise, SOAP 1.1
+    /// always answers 500, unless overridden by [`SoapFault::with_status`].
+    pub fn status(&self, version: SoapVersion) -> StatusCode {
+        self.status.unwrap_or(match (version, &self.code) {
+            (SoapVersion::Soap12, SoapFaultCode::Sender) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })
+    }
+
+    /// Reason text in the best matching language, falling back to English
+    pub fn reason(&self, preferred: &[isolang::Language]) -> &str {
+        crate::i18n::select(&self.reason, preferred)
+            .map(|(_, r)| r)
+            .unwrap()
+    }
+}
+
+impl SoapFault {
+    /// Start building a fault with the given code, a reason must be given
+    /// before the fault can be built.
+    pub fn builder(code: SoapFaultCode) -> SoapFaultBuilder<NoReason> {
+        SoapFaultBuilder {
             code,
-            sub_codes,
-            reason,
-            detail,
+            sub_codes: vec![],
+            reason: HashMap::new(),
+            detail: None,
+            _reason: PhantomData,
+        }
+    }
+
+    /// Sender fault with an English reason
+    pub fn sender(reason: &str) -> Self {
+        Self::builder(SoapFaultCode::Sender)
+            .reason_en(reason)
+            .build()
+    }
+
+    /// Receiver fault with an English reason
+    pub fn receiver(reason: &str) -> Self {
+        Self::builder(SoapFaultCode::Receiver)
+            .reason_en(reason)
+            .build()
+    }
+}
+
+/// Marker of a [`SoapFaultBuilder`] without any reason yet
+pub struct NoReason;
+/// Marker of a [`SoapFaultBuilder`] holding at least one reason
+pub struct WithReason;
+
+/// Builder of [`SoapFault`], see [`SoapFault::builder`]
+pub struct SoapFaultBuilder<R> {
+    code: SoapFaultCode,
+    sub_codes: Vec<(Url, String)>,
+    reason: HashMap<isolang::Language, String>,
+    detail: Option<Element>,
+    _reason: PhantomData<R>,
+}
+
+impl<R> SoapFaultBuilder<R> {
+    /// Add a reason text in the given language
+    pub fn reason(
+        mut self,
+        language: isolang::Language,
+        text: &str,
+    ) -> SoapFaultBuilder<WithReason> {
+        self.reason.insert(language, text.to_string());
+        SoapFaultBuilder {
+            code: self.code,
+            sub_codes: self.sub_codes,
+            reason: self.reason,
+            detail: self.detail,
+            _reason: PhantomData,
+        }
+    }
+
+    pub fn reason_en(self, text: &str) -> SoapFaultBuilder<WithReason> {
+        self.reason(isolang::Language::Eng, text)
+    }
+
+    /// Add all the translations of a reason, e.g. from
+    /// [`Translations::texts`](crate::i18n::Translations::texts)
+    pub fn reasons(mut self, texts: HashMap<isolang::Language, String>) -> Self {
+        self.reason.extend(texts);
+        self
+    }
+
+    /// Append a subcode, nested in the previously added ones
+    pub fn subcode(mut self, namespace: Url, name: &str) -> Self {
+        self.sub_codes.push((namespace, name.to_string()));
+        self
+    }
+
+    pub fn detail(mut self, detail: Element) -> Self {
+        self.detail = Some(detail);
+        self
+    }
+
+    /// Add all the translations of a reason of the built-in catalog
+    pub(crate) fn builtin_reason(self, key: &str) -> SoapFaultBuilder<WithReason> {
+        let texts = crate::i18n::builtin_reason(key);
+        let english = texts[&isolang::Language::Eng].clone();
+        self.reason_en(&english).reasons(texts)
+    }
+}
+
+impl SoapFaultBuilder<WithReason> {
+    pub fn build(self) -> SoapFault {
+        SoapFault {
+            code: self.code,
+            sub_codes: self.sub_codes,
+            reason: self.reason,
+            detail: self.detail.map(Box::new),
+            status: None,
         }
     }
 }
@@ -74,10 +266,327 @@ impl PrefixGenerator {
     }
 }
 
+fn soap_element(name: &str) -> Element {
+    let mut elem = Element::new(name);
+    elem.prefix = Some("env".to_string());
+    elem.namespace = Some("http://www.w3.org/2003/05/soap-envelope".to_string());
+    elem
+}
+
+/// Lets handlers return values infallibly converted to messages
+impl From<std::convert::Infallible> for SoapFault {
+    fn from(value: std::convert::Infallible) -> Self {
+        match value {}
+    }
+}
+
+impl SoapFault {
+    /// Serialize the fault for the given SOAP version, SOAP 1.1 only carries
+    /// one reason so the best match for the given languages is used.
+    pub fn into_message(
+        self,
+        version: SoapVersion,
+        languages: &[isolang::Language],
+    ) -> SoapMessage {
+        match version {
+            SoapVersion::Soap11 => self.into_soap11(languages),
+            SoapVersion::Soap12 => self.into(),
+        }
+    }
+
+    fn into_soap11(self, languages: &[isolang::Language]) -> SoapMessage {
+        let mut msg = SoapMessage::with_version(SoapVersion::Soap11);
+        let mut fault = Element::new("Fault");
+        fault.prefix = Some("env".to_string());
+        fault.namespace = Some(SOAP11_NAMESPACE.to_string());
+
+        // SOAP 1.1 has no subcodes, their local names are appended to the
+        // code with dots, e.g. `env:Client.InvalidArgVal`
+        let mut faultcode = Element::new("faultcode");
+        let code = std::iter::once(match self.code {
+            SoapFaultCode::VersionMismatch => "env:VersionMismatch",
+            SoapFaultCode::MustUnderstand => "env:MustUnderstand",
+            SoapFaultCode::DataEncodingUnknown | SoapFaultCode::Sender => "env:Client",
+            SoapFaultCode::Receiver => "env:Server",
+        })
+        .chain(self.sub_codes.iter().map(|(_, code)| code.as_str()))
+        .collect::<Vec<_>>()
+        .join(".");
+        faultcode.children.push(xmltree::XMLNode::Text(code));
+        fault.children.push(xmltree::XMLNode::Element(faultcode));
+
+        let mut faultstring = Element::new("faultstring");
+        let (lang, reason) = crate::i18n::select(&self.reason, languages).unwrap();
+        faultstring.attributes.insert(
+            "xml:lang".to_string(),
+            lang.to_639_1().unwrap_or(lang.to_639_3()).to_string(),
+        );
+        faultstring
+            .children
+            .push(xmltree::XMLNode::Text(reason.to_string()));
+        fault.children.push(xmltree::XMLNode::Element(faultstring));
+
+        let mut detail = Element::new("detail");
+        if let Some(det) = self.detail {
+            if det.name == "Detail" && det.namespace.as_deref() == Some(SOAP12_NAMESPACE) {
+                detail.children = det.children;
+            } else {
+                detail.children.push(xmltree::XMLNode::Element(*det));
+            }
+        }
+        if !detail.children.is_empty() {
+            fault.children.push(xmltree::XMLNode::Element(detail));
+        }
+
+        msg.get_mut_body()
+            .children
+            .push(xmltree::XMLNode::Element(fault));
+        msg
+    }
+}
+
+/// Resolve a `prefix:name` QName value against the namespaces in scope
+fn resolve_qname(elem: &Element, value: &str) -> Option<(Option<Url>, String)> {
+    let (namespace, name) = match value.trim().split_once(':') {
+        Some((prefix, name)) => (
+            Some(elem.namespaces.as_ref()?.get(prefix)?),
+            name.to_string(),
+        ),
+        None => (
+            elem.namespaces.as_ref().and_then(|n| n.get("")),
+            value.trim().to_string(),
+        ),
+    };
+    Some((namespace.and_then(|ns| Url::parse(ns).ok()), name))
+}
+
+fn reason_language(elem: &Element) -> isolang::Language {
+    elem.attributes
+        .iter()
+        .find(|(k, _)| *k == "lang" || *k == "xml:lang")
+        .and_then(|(_, lang)| {
+            let lang = lang.split('-').next().unwrap_or_default();
+            isolang::Language::from_639_1(lang).or(isolang::Language::from_639_3(lang))
+        })
+        .unwrap_or(isolang::Language::Eng)
+}
+
+/// Error returned when an element or a message doesn't hold a valid fault
+#[derive(Debug)]
+pub struct InvalidFault;
+
+impl std::fmt::Display for InvalidFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Invalid SOAP fault")
+    }
+}
+
+impl std::error::Error for InvalidFault {}
+
+/// Parse a SOAP 1.1 or 1.2 `Fault` element, its version is given by its
+/// namespace.
+impl TryFrom<&Element> for SoapFault {
+    type Error = InvalidFault;
+
+    fn try_from(fault: &Element) -> Result<Self, Self::Error> {
+        let version = fault
+            .namespace
+            .as_deref()
+            .and_then(SoapVersion::from_namespace)
+            .ok_or(InvalidFault)?;
+        if fault.name != "Fault" {
+            return Err(InvalidFault);
+        }
+        Self::from_fault_element(fault, version).ok_or(InvalidFault)
+    }
+}
+
+/// Parse the fault held in the body of a message
+impl TryFrom<SoapMessage> for SoapFault {
+    type Error = InvalidFault;
+
+    fn try_from(msg: SoapMessage) -> Result<Self, Self::Error> {
+        let version = msg.version();
+        let fault = msg
+            .envelope()
+            .get_child(("Body", version.namespace()))
+            .and_then(|b| b.get_child(("Fault", version.namespace())))
+            .ok_or(InvalidFault)?;
+        Self::try_from(fault)
+    }
+}
+
+impl SoapFault {
+    fn from_fault_element(fault: &Element, version: SoapVersion) -> Option<Self> {
+        match version {
+            SoapVersion::Soap12 => Self::from_soap12(fault),
+            SoapVersion::Soap11 => Self::from_soap11(fault),
+        }
+    }
+
+    fn from_soap12(fault: &Element) -> Option<Self> {
+        let child = |e: &Element, name: &str| e.get_child((name, SOAP12_NAMESPACE)).cloned();
+        let value = |e: &Element| {
+            let value = e.get_child(("Value", SOAP12_NAMESPACE))?;
+            resolve_qname(value, &value.get_text()?)
+        };
+
+        let code_elem = child(fault, "Code")?;
+        let code = match value(&code_elem)?.1.as_str() {
+            "VersionMismatch" => SoapFaultCode::VersionMismatch,
+            "MustUnderstand" => SoapFaultCode::MustUnderstand,
+            "DataEncodingUnknown" => SoapFaultCode::DataEncodingUnknown,
+            "Sender" => SoapFaultCode::Sender,
+            "Receiver" => SoapFaultCode::Receiver,
+            _ => return None,
+        };
+        let mut sub_codes = vec![];
+        let mut subcode = child(&code_elem, "Subcode");
+        while let Some(s) = subcode {
+            if let Some((Some(ns), name)) = value(&s) {
+                sub_codes.push((ns, name));
+            }
+            subcode = child(&s, "Subcode");
+        }
+        let reason: HashMap<isolang::Language, String> = child(fault, "Reason")
+            .map(|r| {
+                r.children
+                    .iter()
+                    .filter_map(|c| c.as_element())
+                    .filter(|t| t.name == "Text")
+                    .map(|t| {
+                        (
+                            reason_language(t),
+                            t.get_text().unwrap_or_default().to_string(),
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(Self::from_parts(
+            code,
+            sub_codes,
+            reason,
+            child(fault, "Detail"),
+        ))
+    }
+
+    fn from_soap11(fault: &Element) -> Option<Self> {
+        let faultcode = fault.get_child("faultcode")?;
+        let (namespace, name) = resolve_qname(faultcode, &faultcode.get_text()?)?;
+        let (code, sub_codes) = match namespace {
+            // The dotted subcodes of the faultcode lose their namespace, they
+            // are reported in the one of the envelope
+            Some(ns) if ns.as_str() == SOAP11_NAMESPACE => {
+                let mut names = name.split('.');
+                let code = match names.next().unwrap_or_default() {
+                    "VersionMismatch" => SoapFaultCode::VersionMismatch,
+                    "MustUnderstand" => SoapFaultCode::MustUnderstand,
+                    "Server" => SoapFaultCode::Receiver,
+                    _ => SoapFaultCode::Sender,
+                };
+                let sub_codes = names
+                    .filter(|n| !n.is_empty())
+                    .map(|n| (ns.clone(), n.to_string()))
+                    .collect();
+                (code, sub_codes)
+            }
+            // Some implementations send the subcode in place of the code
+            Some(ns) => (SoapFaultCode::Sender, vec![(ns, name)]),
+            None => (SoapFaultCode::Sender, vec![]),
+        };
+        let reason = fault
+            .get_child("faultstring")
+            .map(|f| {
+                (
+                    reason_language(f),
+                    f.get_text().unwrap_or_default().to_string(),
+                )
+            })
+            .into_iter()
+            .collect();
+        let detail = fault.get_child("detail").map(|d| {
+            let mut detail = soap_element("Detail");
+            detail.children = d.children.clone();
+            detail
+        });
+        Some(Self::from_parts(code, sub_codes, reason, detail))
+    }
+}
+
+/// Fault of the alloc-only model of `soap-core`, a detail that isn't
+/// well-formed XML is left out
+impl From<soap_core::Fault> for SoapFault {
+    fn from(fault: soap_core::Fault) -> Self {
+        let detail = fault.detail().and_then(|detail| {
+            let detail = format!(
+                r#"<env:Detail xmlns:env="{}">{}</env:Detail>"#,
+                SOAP12_NAMESPACE, detail
+            );
+            Element::parse(detail.as_bytes()).ok()
+        });
+        Self::from_parts(
+            fault.code(),
+            fault
+                .sub_code_urls()
+                .map(|(namespace, name)| (namespace, name.to_string()))
+                .collect(),
+            fault
+                .reason_languages()
+                .map(|(language, text)| (language, text.to_string()))
+                .collect(),
+            detail,
+        )
+    }
+}
+
+impl From<&SoapFault> for soap_core::Fault {
+    fn from(fault: &SoapFault) -> Self {
+        let mut reasons: Vec<(&'static str, String)> = fault
+            .reason
+            .iter()
+            .map(|(l, text)| (l.to_639_1().unwrap_or(l.to_639_3()), text.clone()))
+            .collect();
+        reasons.sort();
+        let mut reasons = reasons.into_iter();
+        let (language, text) = reasons.next().unwrap_or(("en", fault.code.to_string()));
+        let core = reasons.fold(
+            soap_core::Fault::new(fault.code, language, text),
+            |core, (language, text)| core.with_reason(language, text),
+        );
+        let core = fault
+            .sub_codes
+            .iter()
+            .fold(core, |core, (namespace, name)| {
+                core.with_subcode(namespace.to_string(), name.clone())
+            });
+        match &fault.detail {
+            None => core,
+            Some(detail) => {
+                let entries: Vec<&Element> = match detail.name == "Detail"
+                    && detail.namespace.as_deref() == Some(SOAP12_NAMESPACE)
+                {
+                    true => detail
+                        .children
+                        .iter()
+                        .filter_map(|c| c.as_element())
+                        .collect(),
+                    false => vec![detail],
+                };
+                let mut content = vec![];
+                let config = xmltree::EmitterConfig::new().write_document_declaration(false);
+                for entry in entries {
+                    let _ = entry.write_with_config(&mut content, config.clone());
+                }
+                core.with_detail(String::from_utf8_lossy(&content))
+            }
+        }
+    }
+}
+
 impl From<SoapFault> for SoapMessage {
     fn from(val: SoapFault) -> SoapMessage {
-        let mut env = Element::new("Enveloppe");
-        env.namespace = Some("http://www.w3.org/2003/05/soap-envelope".to_string());
+        let mut env = soap_element("Envelope");
         let mut namespaces = xmltree::Namespace::empty();
         namespaces.put("xml", "http://www.w3.org/XML/1998/namespace");
         namespaces.put("env", "http://www.w3.org/2003/05/soap-envelope");
@@ -97,50 +606,66 @@ impl From<SoapFault> for SoapMessage {
 
         env.namespaces = Some(namespaces);
 
-        let mut body = Element::new("Body");
-        body.prefix = Some("env".to_string());
-
-        let mut fault = Element::new("Fault");
-        fault.prefix = Some("env".to_string());
+        // Tell the sender which envelopes are supported
+        if let SoapFaultCode::VersionMismatch = val.code {
+            let mut header = soap_element("Header");
+            let mut upgrade = soap_element("Upgrade");
+            for (prefix, namespace) in [("ns1", SOAP12_NAMESPACE), ("ns2", SOAP11_NAMESPACE)] {
+                let mut supported = soap_element("SupportedEnvelope");
+                supported
+                    .attributes
+                    .insert("qname".to_string(), format!("{}:Envelope", prefix));
+                let mut namespaces = xmltree::Namespace::empty();
+                namespaces.put(prefix, namespace);
+                supported.namespaces = Some(namespaces);
+                upgrade.children.push(xmltree::XMLNode::Element(supported));
+            }
+            header.children.push(xmltree::XMLNode::Element(upgrade));
+            env.children.push(xmltree::XMLNode::Element(header));
+        }
 
-        let mut code = Element::new("Fault");
-        code.prefix = Some("env".to_string());
+        let mut body = soap_element("Body");
+        let mut fault = soap_element("Fault");
+        let mut code = soap_element("Code");
 
-        let mut value = Element::new("Value");
-        value.prefix = Some("env".to_string());
+        let mut value = soap_element("Value");
         value
             .children
             .push(xmltree::XMLNode::Text(format!("env:{}", val.code)));
         code.children.push(xmltree::XMLNode::Element(value));
 
-        let code = val
-            .sub_codes
-            .into_iter()
-            .rev()
-            .fold(code, |acc, (ns, val)| {
-                let mut subcode = Element::new("Subcode");
-                subcode.prefix = Some("env".to_string());
-                let mut value = Element::new("Value");
-                value.prefix = Some("env".to_string());
-                value.children.push(xmltree::XMLNode::Text(format!(
-                    "{}:{}",
-                    code_namespaces.get(&ns).as_ref().unwrap(),
-                    val
-                )));
-                subcode.children.push(xmltree::XMLNode::Element(value));
-                subcode.children.push(xmltree::XMLNode::Element(acc));
-                subcode
-            });
+        // Subcodes are nested, the first one being the outermost
+        let subcode =
+            val.sub_codes
+                .into_iter()
+                .rev()
+                .fold(None, |inner: Option<Element>, (ns, val)| {
+                    let mut subcode = soap_element("Subcode");
+                    let mut value = soap_element("Value");
+                    value.children.push(xmltree::XMLNode::Text(format!(
+                        "{}:{}",
+                        code_namespaces.get(&ns).as_ref().unwrap(),
+                        val
+                    )));
+                    subcode.children.push(xmltree::XMLNode::Element(value));
+                    if let Some(inner) = inner {
+                        subcode.children.push(xmltree::XMLNode::Element(inner));
+                    }
+                    Some(subcode)
+                });
+        if let Some(subcode) = subcode {
+            code.children.push(xmltree::XMLNode::Element(subcode));
+        }
 
         fault.children.push(xmltree::XMLNode::Element(code));
 
-        let mut reason = Element::new("Reason");
-        reason.prefix = Some("env".to_string());
+        let reason = soap_element("Reason");
         let reason = val.reason.into_iter().fold(reason, |mut acc, (ln, val)| {
-            let mut text = Element::new("Text");
-            text.prefix = Some("env".to_string());
-            text.attributes
-                .insert("xml:lang".to_string(), ln.to_639_3().to_string());
+            let mut text = soap_element("Text");
+            text.attributes.insert(
+                "xml:lang".to_string(),
+                ln.to_639_1().unwrap_or(ln.to_639_3()).to_string(),
+            );
             text.children.push(xmltree::XMLNode::Text(val));
             acc.children.push(xmltree::XMLNode::Element(text));
             acc
@@ -149,11 +674,11 @@ impl From<SoapFault> for SoapMessage {
         fault.children.push(xmltree::XMLNode::Element(reason));
 
         if let Some(det) = val.detail {
-            fault.children.push(xmltree::XMLNode::Element(det));
+            fault.children.push(xmltree::XMLNode::Element(*det));
         }
         body.children.push(xmltree::XMLNode::Element(fault));
         env.children.push(xmltree::XMLNode::Element(body));
-        SoapMessage(env)
+        env.into()
     }
 }
 
@@ -162,18 +687,213 @@ impl std::fmt::Display for SoapFault {
         f.write_fmt(format_args!(
             "SOAP Fault <{}.>: {}",
             self.code,
-            self.reason
-                .get(&isolang::Language::Eng)
-                .unwrap_or(self.reason.values().next().unwrap())
+            self.reason(&[])
         ))
     }
 }
 
 impl IntoResponse for SoapFault {
     fn into_response(self) -> axum::response::Response {
-        let xml_body = Into::<SoapMessage>::into(self).0;
+        let status = self.status(SoapVersion::Soap12);
+        let xml_body = Into::<SoapMessage>::into(self).into_envelope();
         let mut buf = vec![].writer();
         xml_body.write(buf.by_ref()).unwrap();
-        buf.into_inner().into_response()
+        (
+            status,
+            [(CONTENT_TYPE, ContentType::response(SoapVersion::Soap12))],
+            buf.into_inner(),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder() {
+        let ns = Url::parse("http://www.example.org").unwrap();
+        let fault = SoapFault::builder(SoapFaultCode::Sender)
+            .subcode(ns.clone(), "Outer")
+            .reason_en("Invalid value")
+            .reason(isolang::Language::Fra, "Valeur invalide")
+            .subcode(ns.clone(), "Inner")
+            .detail(Element::new("Detail"))
+            .build();
+        assert!(matches!(fault.code(), SoapFaultCode::Sender));
+        assert_eq!(
+            fault.sub_codes(),
+            &[(ns.clone(), "Outer".to_string()), (ns, "Inner".to_string())]
+        );
+        assert_eq!(fault.reason(&[isolang::Language::Fra]), "Valeur invalide");
+        assert_eq!(fault.reason(&[isolang::Language::Deu]), "Invalid value");
+        assert!(fault.detail.is_some());
+
+        let fault = SoapFault::receiver("Device busy");
+        assert!(matches!(fault.code(), SoapFaultCode::Receiver));
+        assert!(fault.sub_codes().is_empty());
+        assert_eq!(fault.reason(&[]), "Device busy");
+
+        #[allow(deprecated)]
+        let fault = SoapFault::new(SoapFaultCode::Receiver, vec![], HashMap::new(), None);
+        assert_eq!(fault.reason(&[]), "Receiver");
+
+        // Details failing to serialize are left out of the fault
+        struct Broken;
+        impl YaSerialize for Broken {
+            fn serialize<W: Write>(
+                &self,
+                _writer: &mut yaserde::ser::Serializer<W>,
+            ) -> Result<(), String> {
+                Err("broken".to_string())
+            }
+
+            fn serialize_attributes(
+                &self,
+                attributes: Vec<yaserde::__xml::attribute::OwnedAttribute>,
+                namespace: yaserde::__xml::namespace::Namespace,
+            ) -> Result<
+                (
+                    Vec<yaserde::__xml::attribute::OwnedAttribute>,
+                    yaserde::__xml::namespace::Namespace,
+                ),
+                String,
+            > {
+                Ok((attributes, namespace))
+            }
+        }
+        let fault = SoapFault::sender("Invalid value").with_detail(&Broken);
+        assert!(matches!(fault.code(), SoapFaultCode::Sender));
+        assert_eq!(fault.reason(&[]), "Invalid value");
+        assert!(fault.detail().is_none());
+    }
+
+    #[test]
+    fn test_parse_fault() {
+        let raw = r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:ter="http://www.onvif.org/ver10/error">
+            <env:Body>
+                <env:Fault>
+                    <env:Code>
+                        <env:Value>env:Sender</env:Value>
+                        <env:Subcode>
+                            <env:Value>ter:InvalidArgVal</env:Value>
+                            <env:Subcode>
+                                <env:Value xmlns:x="http://www.example.org">x:NoProfile</env:Value>
+                            </env:Subcode>
+                        </env:Subcode>
+                    </env:Code>
+                    <env:Reason>
+                        <env:Text xml:lang="en">No such profile</env:Text>
+                        <env:Text xml:lang="fr-FR">Profil inexistant</env:Text>
+                    </env:Reason>
+                    <env:Detail><x:Token xmlns:x="http://www.example.org">main</x:Token></env:Detail>
+                </env:Fault>
+            </env:Body>
+        </env:Envelope>"#;
+        let fault = SoapFault::try_from(SoapMessage::from(Element::parse(raw.as_bytes()).unwrap()))
+            .unwrap();
+        assert!(matches!(fault.code(), SoapFaultCode::Sender));
+        assert_eq!(
+            fault.sub_codes(),
+            &[
+                (
+                    Url::parse("http://www.onvif.org/ver10/error").unwrap(),
+                    "InvalidArgVal".to_string()
+                ),
+                (
+                    Url::parse("http://www.example.org").unwrap(),
+                    "NoProfile".to_string()
+                ),
+            ]
+        );
+        assert_eq!(fault.reason(&[]), "No such profile");
+        assert_eq!(fault.reason(&[isolang::Language::Fra]), "Profil inexistant");
+        let detail = fault.detail().unwrap();
+        assert_eq!(detail.name, "Detail");
+        assert!(detail
+            .get_child(("Token", "http://www.example.org"))
+            .is_some());
+
+        let raw = r#"<soap:Fault xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/">
+            <faultcode>soap:Server</faultcode>
+            <faultstring>Out of memory</faultstring>
+        </soap:Fault>"#;
+        let fault = SoapFault::try_from(&Element::parse(raw.as_bytes()).unwrap()).unwrap();
+        assert!(matches!(fault.code(), SoapFaultCode::Receiver));
+        assert!(fault.sub_codes().is_empty());
+        assert_eq!(fault.reason(&[]), "Out of memory");
+
+        // The class survives a SOAP 1.1 round trip
+        let fault = SoapFault::builder(SoapFaultCode::Receiver)
+            .subcode(
+                Url::parse("http://www.onvif.org/ver10/error").unwrap(),
+                "Action",
+            )
+            .reason_en("Device busy")
+            .build()
+            .into_message(SoapVersion::Soap11, &[]);
+        let mut raw = vec![];
+        fault.envelope().write(&mut raw).unwrap();
+        let msg = SoapMessage::from(Element::parse(raw.as_slice()).unwrap());
+        let faultcode = msg
+            .get_body()
+            .get_child(("Fault", SOAP11_NAMESPACE))
+            .and_then(|f| f.get_child("faultcode"))
+            .and_then(|c| c.get_text())
+            .unwrap()
+            .to_string();
+        assert!(faultcode.ends_with(":Server.Action"), "{}", faultcode);
+        let fault = SoapFault::try_from(msg).unwrap();
+        assert!(matches!(fault.code(), SoapFaultCode::Receiver));
+        assert_eq!(
+            fault.sub_codes(),
+            &[(Url::parse(SOAP11_NAMESPACE).unwrap(), "Action".to_string())]
+        );
+        assert!(fault.detail().is_none());
+
+        assert!(SoapFault::try_from(SoapMessage::new()).is_err());
+        assert!(SoapFault::try_from(&Element::new("Fault")).is_err());
+    }
+
+    #[test]
+    fn test_core_fault() {
+        let fault = SoapFault::builder(SoapFaultCode::Sender)
+            .subcode(
+                Url::parse("http://www.onvif.org/ver10/error").unwrap(),
+                "InvalidArgVal",
+            )
+            .reason_en("Invalid argument")
+            .reasons(HashMap::from([(
+                isolang::Language::Fra,
+                "Argument invalide".to_string(),
+            )]))
+            .build();
+        let core = soap_core::Fault::from(&fault);
+        assert_eq!(core.code(), SoapFaultCode::Sender);
+        assert_eq!(core.reason(&["fr"]), "Argument invalide");
+        assert_eq!(
+            core.sub_codes()[0],
+            (
+                "http://www.onvif.org/ver10/error".into(),
+                "InvalidArgVal".into()
+            )
+        );
+
+        let core = core.with_detail(r#"<x:Token xmlns:x="http://www.example.org">main</x:Token>"#);
+        let fault = SoapFault::from(core);
+        assert_eq!(
+            fault.sub_codes(),
+            &[(
+                Url::parse("http://www.onvif.org/ver10/error").unwrap(),
+                "InvalidArgVal".to_string()
+            )]
+        );
+        assert_eq!(fault.reason(&[isolang::Language::Fra]), "Argument invalide");
+        assert_eq!(fault.reason(&[]), "Invalid argument");
+        assert!(fault
+            .detail()
+            .and_then(|d| d.get_child(("Token", "http://www.example.org")))
+            .is_some());
     }
 }
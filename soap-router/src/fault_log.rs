@@ -0,0 +1,154 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use crate::{
+    fault::{SoapFault, SoapFaultCode},
+    interceptor::QName,
+};
+
+/// Number of faults kept by default in a [`FaultLog`]
+pub const DEFAULT_RECENT_FAULTS: usize = 32;
+
+/// Fault sent by a router
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FaultRecord {
+    pub time: SystemTime,
+    /// `Sender`, `Receiver`...
+    pub code: String,
+    /// Local names of the subcodes, most generic first
+    pub subcodes: Vec<String>,
+    /// English reason
+    pub reason: String,
+    /// First element of the request body, when it could be parsed
+    pub operation: Option<QName>,
+}
+
+impl FaultRecord {
+    /// Code and subcodes joined with slashes, e.g. `Sender/InvalidArgVal`,
+    /// the faults are counted by key
+    pub fn key(&self) -> String {
+        join_key(&self.code, self.subcodes.iter().map(|s| s.as_str()))
+    }
+}
+
+/// [`FaultRecord::key`] of a fault
+pub(crate) fn fault_key(fault: &SoapFault) -> String {
+    join_key(
+        &fault.code().to_string(),
+        fault.sub_codes().iter().map(|(_, s)| s.as_str()),
+    )
+}
+
+fn join_key<'a>(code: &'a str, subcodes: impl Iterator<Item = &'a str>) -> String {
+    std::iter::once(code)
+        .chain(subcodes)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[derive(Debug)]
+struct Inner {
+    capacity: usize,
+    counts: BTreeMap<String, u64>,
+    recent: VecDeque<FaultRecord>,
+}
+
+/// Statistics of the faults sent by the routers sharing the log, along with
+/// the most recent ones. Meant to be exposed on a diagnostics endpoint, as
+/// devices in the field rarely have a debugger attached.
+#[derive(Clone, Debug)]
+pub struct FaultLog(Arc<Mutex<Inner>>);
+
+impl Default for FaultLog {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_RECENT_FAULTS)
+    }
+}
+
+impl FaultLog {
+    /// Log keeping the `capacity` most recent faults
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self(Arc::new(Mutex::new(Inner {
+            capacity,
+            counts: Default::default(),
+            recent: VecDeque::with_capacity(capacity),
+        })))
+    }
+
+    pub(crate) fn record(&self, fault: &SoapFault, operation: Option<QName>) {
+        let record = FaultRecord {
+            time: SystemTime::now(),
+            code: fault.code().to_string(),
+            subcodes: fault.sub_codes().iter().map(|(_, s)| s.clone()).collect(),
+            reason: fault.reason(&[]).to_string(),
+            operation,
+        };
+        let key = fault_key(fault);
+        tracing::Span::current().record("outcome", key.as_str());
+        let operation = record.operation.as_ref().map(|o| o.to_string());
+        match fault.code() {
+            SoapFaultCode::Receiver => {
+                tracing::warn!(fault = key, reason = record.reason, operation, "SOAP fault")
+            }
+            _ => tracing::debug!(fault = key, reason = record.reason, operation, "SOAP fault"),
+        }
+        let mut inner = self.0.lock().unwrap();
+        *inner.counts.entry(key).or_default() += 1;
+        if inner.capacity == 0 {
+            return;
+        }
+        if inner.recent.len() == inner.capacity {
+            inner.recent.pop_front();
+        }
+        inner.recent.push_back(record);
+    }
+
+    /// Number of faults sent for each [`FaultRecord::key`]
+    pub fn counts(&self) -> BTreeMap<String, u64> {
+        self.0.lock().unwrap().counts.clone()
+    }
+
+    /// Most recent faults, oldest first
+    pub fn recent(&self) -> Vec<FaultRecord> {
+        self.0.lock().unwrap().recent.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::onvif_fault::OnvifFault;
+
+    #[test]
+    fn test_fault_log() {
+        let log = FaultLog::with_capacity(2);
+        log.record(&SoapFault::sender("Bad request"), None);
+        log.record(
+            &OnvifFault::invalid_arg_val("NoProfile", "No such profile").into(),
+            Some(QName::new(
+                "http://www.onvif.org/ver10/media/wsdl",
+                "GetProfile",
+            )),
+        );
+        log.record(&SoapFault::sender("Bad request"), None);
+
+        assert_eq!(
+            log.counts(),
+            BTreeMap::from([
+                ("Sender".to_string(), 2),
+                ("Sender/InvalidArgVal/NoProfile".to_string(), 1)
+            ])
+        );
+        let recent = log.recent();
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].reason, "No such profile");
+        assert_eq!(
+            recent[0].operation.as_ref().unwrap().name,
+            "GetProfile".to_string()
+        );
+        assert_eq!(recent[1].subcodes, Vec::<String>::new());
+    }
+}
@@ -0,0 +1,167 @@
+use std::net::IpAddr;
+
+use axum::http::{header::HOST, HeaderMap};
+use url::Url;
+
+/// Peers whose `Forwarded` and `X-Forwarded-*` headers are honored when
+/// building the URLs handed to clients
+#[derive(Clone, Debug, Default)]
+pub enum TrustedProxies {
+    /// The headers are ignored, only `Host` is used
+    #[default]
+    None,
+    /// The headers are honored whatever the peer, only suitable when the
+    /// router is not reachable without going through a proxy
+    Any,
+    /// The headers are honored for requests coming from the given addresses
+    Addresses(Vec<IpAddr>),
+}
+
+impl TrustedProxies {
+    fn trusts(&self, peer: Option<IpAddr>) -> bool {
+        match self {
+            TrustedProxies::None => false,
+            TrustedProxies::Any => true,
+            TrustedProxies::Addresses(addresses) => peer.is_some_and(|p| addresses.contains(&p)),
+        }
+    }
+}
+
+/// Scheme and host the client used to reach the service, available in the
+/// request extensions to build XAddrs and other URLs given out to clients.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestOrigin {
+    pub scheme: String,
+    /// Host, along with the port when it isn't the default one
+    pub host: String,
+}
+
+impl RequestOrigin {
+    pub fn base_url(&self) -> Option<Url> {
+        Url::parse(&format!("{}://{}/", self.scheme, self.host)).ok()
+    }
+
+    /// Origin of a request, the headers set by a proxy are only used when
+    /// `peer` is trusted. The scheme defaults to `http`.
+    pub(crate) fn from_headers(
+        headers: &HeaderMap,
+        peer: Option<IpAddr>,
+        trusted: &TrustedProxies,
+    ) -> Option<Self> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|h| h.to_str().ok())
+                .and_then(|h| h.split(',').next())
+                .map(|h| h.trim().to_string())
+                .filter(|h| !h.is_empty())
+        };
+        let mut scheme = None;
+        let mut host = None;
+        if trusted.trusts(peer) {
+            // Only the first element, describing the request received by the
+            // outermost proxy, is considered
+            match header("forwarded") {
+                Some(forwarded) => {
+                    for pair in forwarded.split(';') {
+                        let Some((key, value)) = pair.split_once('=') else {
+                            continue;
+                        };
+                        let value = value.trim().trim_matches('"').to_string();
+                        match key.trim().to_ascii_lowercase().as_str() {
+                            "proto" => scheme = Some(value),
+                            "host" => host = Some(value),
+                            _ => (),
+                        }
+                    }
+                }
+                None => {
+                    scheme = header("x-forwarded-proto");
+                    host = header("x-forwarded-host");
+                    if let (Some(h), Some(port)) = (&host, header("x-forwarded-port")) {
+                        let has_port = match h.rfind(']') {
+                            Some(i) => h[i..].contains(':'),
+                            None => h.contains(':'),
+                        };
+                        if !has_port {
+                            host = Some(format!("{}:{}", h, port));
+                        }
+                    }
+                }
+            }
+        }
+        let scheme = scheme
+            .filter(|s| s.eq_ignore_ascii_case("http") || s.eq_ignore_ascii_case("https"))
+            .map(|s| s.to_ascii_lowercase())
+            .unwrap_or_else(|| "http".to_string());
+        let host = host.or_else(|| header(HOST.as_str()))?;
+        let origin = RequestOrigin { scheme, host };
+        // Discard hosts that wouldn't make a valid URL
+        origin.base_url().map(|_| origin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_origin() {
+        let proxy: IpAddr = [10, 0, 0, 1].into();
+        let trusted = TrustedProxies::Addresses(vec![proxy]);
+        let headers = |pairs: &[(&'static str, &str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(*name, value.parse().unwrap());
+            }
+            headers
+        };
+        let origin = |pairs: &[(&'static str, &str)], peer: IpAddr| {
+            RequestOrigin::from_headers(&headers(pairs), Some(peer), &trusted)
+                .map(|o| o.base_url().unwrap().to_string())
+        };
+
+        let forwarded = [
+            ("host", "10.0.0.2:8080"),
+            (
+                "forwarded",
+                r#"for=192.0.2.43;proto=https;host="camera.example.org", for=10.0.0.3"#,
+            ),
+        ];
+        assert_eq!(
+            origin(&forwarded, proxy).as_deref(),
+            Some("https://camera.example.org/")
+        );
+        assert_eq!(
+            origin(&forwarded, [192, 0, 2, 43].into()).as_deref(),
+            Some("http://10.0.0.2:8080/")
+        );
+        assert_eq!(
+            origin(
+                &[
+                    ("host", "10.0.0.2:8080"),
+                    ("x-forwarded-proto", "https"),
+                    ("x-forwarded-host", "camera.example.org, proxy.local"),
+                    ("x-forwarded-port", "8443"),
+                ],
+                proxy
+            )
+            .as_deref(),
+            Some("https://camera.example.org:8443/")
+        );
+        assert_eq!(
+            origin(
+                &[("host", "10.0.0.2"), ("x-forwarded-proto", "gopher")],
+                proxy
+            )
+            .as_deref(),
+            Some("http://10.0.0.2/")
+        );
+        assert_eq!(origin(&[("host", "bad host")], proxy), None);
+        assert_eq!(origin(&[], proxy), None);
+        assert!(
+            RequestOrigin::from_headers(&headers(&forwarded), None, &TrustedProxies::Any)
+                .is_some_and(|o| o.scheme == "https")
+        );
+    }
+}
@@ -0,0 +1,396 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use isolang::Language;
+
+/// Parse an HTTP `Accept-Language` header value into a list of languages,
+/// most preferred first. Wildcards and tags that don't map to a known
+/// language are ignored.
+pub fn parse_accept_language(header: &str) -> Vec<Language> {
+    let mut weighted: Vec<(Language, u16)> = vec![];
+    for item in header.split(',') {
+        let mut parts = item.split(';');
+        let tag = parts.next().unwrap_or_default().trim();
+        let quality = parts
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .filter_map(|q| q.trim().parse::<f32>().ok())
+            .next()
+            .unwrap_or(1.0);
+        if quality <= 0.0 {
+            continue;
+        }
+        let primary = tag.split(['-', '_']).next().unwrap_or_default();
+        let lang = match primary.len() {
+            2 => Language::from_639_1(&primary.to_ascii_lowercase()),
+            3 => Language::from_639_3(&primary.to_ascii_lowercase()),
+            _ => None,
+        };
+        if let Some(lang) = lang {
+            if !weighted.iter().any(|(l, _)| *l == lang) {
+                weighted.push((lang, (quality.min(1.0) * 1000.0) as u16));
+            }
+        }
+    }
+    // Stable sort keeps header order for equal weights
+    weighted.sort_by_key(|(_, q)| std::cmp::Reverse(*q));
+    weighted.into_iter().map(|(l, _)| l).collect()
+}
+
+/// Select the text matching the first available preferred language, falling
+/// back to English, then to any available text.
+pub fn select<'a>(
+    texts: &'a HashMap<Language, String>,
+    preferred: &[Language],
+) -> Option<(Language, &'a str)> {
+    preferred
+        .iter()
+        .chain(std::iter::once(&Language::Eng))
+        .find_map(|l| texts.get(l).map(|t| (*l, t.as_str())))
+        .or_else(|| texts.iter().next().map(|(l, t)| (*l, t.as_str())))
+}
+
+/// Registry of localized strings, indexed by an application defined key.
+#[derive(Clone, Debug, Default)]
+pub struct Translations {
+    strings: HashMap<String, HashMap<Language, String>>,
+}
+
+impl Translations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(mut self, key: &str, language: Language, text: &str) -> Self {
+        self.insert(key, language, text);
+        self
+    }
+
+    pub fn insert(&mut self, key: &str, language: Language, text: &str) {
+        self.strings
+            .entry(key.to_string())
+            .or_default()
+            .insert(language, text.to_string());
+    }
+
+    /// Get all the known translations for a key, suitable as a fault reason
+    pub fn texts(&self, key: &str) -> Option<&HashMap<Language, String>> {
+        self.strings.get(key)
+    }
+
+    pub fn get(&self, key: &str, preferred: &[Language]) -> Option<&str> {
+        self.texts(key)
+            .and_then(|t| select(t, preferred))
+            .map(|(_, t)| t)
+    }
+}
+
+const BUILTIN_REASONS: &[(&str, &[(Language, &str)])] = &[
+    (
+        "ActionNotSupported",
+        &[
+            (Language::Eng, "Optional Action Not Implemented"),
+            (Language::Fra, "Action optionnelle non implémentée"),
+            (Language::Deu, "Optionale Aktion nicht implementiert"),
+            (Language::Zho, "可选操作未实现"),
+        ],
+    ),
+    (
+        "InvalidBody",
+        &[
+            (Language::Eng, "Invalid message body"),
+            (Language::Fra, "Corps du message invalide"),
+            (Language::Deu, "Ungültiger Nachrichteninhalt"),
+            (Language::Zho, "消息体无效"),
+        ],
+    ),
+    (
+        "SerializationFailed",
+        &[
+            (Language::Eng, "Unable to serialize the response"),
+            (Language::Fra, "Impossible de sérialiser la réponse"),
+            (Language::Deu, "Antwort kann nicht serialisiert werden"),
+            (Language::Zho, "无法序列化响应"),
+        ],
+    ),
+    (
+        "ProcedureNotPresent",
+        &[
+            (Language::Eng, "Procedure not present"),
+            (Language::Fra, "Procédure inexistante"),
+            (Language::Deu, "Prozedur nicht vorhanden"),
+            (Language::Zho, "过程不存在"),
+        ],
+    ),
+    (
+        "MissingHeader",
+        &[
+            (Language::Eng, "Missing required header"),
+            (Language::Fra, "En-tête requis manquant"),
+            (Language::Deu, "Erforderlicher Header fehlt"),
+            (Language::Zho, "缺少必需的消息头"),
+        ],
+    ),
+    (
+        "MalformedMessage",
+        &[
+            (Language::Eng, "Malformed SOAP message"),
+            (Language::Fra, "Message SOAP mal formé"),
+            (Language::Deu, "Fehlerhafte SOAP-Nachricht"),
+            (Language::Zho, "SOAP 消息格式错误"),
+        ],
+    ),
+    (
+        "UnsupportedMediaType",
+        &[
+            (Language::Eng, "Unsupported media type"),
+            (Language::Fra, "Type de média non pris en charge"),
+            (Language::Deu, "Nicht unterstützter Medientyp"),
+            (Language::Zho, "不支持的媒体类型"),
+        ],
+    ),
+    (
+        "VersionMismatch",
+        &[
+            (Language::Eng, "Unsupported SOAP envelope version"),
+            (
+                Language::Fra,
+                "Version d'enveloppe SOAP non prise en charge",
+            ),
+            (Language::Deu, "Nicht unterstützte SOAP-Umschlagversion"),
+            (Language::Zho, "不支持的 SOAP 信封版本"),
+        ],
+    ),
+    (
+        "MustUnderstand",
+        &[
+            (Language::Eng, "Mandatory header block not understood"),
+            (Language::Fra, "Bloc d'en-tête obligatoire non compris"),
+            (
+                Language::Deu,
+                "Verpflichtender Header-Block nicht verstanden",
+            ),
+            (Language::Zho, "无法理解必需的消息头块"),
+        ],
+    ),
+    (
+        "InternalError",
+        &[
+            (Language::Eng, "Internal error"),
+            (Language::Fra, "Erreur interne"),
+            (Language::Deu, "Interner Fehler"),
+            (Language::Zho, "内部错误"),
+        ],
+    ),
+    (
+        "NotAuthorized",
+        &[
+            (Language::Eng, "Sender not authorized"),
+            (Language::Fra, "Émetteur non autorisé"),
+            (Language::Deu, "Absender nicht autorisiert"),
+            (Language::Zho, "发送方未经授权"),
+        ],
+    ),
+    (
+        "InvalidAddressingHeader",
+        &[
+            (Language::Eng, "Invalid addressing header"),
+            (Language::Fra, "En-tête d'adressage invalide"),
+            (Language::Deu, "Ungültiger Adressierungs-Header"),
+            (Language::Zho, "寻址消息头无效"),
+        ],
+    ),
+    (
+        "LimitExceeded",
+        &[
+            (Language::Eng, "Message exceeds the size or nesting limits"),
+            (
+                Language::Fra,
+                "Le message dépasse les limites de taille ou d'imbrication",
+            ),
+            (
+                Language::Deu,
+                "Nachricht überschreitet die Größen- oder Verschachtelungsgrenzen",
+            ),
+            (Language::Zho, "消息超出大小或嵌套限制"),
+        ],
+    ),
+    (
+        "TagMismatch",
+        &[
+            (Language::Eng, "Tag mismatch"),
+            (Language::Fra, "Balises non concordantes"),
+            (Language::Deu, "Tags stimmen nicht überein"),
+            (Language::Zho, "标签不匹配"),
+        ],
+    ),
+    (
+        "UnknownTag",
+        &[
+            (Language::Eng, "Unknown tag"),
+            (Language::Fra, "Balise inconnue"),
+            (Language::Deu, "Unbekanntes Tag"),
+            (Language::Zho, "未知标签"),
+        ],
+    ),
+    (
+        "UnknownNamespace",
+        &[
+            (Language::Eng, "Namespace error"),
+            (Language::Fra, "Erreur d'espace de noms"),
+            (Language::Deu, "Namensraumfehler"),
+            (Language::Zho, "命名空间错误"),
+        ],
+    ),
+    (
+        "MissingAttribute",
+        &[
+            (Language::Eng, "Required attribute not present"),
+            (Language::Fra, "Attribut requis manquant"),
+            (Language::Deu, "Erforderliches Attribut fehlt"),
+            (Language::Zho, "缺少必需的属性"),
+        ],
+    ),
+    (
+        "ProhibitedAttribute",
+        &[
+            (Language::Eng, "Prohibited attribute"),
+            (Language::Fra, "Attribut interdit"),
+            (Language::Deu, "Unzulässiges Attribut"),
+            (Language::Zho, "禁止的属性"),
+        ],
+    ),
+    (
+        "InvalidArgs",
+        &[
+            (Language::Eng, "Invalid arguments"),
+            (Language::Fra, "Arguments invalides"),
+            (Language::Deu, "Ungültige Argumente"),
+            (Language::Zho, "参数无效"),
+        ],
+    ),
+    (
+        "InvalidArgVal",
+        &[
+            (Language::Eng, "Argument value invalid"),
+            (Language::Fra, "Valeur d'argument invalide"),
+            (Language::Deu, "Ungültiger Argumentwert"),
+            (Language::Zho, "参数值无效"),
+        ],
+    ),
+    (
+        "UnknownAction",
+        &[
+            (Language::Eng, "Unknown action"),
+            (Language::Fra, "Action inconnue"),
+            (Language::Deu, "Unbekannte Aktion"),
+            (Language::Zho, "未知操作"),
+        ],
+    ),
+    (
+        "OperationProhibited",
+        &[
+            (Language::Eng, "Operation not permitted"),
+            (Language::Fra, "Opération non autorisée"),
+            (Language::Deu, "Vorgang nicht zulässig"),
+            (Language::Zho, "不允许的操作"),
+        ],
+    ),
+    (
+        "ActionFailed",
+        &[
+            (Language::Eng, "Action failed"),
+            (Language::Fra, "Échec de l'action"),
+            (Language::Deu, "Aktion fehlgeschlagen"),
+            (Language::Zho, "操作失败"),
+        ],
+    ),
+    (
+        "OperationTimedOut",
+        &[
+            (Language::Eng, "Operation timed out"),
+            (Language::Fra, "Délai de l'opération dépassé"),
+            (Language::Deu, "Zeitüberschreitung der Operation"),
+            (Language::Zho, "操作超时"),
+        ],
+    ),
+    (
+        "OutOfMemory",
+        &[
+            (Language::Eng, "Out of memory"),
+            (Language::Fra, "Mémoire insuffisante"),
+            (Language::Deu, "Nicht genügend Speicher"),
+            (Language::Zho, "内存不足"),
+        ],
+    ),
+    (
+        "CriticalError",
+        &[
+            (Language::Eng, "Critical error"),
+            (Language::Fra, "Erreur critique"),
+            (Language::Deu, "Kritischer Fehler"),
+            (Language::Zho, "严重错误"),
+        ],
+    ),
+];
+
+/// Translations for the reasons of the faults generated by this crate
+pub fn builtin() -> &'static Translations {
+    static BUILTIN: OnceLock<Translations> = OnceLock::new();
+    BUILTIN.get_or_init(|| {
+        let mut tr = Translations::new();
+        for (key, texts) in BUILTIN_REASONS {
+            for (lang, text) in texts.iter() {
+                tr.insert(key, *lang, text);
+            }
+        }
+        tr
+    })
+}
+
+pub(crate) fn builtin_reason(key: &str) -> HashMap<Language, String> {
+    builtin().texts(key).cloned().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_accept_language() {
+        assert_eq!(
+            parse_accept_language("fr-CH, fr;q=0.9, en;q=0.8, de;q=0.7, *;q=0.5"),
+            vec![Language::Fra, Language::Eng, Language::Deu]
+        );
+        assert_eq!(
+            parse_accept_language("de;q=0.2, zh-Hans, en;q=0"),
+            vec![Language::Zho, Language::Deu]
+        );
+        assert!(parse_accept_language("").is_empty());
+    }
+
+    #[test]
+    fn test_translations_fallback() {
+        let tr = Translations::new()
+            .add("hello", Language::Eng, "Hello")
+            .add("hello", Language::Fra, "Bonjour")
+            .add("bye", Language::Deu, "Tschüss");
+
+        assert_eq!(tr.get("hello", &[Language::Fra]), Some("Bonjour"));
+        assert_eq!(tr.get("hello", &[Language::Spa]), Some("Hello"));
+        assert_eq!(tr.get("hello", &[]), Some("Hello"));
+        assert_eq!(tr.get("bye", &[Language::Fra]), Some("Tschüss"));
+        assert_eq!(tr.get("missing", &[Language::Fra]), None);
+    }
+
+    #[test]
+    fn test_builtin_reasons() {
+        for (key, _) in BUILTIN_REASONS {
+            for lang in [Language::Eng, Language::Fra, Language::Deu, Language::Zho] {
+                assert!(builtin_reason(key).contains_key(&lang));
+            }
+        }
+        assert_eq!(
+            builtin().get("ActionNotSupported", &[Language::Deu]),
+            Some("Optionale Aktion nicht implementiert")
+        );
+    }
+}
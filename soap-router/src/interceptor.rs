@@ -0,0 +1,71 @@
+use serde::Serialize;
+
+use crate::{
+    fault::SoapFault,
+    router::{SoapMessage, SoapRequest},
+};
+
+/// Qualified name of a SOAP operation, i.e. the body element name and its
+/// namespace.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+pub struct QName {
+    pub namespace: String,
+    pub name: String,
+}
+
+impl QName {
+    pub fn new(namespace: &str, name: &str) -> Self {
+        Self {
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for QName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{{{}}}{}", self.namespace, self.name)
+    }
+}
+
+/// Hooks run around every dispatched operation.
+///
+/// `before` is called once the operation is matched and can reject it by
+/// returning a fault, in which case the handler isn't called. `after` sees the
+/// outcome of the operation, including faults returned by `before`.
+pub trait Interceptor: Send + Sync + 'static {
+    fn before(&self, _operation: &QName, _headers: &xmltree::Element) -> Result<(), SoapFault> {
+        Ok(())
+    }
+
+    fn after(&self, _operation: &QName, _result: &Result<SoapMessage, SoapFault>) {}
+}
+
+/// Processor of a given header block, registered with
+/// [`SoapRouter::add_header_processor`](crate::router::SoapRouter::add_header_processor).
+///
+/// It is called once per envelope with every matching block of the request,
+/// before the interceptors and the handlers of the operations, and can modify
+/// the request or reject it by returning a fault. The request body is the
+/// first operation of the envelope, the headers and extensions it leaves are
+/// shared by all the operations.
+pub trait HeaderProcessor: Send + Sync + 'static {
+    fn process(
+        &self,
+        header: &xmltree::Element,
+        request: &mut SoapRequest,
+    ) -> Result<(), SoapFault>;
+}
+
+impl<F> HeaderProcessor for F
+where
+    F: Fn(&xmltree::Element, &mut SoapRequest) -> Result<(), SoapFault> + Send + Sync + 'static,
+{
+    fn process(
+        &self,
+        header: &xmltree::Element,
+        request: &mut SoapRequest,
+    ) -> Result<(), SoapFault> {
+        self(header, request)
+    }
+}
@@ -1,5 +1,31 @@
+pub mod c14n;
+pub mod cancellation;
+pub mod codec;
+pub(crate) mod compression;
+pub mod describe;
+pub mod digest_auth;
+pub mod entropy;
+pub mod extract;
 pub mod fault;
+pub mod fault_log;
+pub mod forwarded;
+pub mod i18n;
+pub mod interceptor;
+#[cfg(feature = "metrics")]
+mod metrics;
+pub mod mtom;
+mod must_understand;
+pub mod onvif_fault;
+pub mod pretty;
+pub mod registry;
 pub mod router;
+pub mod soap_client;
+pub mod soap_security;
+pub mod stream;
+pub mod uri;
+pub mod version;
+pub mod ws_addressing;
+pub mod xml_dsig;
 
 pub fn add(left: usize, right: usize) -> usize {
     left + right
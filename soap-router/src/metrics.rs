@@ -0,0 +1,180 @@
+//! Per-operation metrics reported through the `metrics` facade, installing
+//! a recorder such as `metrics-exporter-prometheus` is up to the
+//! application:
+//!  - `soap_requests_total`, counter
+//!  - `soap_faults_total`, counter with an additional `fault` label holding
+//!    the code and subcodes, e.g. `Sender/InvalidArgVal/NoProfile`
+//!  - `soap_request_duration_seconds`, histogram
+//!  - `soap_requests_in_flight`, gauge
+//!
+//! All of them are labelled with the `namespace` and `operation` name.
+
+use std::future::Future;
+
+use metrics::{counter, gauge, histogram, Gauge, Label};
+use tokio::time::Instant;
+use xmltree::Element;
+
+use crate::{fault::SoapFault, fault_log::fault_key, router::SoapMessage};
+
+struct InFlight(Gauge);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.decrement(1.0);
+    }
+}
+
+/// Report the call of the operation whose request element is `body`
+pub(crate) fn instrument<F>(
+    body: &Element,
+    call: F,
+) -> impl Future<Output = Result<SoapMessage, SoapFault>>
+where
+    F: Future<Output = Result<SoapMessage, SoapFault>>,
+{
+    let labels = vec![
+        Label::new("namespace", body.namespace.clone().unwrap_or_default()),
+        Label::new("operation", body.name.clone()),
+    ];
+    counter!("soap_requests_total", labels.clone()).increment(1);
+    let duration = histogram!("soap_request_duration_seconds", labels.clone());
+    let in_flight = gauge!("soap_requests_in_flight", labels.clone());
+    in_flight.increment(1.0);
+    // Also decremented when the call is dropped on timeout or cancellation
+    let in_flight = InFlight(in_flight);
+    let start = Instant::now();
+    async move {
+        let result = call.await;
+        duration.record(start.elapsed());
+        if let Err(fault) = &result {
+            let mut labels = labels;
+            labels.push(Label::new("fault", fault_key(fault)));
+            counter!("soap_faults_total", labels).increment(1);
+        }
+        drop(in_flight);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use metrics::{
+        Counter, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder, SharedString, Unit,
+    };
+
+    use super::*;
+    use crate::onvif_fault::OnvifFault;
+
+    #[derive(Default)]
+    struct Samples(Mutex<Vec<f64>>);
+
+    impl HistogramFn for Samples {
+        fn record(&self, value: f64) {
+            self.0.lock().unwrap().push(value);
+        }
+    }
+
+    #[derive(Default)]
+    struct TestRecorder {
+        counters: Mutex<BTreeMap<String, Arc<AtomicU64>>>,
+        gauges: Mutex<BTreeMap<String, Arc<AtomicU64>>>,
+        histograms: Mutex<BTreeMap<String, Arc<Samples>>>,
+    }
+
+    fn key_name(key: &Key) -> String {
+        let labels: Vec<String> = key
+            .labels()
+            .map(|l| format!("{}={}", l.key(), l.value()))
+            .collect();
+        format!("{}{{{}}}", key.name(), labels.join(","))
+    }
+
+    impl Recorder for TestRecorder {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            let mut counters = self.counters.lock().unwrap();
+            Counter::from_arc(counters.entry(key_name(key)).or_default().clone())
+        }
+
+        fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+            let mut gauges = self.gauges.lock().unwrap();
+            Gauge::from_arc(gauges.entry(key_name(key)).or_default().clone())
+        }
+
+        fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+            let mut histograms = self.histograms.lock().unwrap();
+            Histogram::from_arc(histograms.entry(key_name(key)).or_default().clone())
+        }
+    }
+
+    #[test]
+    fn test_metrics() {
+        let recorder = TestRecorder::default();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        let mut body = Element::new("GetProfile");
+        body.namespace = Some("http://www.onvif.org/ver10/media/wsdl".to_string());
+        metrics::with_local_recorder(&recorder, || {
+            runtime.block_on(async {
+                let call = instrument(&body, async {
+                    tokio::time::sleep(Duration::from_millis(250)).await;
+                    Ok(SoapMessage::new())
+                });
+                let gauge = "soap_requests_in_flight{namespace=http://www.onvif.org/ver10/media/wsdl,operation=GetProfile}";
+                let in_flight = || {
+                    f64::from_bits(recorder.gauges.lock().unwrap()[gauge].load(Ordering::Relaxed))
+                };
+                assert_eq!(in_flight(), 1.0);
+                call.await.unwrap();
+                assert_eq!(in_flight(), 0.0);
+
+                let call = instrument(&body, async {
+                    Err(OnvifFault::invalid_arg_val("NoProfile", "No such profile").into())
+                });
+                assert!(call.await.is_err());
+                // Dropped before completion
+                drop(instrument(&body, std::future::pending()));
+                assert_eq!(in_flight(), 0.0);
+            })
+        });
+
+        let counters: BTreeMap<String, u64> = recorder
+            .counters
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (k.clone(), v.load(Ordering::Relaxed)))
+            .filter(|(_, v)| *v > 0)
+            .collect();
+        assert_eq!(
+            counters,
+            BTreeMap::from([
+                ("soap_faults_total{namespace=http://www.onvif.org/ver10/media/wsdl,operation=GetProfile,fault=Sender/InvalidArgVal/NoProfile}".to_string(), 1),
+                ("soap_requests_total{namespace=http://www.onvif.org/ver10/media/wsdl,operation=GetProfile}".to_string(), 3),
+            ])
+        );
+        let histograms = recorder.histograms.lock().unwrap();
+        let samples = histograms["soap_request_duration_seconds{namespace=http://www.onvif.org/ver10/media/wsdl,operation=GetProfile}"]
+            .0
+            .lock()
+            .unwrap()
+            .clone();
+        assert_eq!(samples, vec![0.25, 0.0]);
+    }
+}
@@ -0,0 +1,406 @@
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    body::{boxed, Body, Bytes, StreamBody},
+    http::header::CONTENT_TYPE,
+    response::Response,
+    BoxError,
+};
+use bytes::{Buf, BytesMut};
+use futures::{
+    stream::{self, BoxStream},
+    Stream, StreamExt, TryStreamExt,
+};
+use xmltree::Element;
+
+use crate::{
+    codec::invalid_message, extract::FromSoapRequest, fault::SoapFault, router::SoapRequest,
+    version::SoapVersion,
+};
+
+const ROOT_CONTENT_ID: &str = "<root.message@soap-router>";
+
+/// A binary part sent alongside the response envelope as an MTOM/XOP
+/// attachment without being buffered in memory.
+pub struct Attachment {
+    content_id: String,
+    content_type: String,
+    stream: BoxStream<'static, Result<Bytes, BoxError>>,
+}
+
+/// Extractor allowing handlers to stream attachments in their response.
+///
+/// [`ResponseAttachments::add`] returns the `xop:Include` element the
+/// handler must place where the binary data belongs in its response.
+#[derive(Clone, Default)]
+pub struct ResponseAttachments(Arc<Mutex<Vec<Attachment>>>);
+
+impl ResponseAttachments {
+    pub fn add<St, E>(&self, content_type: &str, stream: St) -> Element
+    where
+        St: Stream<Item = Result<Bytes, E>> + Send + 'static,
+        E: Into<BoxError> + 'static,
+    {
+        let mut attachments = self.0.lock().unwrap();
+        let content_id = format!("attachment{}@soap-router", attachments.len());
+        attachments.push(Attachment {
+            content_id: content_id.clone(),
+            content_type: content_type.to_string(),
+            stream: stream.map_err(Into::into).boxed(),
+        });
+
+        let mut include = Element::new("Include");
+        include.prefix = Some("xop".to_string());
+        include.namespace = Some("http://www.w3.org/2004/08/xop/include".to_string());
+        let mut namespaces = xmltree::Namespace::empty();
+        namespaces.put("xop", "http://www.w3.org/2004/08/xop/include");
+        include.namespaces = Some(namespaces);
+        include
+            .attributes
+            .insert("href".to_string(), format!("cid:{}", content_id));
+        include
+    }
+
+    pub(crate) fn take(&self) -> Vec<Attachment> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
+impl<S> FromSoapRequest<S> for ResponseAttachments {
+    fn from_soap_request(req: &SoapRequest, _state: &S) -> Result<Self, SoapFault> {
+        Ok(req.response_attachments.clone())
+    }
+}
+
+/// Build a streamed `multipart/related` response holding the envelope as the
+/// root part, followed by the attachments.
+pub(crate) fn multipart_response(
+    boundary: &str,
+    version: SoapVersion,
+    envelope: Vec<u8>,
+    attachments: Vec<Attachment>,
+) -> Response {
+    let root = format!(
+        "--{boundary}\r\n\
+         Content-Type: application/xop+xml; charset=UTF-8; type=\"{}\"\r\n\
+         Content-Transfer-Encoding: binary\r\n\
+         Content-ID: {ROOT_CONTENT_ID}\r\n\r\n",
+        version.content_type()
+    );
+    let root = stream::iter([Ok(Bytes::from(root)), Ok(Bytes::from(envelope))]);
+
+    let part_boundary = boundary.to_string();
+    let parts = stream::iter(attachments).flat_map(move |a| {
+        let boundary = &part_boundary;
+        let headers = format!(
+            "\r\n--{boundary}\r\n\
+             Content-Type: {}\r\n\
+             Content-Transfer-Encoding: binary\r\n\
+             Content-ID: <{}>\r\n\r\n",
+            a.content_type, a.content_id
+        );
+        stream::once(async move { Ok(Bytes::from(headers)) }).chain(a.stream)
+    });
+    let end = Bytes::from(format!("\r\n--{boundary}--\r\n"));
+    let end = stream::once(async { Ok(end) });
+
+    let body = StreamBody::new(root.chain(parts).chain(end));
+    Response::builder()
+        .header(
+            CONTENT_TYPE,
+            format!(
+                "multipart/related; type=\"application/xop+xml\"; \
+                 start=\"{ROOT_CONTENT_ID}\"; start-info=\"{}\"; \
+                 boundary=\"{boundary}\"",
+                version.content_type()
+            ),
+        )
+        .body(boxed(body))
+        .unwrap()
+}
+
+/// Largest accepted size of the headers of a part
+const MAX_PART_HEADERS: usize = 16 * 1024;
+
+/// Split a header value into its lowercased media type and its parameters,
+/// quoted values may hold `;`.
+pub(crate) fn media_type_parameters(value: &str) -> (String, Vec<(String, String)>) {
+    let (media_type, mut rest) = value.split_once(';').unwrap_or((value, ""));
+    let mut params = vec![];
+    while let Some((name, after)) = rest.split_once('=') {
+        let after = after.trim_start();
+        let (value, remaining) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                let remaining = quoted.get(end + 1..).unwrap_or_default();
+                (&quoted[..end], remaining)
+            }
+            None => after.split_once(';').map_or((after, ""), |(v, r)| (v, r)),
+        };
+        params.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        rest = remaining.split_once(';').map_or("", |(_, r)| r);
+    }
+    (media_type.trim().to_ascii_lowercase(), params)
+}
+
+/// Headers of a part of a `multipart/related` request
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AttachmentPart {
+    /// Content-ID without the angle brackets, as referenced by `cid:` URLs
+    pub content_id: String,
+    pub content_type: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ReaderState {
+    /// Before the first delimiter
+    Preamble,
+    /// Right after a delimiter, before the part headers
+    Delimiter,
+    InPart,
+    End,
+}
+
+/// Incremental reader of a `multipart/related` body, only the bytes needed
+/// to find the next delimiter are kept in memory.
+pub(crate) struct MultipartReader {
+    body: Body,
+    buf: BytesMut,
+    delimiter: Vec<u8>,
+    state: ReaderState,
+}
+
+impl MultipartReader {
+    pub(crate) fn new(body: Body, boundary: &str) -> Self {
+        Self {
+            body,
+            // Lets the first delimiter be found like the following ones
+            buf: BytesMut::from(&b"\r\n"[..]),
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            state: ReaderState::Preamble,
+        }
+    }
+
+    /// Read more of the body, false once it is over
+    async fn fill(&mut self) -> Result<bool, SoapFault> {
+        match self.body.next().await {
+            Some(Ok(chunk)) => {
+                self.buf.extend_from_slice(&chunk);
+                Ok(true)
+            }
+            Some(Err(_)) => Err(invalid_message("MalformedMessage")),
+            None => Ok(false),
+        }
+    }
+
+    async fn fill_or_fail(&mut self) -> Result<(), SoapFault> {
+        match self.fill().await? {
+            true => Ok(()),
+            false => Err(invalid_message("MalformedMessage")),
+        }
+    }
+
+    fn find(&self, needle: &[u8]) -> Option<usize> {
+        self.buf.windows(needle.len()).position(|w| w == needle)
+    }
+
+    /// Skip the rest of the current part, if any, and read the headers of
+    /// the next one.
+    pub(crate) async fn next_part(&mut self) -> Result<Option<AttachmentPart>, SoapFault> {
+        loop {
+            match self.state {
+                ReaderState::End => return Ok(None),
+                ReaderState::InPart => while self.chunk().await?.is_some() {},
+                ReaderState::Preamble => match self.find(&self.delimiter) {
+                    Some(i) => {
+                        self.buf.advance(i + self.delimiter.len());
+                        self.state = ReaderState::Delimiter;
+                    }
+                    None => {
+                        let keep = self.buf.len().min(self.delimiter.len() - 1);
+                        self.buf.advance(self.buf.len() - keep);
+                        self.fill_or_fail().await?;
+                    }
+                },
+                ReaderState::Delimiter => {
+                    while self.buf.len() < 2 {
+                        self.fill_or_fail().await?;
+                    }
+                    if self.buf.starts_with(b"--") {
+                        self.state = ReaderState::End;
+                        continue;
+                    }
+                    let end = loop {
+                        if let Some(end) = self.find(b"\r\n\r\n") {
+                            break end;
+                        }
+                        if self.buf.len() > MAX_PART_HEADERS {
+                            return Err(invalid_message("MalformedMessage"));
+                        }
+                        self.fill_or_fail().await?;
+                    };
+                    let headers = self.buf.split_to(end + 4);
+                    let headers = std::str::from_utf8(&headers)
+                        .map_err(|_| invalid_message("MalformedMessage"))?;
+                    let mut part = AttachmentPart::default();
+                    for line in headers.split("\r\n") {
+                        let Some((name, value)) = line.split_once(':') else {
+                            continue;
+                        };
+                        match name.trim().to_ascii_lowercase().as_str() {
+                            "content-id" => {
+                                part.content_id = value
+                                    .trim()
+                                    .trim_start_matches('<')
+                                    .trim_end_matches('>')
+                                    .to_string()
+                            }
+                            "content-type" => part.content_type = value.trim().to_string(),
+                            _ => (),
+                        }
+                    }
+                    self.state = ReaderState::InPart;
+                    return Ok(Some(part));
+                }
+            }
+        }
+    }
+
+    /// Next chunk of the current part, `None` at the end of the part
+    pub(crate) async fn chunk(&mut self) -> Result<Option<Bytes>, SoapFault> {
+        if self.state != ReaderState::InPart {
+            return Ok(None);
+        }
+        loop {
+            match self.find(&self.delimiter) {
+                Some(0) => {
+                    self.buf.advance(self.delimiter.len());
+                    self.state = ReaderState::Delimiter;
+                    return Ok(None);
+                }
+                Some(i) => return Ok(Some(self.buf.split_to(i).freeze())),
+                None => {
+                    // The end of the buffer may be the start of a delimiter
+                    let keep = self.delimiter.len() - 1;
+                    if self.buf.len() > keep {
+                        let len = self.buf.len() - keep;
+                        return Ok(Some(self.buf.split_to(len).freeze()));
+                    }
+                    self.fill_or_fail().await?;
+                }
+            }
+        }
+    }
+}
+
+/// Attachments of a `multipart/related` request, shared by the operations
+/// of the request until one of them extracts [`RequestAttachments`].
+#[derive(Clone)]
+pub(crate) struct IncomingAttachments(pub(crate) Arc<Mutex<Option<MultipartReader>>>);
+
+/// Extractor reading the parts following the envelope of an MTOM/XOP
+/// request as they arrive, the body is only read as fast as the handler
+/// consumes it. Parts must be read in order, calling
+/// [`RequestAttachments::next_part`] skips the rest of the current one.
+///
+/// Only one operation of a request gets the attachments, the others, like
+/// the handlers of non multipart requests, see no part.
+pub struct RequestAttachments(Option<MultipartReader>);
+
+impl RequestAttachments {
+    pub async fn next_part(&mut self) -> Result<Option<AttachmentPart>, SoapFault> {
+        match self.0.as_mut() {
+            Some(reader) => reader.next_part().await,
+            None => Ok(None),
+        }
+    }
+
+    /// Next chunk of the current part, `None` once it is over
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, SoapFault> {
+        match self.0.as_mut() {
+            Some(reader) => reader.chunk().await,
+            None => Ok(None),
+        }
+    }
+}
+
+impl<S> FromSoapRequest<S> for RequestAttachments {
+    fn from_soap_request(req: &SoapRequest, _state: &S) -> Result<Self, SoapFault> {
+        Ok(Self(
+            req.extensions
+                .get::<IncomingAttachments>()
+                .and_then(|a| a.0.lock().unwrap().take()),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunked(raw: &'static [u8], size: usize) -> Body {
+        Body::wrap_stream(stream::iter(
+            raw.chunks(size)
+                .map(|c| Ok::<_, std::io::Error>(Bytes::from_static(c))),
+        ))
+    }
+
+    #[test]
+    fn test_media_type_parameters() {
+        let (media_type, params) = media_type_parameters(
+            r#"Multipart/Related; boundary="b;1"; start-info="application/soap+xml; action=\"urn:a\""; type=application/xop+xml"#,
+        );
+        assert_eq!(media_type, "multipart/related");
+        assert_eq!(params[0], ("boundary".to_string(), "b;1".to_string()));
+        assert_eq!(
+            params[2],
+            ("type".to_string(), "application/xop+xml".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_multipart_reader() {
+        let raw: &[u8] = b"preamble\r\n--sep\r\n\
+            Content-Type: application/xop+xml\r\nContent-ID: <root>\r\n\r\n\
+            <env/>\r\n--sep\r\n\
+            Content-Type: application/octet-stream\r\nContent-ID: <data>\r\n\r\n\
+            0123456789\r\n--se\r\n--\r\n--sep\r\n\
+            Content-ID: <skipped>\r\n\r\n\
+            unread\r\n--sep--\r\n";
+        for size in [1, 3, 7, raw.len()] {
+            let mut reader = MultipartReader::new(chunked(raw, size), "sep");
+            let root = reader.next_part().await.unwrap().unwrap();
+            assert_eq!(root.content_id, "root");
+            assert_eq!(root.content_type, "application/xop+xml");
+            let mut body = vec![];
+            while let Some(chunk) = reader.chunk().await.unwrap() {
+                body.extend_from_slice(&chunk);
+            }
+            assert_eq!(body, b"<env/>");
+
+            let data = reader.next_part().await.unwrap().unwrap();
+            assert_eq!(data.content_id, "data");
+            let mut body = vec![];
+            while let Some(chunk) = reader.chunk().await.unwrap() {
+                body.extend_from_slice(&chunk);
+            }
+            assert_eq!(body, b"0123456789\r\n--se\r\n--");
+
+            let skipped = reader.next_part().await.unwrap().unwrap();
+            assert_eq!(skipped.content_id, "skipped");
+            assert_eq!(reader.next_part().await.unwrap(), None);
+        }
+
+        // The body ends before the closing delimiter
+        let mut reader = MultipartReader::new(chunked(b"--sep\r\n\r\ntruncated", 4), "sep");
+        reader.next_part().await.unwrap().unwrap();
+        let mut result = Ok(None);
+        for _ in 0..10 {
+            result = reader.chunk().await;
+            if result.is_err() {
+                break;
+            }
+        }
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashSet;
+
+use xmltree::{Element, XMLNode};
+
+use crate::{
+    fault::{SoapFault, SoapFaultCode},
+    interceptor::QName,
+    version::{SoapVersion, SOAP12_NAMESPACE},
+};
+
+const SOAP12_ROLE_NEXT: &str = "http://www.w3.org/2003/05/soap-envelope/role/next";
+const SOAP12_ROLE_ULTIMATE_RECEIVER: &str =
+    "http://www.w3.org/2003/05/soap-envelope/role/ultimateReceiver";
+const SOAP11_ACTOR_NEXT: &str = "http://schemas.xmlsoap.org/soap/actor/next";
+
+fn attribute<'a>(elem: &'a Element, name: &str) -> Option<&'a str> {
+    elem.attributes
+        .iter()
+        .find(|(k, _)| k.rsplit(':').next() == Some(name))
+        .map(|(_, v)| v.trim())
+}
+
+/// Header blocks targeted at this node that must be understood but are not
+/// part of the understood ones.
+pub(crate) fn not_understood(
+    headers: &Element,
+    version: SoapVersion,
+    understood: &HashSet<QName>,
+) -> Vec<QName> {
+    headers
+        .children
+        .iter()
+        .filter_map(|c| c.as_element())
+        .filter(|block| {
+            let mandatory = matches!(attribute(block, "mustUnderstand"), Some("true" | "1"));
+            let targeted = match version {
+                SoapVersion::Soap12 => matches!(
+                    attribute(block, "role"),
+                    None | Some(SOAP12_ROLE_NEXT | SOAP12_ROLE_ULTIMATE_RECEIVER)
+                ),
+                SoapVersion::Soap11 => {
+                    matches!(attribute(block, "actor"), None | Some(SOAP11_ACTOR_NEXT))
+                }
+            };
+            mandatory && targeted
+        })
+        .map(|block| QName {
+            namespace: block.namespace.clone().unwrap_or_default(),
+            name: block.name.clone(),
+        })
+        .filter(|qname| !understood.contains(qname))
+        .collect()
+}
+
+pub(crate) fn must_understand_fault() -> SoapFault {
+    SoapFault::builder(SoapFaultCode::MustUnderstand)
+        .builtin_reason("MustUnderstand")
+        .build()
+}
+
+/// `env:NotUnderstood` header block reporting the given header, only defined
+/// for SOAP 1.2.
+pub(crate) fn not_understood_header(qname: &QName) -> Element {
+    let mut elem = Element::new("NotUnderstood");
+    elem.prefix = Some("env".to_string());
+    elem.namespace = Some(SOAP12_NAMESPACE.to_string());
+    let mut namespaces = xmltree::Namespace::empty();
+    namespaces.put("nu", qname.namespace.as_str());
+    elem.namespaces = Some(namespaces);
+    elem.attributes
+        .insert("qname".to_string(), format!("nu:{}", qname.name));
+    elem
+}
+
+/// Add the `env:NotUnderstood` blocks to a MustUnderstand fault message
+pub(crate) fn report(headers: &mut Element, version: SoapVersion, qnames: &[QName]) {
+    if version != SoapVersion::Soap12 {
+        // SOAP 1.1 has no way to report the offending headers
+        return;
+    }
+    headers.children.extend(
+        qnames
+            .iter()
+            .map(|q| XMLNode::Element(not_understood_header(q))),
+    );
+}
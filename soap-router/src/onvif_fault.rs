@@ -0,0 +1,203 @@
+use url::Url;
+
+use crate::fault::{SoapFault, SoapFaultCode};
+
+pub const ONVIF_ERROR_NAMESPACE: &str = "http://www.onvif.org/ver10/error";
+
+/// Service specific subcode refining a generic ONVIF fault, along with the
+/// reason reported to the client
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpecificFault {
+    pub subcode: String,
+    pub reason: String,
+}
+
+/// Generic faults of the ONVIF core specification, the ones accepting a
+/// [`SpecificFault`] get the matching `ter:` subcode appended when it is set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OnvifFault {
+    /// `env:Sender/ter:WellFormed`
+    WellFormed,
+    /// `env:Sender/ter:TagMismatch`
+    TagMismatch,
+    /// `env:Sender/ter:Tag`
+    Tag,
+    /// `env:Sender/ter:Namespace`
+    Namespace,
+    /// `env:Sender/ter:MissingAttr`
+    MissingAttr,
+    /// `env:Sender/ter:ProhibAttr`
+    ProhibAttr,
+    /// `env:Sender/ter:InvalidArgs`
+    InvalidArgs(Option<SpecificFault>),
+    /// `env:Sender/ter:InvalidArgVal`
+    InvalidArgVal(Option<SpecificFault>),
+    /// `env:Sender/ter:UnknownAction`
+    UnknownAction,
+    /// `env:Sender/ter:OperationProhibited`
+    OperationProhibited(Option<SpecificFault>),
+    /// `env:Sender/ter:NotAuthorized`
+    NotAuthorized,
+    /// `env:Receiver/ter:ActionNotSupported`
+    ActionNotSupported(Option<SpecificFault>),
+    /// `env:Receiver/ter:Action`
+    Action(Option<SpecificFault>),
+    /// `env:Receiver/ter:OutofMemory`
+    OutOfMemory,
+    /// `env:Receiver/ter:CriticalError`
+    CriticalError,
+}
+
+fn specific(subcode: &str, reason: &str) -> Option<SpecificFault> {
+    Some(SpecificFault {
+        subcode: subcode.to_string(),
+        reason: reason.to_string(),
+    })
+}
+
+impl OnvifFault {
+    pub fn invalid_args(subcode: &str, reason: &str) -> Self {
+        OnvifFault::InvalidArgs(specific(subcode, reason))
+    }
+
+    pub fn invalid_arg_val(subcode: &str, reason: &str) -> Self {
+        OnvifFault::InvalidArgVal(specific(subcode, reason))
+    }
+
+    pub fn operation_prohibited(subcode: &str, reason: &str) -> Self {
+        OnvifFault::OperationProhibited(specific(subcode, reason))
+    }
+
+    pub fn action_not_supported(subcode: &str, reason: &str) -> Self {
+        OnvifFault::ActionNotSupported(specific(subcode, reason))
+    }
+
+    pub fn action(subcode: &str, reason: &str) -> Self {
+        OnvifFault::Action(specific(subcode, reason))
+    }
+
+    pub fn code(&self) -> SoapFaultCode {
+        match self {
+            OnvifFault::ActionNotSupported(_)
+            | OnvifFault::Action(_)
+            | OnvifFault::OutOfMemory
+            | OnvifFault::CriticalError => SoapFaultCode::Receiver,
+            _ => SoapFaultCode::Sender,
+        }
+    }
+
+    /// Name of the generic `ter:` subcode
+    pub fn subcode(&self) -> &'static str {
+        match self {
+            OnvifFault::WellFormed => "WellFormed",
+            OnvifFault::TagMismatch => "TagMismatch",
+            OnvifFault::Tag => "Tag",
+            OnvifFault::Namespace => "Namespace",
+            OnvifFault::MissingAttr => "MissingAttr",
+            OnvifFault::ProhibAttr => "ProhibAttr",
+            OnvifFault::InvalidArgs(_) => "InvalidArgs",
+            OnvifFault::InvalidArgVal(_) => "InvalidArgVal",
+            OnvifFault::UnknownAction => "UnknownAction",
+            OnvifFault::OperationProhibited(_) => "OperationProhibited",
+            OnvifFault::NotAuthorized => "NotAuthorized",
+            OnvifFault::ActionNotSupported(_) => "ActionNotSupported",
+            OnvifFault::Action(_) => "Action",
+            OnvifFault::OutOfMemory => "OutofMemory",
+            OnvifFault::CriticalError => "CriticalError",
+        }
+    }
+
+    fn reason_key(&self) -> &'static str {
+        match self {
+            OnvifFault::WellFormed => "MalformedMessage",
+            OnvifFault::Tag => "UnknownTag",
+            OnvifFault::Namespace => "UnknownNamespace",
+            OnvifFault::MissingAttr => "MissingAttribute",
+            OnvifFault::ProhibAttr => "ProhibitedAttribute",
+            OnvifFault::Action(_) => "ActionFailed",
+            OnvifFault::OutOfMemory => "OutOfMemory",
+            other => other.subcode(),
+        }
+    }
+
+    fn specific(&self) -> Option<&SpecificFault> {
+        match self {
+            OnvifFault::InvalidArgs(s)
+            | OnvifFault::InvalidArgVal(s)
+            | OnvifFault::OperationProhibited(s)
+            | OnvifFault::ActionNotSupported(s)
+            | OnvifFault::Action(s) => s.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl From<OnvifFault> for SoapFault {
+    fn from(fault: OnvifFault) -> Self {
+        let ns = Url::parse(ONVIF_ERROR_NAMESPACE).unwrap();
+        let builder = SoapFault::builder(fault.code()).subcode(ns.clone(), fault.subcode());
+        match fault.specific() {
+            Some(specific) => builder
+                .subcode(ns, &specific.subcode)
+                .reason_en(&specific.reason)
+                .build(),
+            None => builder.builtin_reason(fault.reason_key()).build(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use isolang::Language;
+
+    #[test]
+    fn test_onvif_fault() {
+        let fault: SoapFault =
+            OnvifFault::invalid_arg_val("NoProfile", "profile token not found").into();
+        assert!(matches!(fault.code(), SoapFaultCode::Sender));
+        let sub_codes: Vec<_> = fault
+            .sub_codes()
+            .iter()
+            .map(|(ns, c)| (ns.as_str(), c.as_str()))
+            .collect();
+        assert_eq!(
+            sub_codes,
+            vec![
+                (ONVIF_ERROR_NAMESPACE, "InvalidArgVal"),
+                (ONVIF_ERROR_NAMESPACE, "NoProfile")
+            ]
+        );
+        assert_eq!(fault.reason(&[Language::Fra]), "profile token not found");
+
+        let fault: SoapFault = OnvifFault::ActionNotSupported(None).into();
+        assert!(matches!(fault.code(), SoapFaultCode::Receiver));
+        assert_eq!(fault.sub_codes().len(), 1);
+        assert_eq!(
+            fault.reason(&[Language::Fra]),
+            "Action optionnelle non implémentée"
+        );
+
+        // Every generic fault has a builtin reason
+        for fault in [
+            OnvifFault::WellFormed,
+            OnvifFault::TagMismatch,
+            OnvifFault::Tag,
+            OnvifFault::Namespace,
+            OnvifFault::MissingAttr,
+            OnvifFault::ProhibAttr,
+            OnvifFault::InvalidArgs(None),
+            OnvifFault::InvalidArgVal(None),
+            OnvifFault::UnknownAction,
+            OnvifFault::OperationProhibited(None),
+            OnvifFault::NotAuthorized,
+            OnvifFault::Action(None),
+            OnvifFault::OutOfMemory,
+            OnvifFault::CriticalError,
+        ] {
+            let subcode = fault.subcode();
+            let fault: SoapFault = fault.into();
+            assert_eq!(fault.sub_codes()[0].1, subcode);
+        }
+    }
+}
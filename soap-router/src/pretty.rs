@@ -0,0 +1,302 @@
+use std::collections::{BTreeMap, HashMap};
+
+use xml::{
+    attribute::OwnedAttribute,
+    name::OwnedName,
+    namespace::Namespace,
+    reader::{EventReader, ParserConfig, XmlEvent},
+    writer::{EmitterConfig, XmlEvent as WriterEvent},
+};
+
+use crate::version::{SOAP11_NAMESPACE, SOAP12_NAMESPACE};
+
+/// Prefixes used by [`pretty_print`] for the namespaces commonly found in
+/// ONVIF traffic, other namespaces get `ns0`, `ns1`...
+pub const WELL_KNOWN_PREFIXES: &[(&str, &str)] = &[
+    ("env", SOAP12_NAMESPACE),
+    ("soap", SOAP11_NAMESPACE),
+    ("xs", "http://www.w3.org/2001/XMLSchema"),
+    ("xsi", "http://www.w3.org/2001/XMLSchema-instance"),
+    ("xop", "http://www.w3.org/2004/08/xop/include"),
+    ("xmime", "http://www.w3.org/2005/05/xmlmime"),
+    ("wsa", "http://www.w3.org/2005/08/addressing"),
+    (
+        "wsse",
+        "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-secext-1.0.xsd",
+    ),
+    (
+        "wsu",
+        "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd",
+    ),
+    ("wsnt", "http://docs.oasis-open.org/wsn/b-2"),
+    ("wstop", "http://docs.oasis-open.org/wsn/t-1"),
+    ("d", "http://schemas.xmlsoap.org/ws/2005/04/discovery"),
+    ("tt", "http://www.onvif.org/ver10/schema"),
+    ("ter", "http://www.onvif.org/ver10/error"),
+    ("tns1", "http://www.onvif.org/ver10/topics"),
+    ("dn", "http://www.onvif.org/ver10/network/wsdl"),
+    ("tds", "http://www.onvif.org/ver10/device/wsdl"),
+    ("trt", "http://www.onvif.org/ver10/media/wsdl"),
+    ("tr2", "http://www.onvif.org/ver20/media/wsdl"),
+    ("tev", "http://www.onvif.org/ver10/events/wsdl"),
+    ("tptz", "http://www.onvif.org/ver20/ptz/wsdl"),
+    ("timg", "http://www.onvif.org/ver20/imaging/wsdl"),
+    ("tan", "http://www.onvif.org/ver20/analytics/wsdl"),
+];
+
+const XSI_NAMESPACE: &str = "http://www.w3.org/2001/XMLSchema-instance";
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// Error returned by [`pretty_print`] when the input is not well formed
+#[derive(Debug)]
+pub struct InvalidDocument(pub(crate) String);
+
+impl std::fmt::Display for InvalidDocument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid XML document: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidDocument {}
+
+/// Whether the text of an element, or the value of an attribute, holds
+/// QNames whose prefixes must follow the renaming.
+fn holds_qnames(element: &OwnedName) -> bool {
+    matches!(
+        (element.namespace.as_deref(), element.local_name.as_str()),
+        (Some(SOAP12_NAMESPACE), "Value")
+            | (_, "faultcode")
+            | (
+                Some("http://www.w3.org/2005/08/addressing"),
+                "ProblemHeaderQName"
+            )
+            | (Some("http://docs.oasis-open.org/wsn/b-2"), "Topic")
+    )
+}
+
+fn attribute_holds_qnames(attribute: &OwnedName) -> bool {
+    matches!(
+        (
+            attribute.namespace.as_deref(),
+            attribute.local_name.as_str()
+        ),
+        (Some(XSI_NAMESPACE), "type") | (None, "qname")
+    )
+}
+
+/// Namespaces of the `prefix:name` parts of a QName or of a topic path
+fn qname_namespaces<'a>(value: &'a str, scope: &'a Namespace) -> Vec<&'a str> {
+    value
+        .split(['/', '|', ' '])
+        .filter_map(|part| part.trim().split_once(':'))
+        .filter_map(|(prefix, _)| scope.get(prefix))
+        .collect()
+}
+
+fn rename_qnames(value: &str, scope: &Namespace, prefixes: &HashMap<String, String>) -> String {
+    let mut renamed = String::new();
+    let mut part = String::new();
+    let flush = |part: &mut String, renamed: &mut String| {
+        match part.split_once(':') {
+            Some((prefix, name)) => match scope.get(prefix).and_then(|ns| prefixes.get(ns)) {
+                Some(new) => renamed.push_str(&format!("{}:{}", new, name)),
+                None => renamed.push_str(part),
+            },
+            None => renamed.push_str(part),
+        }
+        part.clear();
+    };
+    for c in value.chars() {
+        match c {
+            '/' | '|' | ' ' => {
+                flush(&mut part, &mut renamed);
+                renamed.push(c);
+            }
+            c => part.push(c),
+        }
+    }
+    flush(&mut part, &mut renamed);
+    renamed
+}
+
+/// Indent an XML document and rename its namespace prefixes after the
+/// [`WELL_KNOWN_PREFIXES`], all of them being declared on the root element.
+/// Meant for reading captured traffic and writing golden files.
+pub fn pretty_print(doc: &[u8]) -> Result<String, InvalidDocument> {
+    let reader = EventReader::new_with_config(
+        doc,
+        ParserConfig::new()
+            .trim_whitespace(true)
+            .ignore_comments(false),
+    );
+    let mut events = vec![];
+    let mut used: Vec<String> = vec![];
+    let mut use_namespace = |ns: &str| {
+        if ns != XML_NAMESPACE && !used.iter().any(|u| u == ns) {
+            used.push(ns.to_string());
+        }
+    };
+    let mut scopes: Vec<(OwnedName, Namespace)> = vec![];
+    for event in reader {
+        let event = event.map_err(|e| InvalidDocument(e.to_string()))?;
+        match &event {
+            XmlEvent::StartElement {
+                name,
+                attributes,
+                namespace,
+            } => {
+                name.namespace
+                    .as_deref()
+                    .into_iter()
+                    .for_each(&mut use_namespace);
+                for attribute in attributes {
+                    attribute
+                        .name
+                        .namespace
+                        .as_deref()
+                        .into_iter()
+                        .for_each(&mut use_namespace);
+                    if attribute_holds_qnames(&attribute.name) {
+                        qname_namespaces(&attribute.value, namespace)
+                            .into_iter()
+                            .for_each(&mut use_namespace);
+                    }
+                }
+                scopes.push((name.clone(), namespace.clone()));
+            }
+            XmlEvent::EndElement { .. } => {
+                scopes.pop();
+            }
+            XmlEvent::Characters(text) => {
+                if let Some((_, scope)) = scopes.last().filter(|(n, _)| holds_qnames(n)) {
+                    qname_namespaces(text, scope)
+                        .into_iter()
+                        .for_each(&mut use_namespace);
+                }
+            }
+            _ => (),
+        }
+        events.push(event);
+    }
+
+    let well_known: HashMap<&str, &str> = WELL_KNOWN_PREFIXES
+        .iter()
+        .map(|(prefix, ns)| (*ns, *prefix))
+        .collect();
+    let mut next = 0;
+    let prefixes: HashMap<String, String> = used
+        .into_iter()
+        .map(|ns| {
+            let prefix = match well_known.get(ns.as_str()) {
+                Some(prefix) => prefix.to_string(),
+                None => {
+                    next += 1;
+                    format!("ns{}", next - 1)
+                }
+            };
+            (ns, prefix)
+        })
+        .collect();
+    let rename = |name: &OwnedName| OwnedName {
+        local_name: name.local_name.clone(),
+        namespace: name.namespace.clone(),
+        prefix: match name.namespace.as_deref() {
+            Some(XML_NAMESPACE) => Some("xml".to_string()),
+            Some(ns) => prefixes.get(ns).cloned(),
+            None => None,
+        },
+    };
+
+    let mut out = vec![];
+    let mut writer = EmitterConfig::new()
+        .perform_indent(true)
+        .write_document_declaration(false)
+        .create_writer(&mut out);
+    let mut root = true;
+    let mut scopes: Vec<(OwnedName, Namespace)> = vec![];
+    for event in events {
+        let result = match event {
+            XmlEvent::StartElement {
+                name,
+                attributes,
+                namespace,
+            } => {
+                let renamed = rename(&name);
+                let attributes: Vec<OwnedAttribute> = attributes
+                    .into_iter()
+                    .map(|a| OwnedAttribute {
+                        value: match attribute_holds_qnames(&a.name) {
+                            true => rename_qnames(&a.value, &namespace, &prefixes),
+                            false => a.value,
+                        },
+                        name: rename(&a.name),
+                    })
+                    .collect();
+                let mut declared = Namespace::empty();
+                if root {
+                    // Sorted for a stable output
+                    let sorted: BTreeMap<&String, &String> =
+                        prefixes.iter().map(|(ns, p)| (p, ns)).collect();
+                    for (prefix, ns) in sorted {
+                        declared.put(prefix.as_str(), ns.as_str());
+                    }
+                    root = false;
+                }
+                scopes.push((name, namespace));
+                let mut event = WriterEvent::start_element(renamed.borrow());
+                for attribute in &attributes {
+                    event = event.attr(attribute.name.borrow(), &attribute.value);
+                }
+                for (prefix, ns) in &declared {
+                    event = event.ns(prefix, ns);
+                }
+                writer.write(event)
+            }
+            XmlEvent::EndElement { .. } => {
+                scopes.pop();
+                writer.write(WriterEvent::end_element())
+            }
+            XmlEvent::Characters(text) => match scopes.last().filter(|(n, _)| holds_qnames(n)) {
+                Some((_, scope)) => writer.write(WriterEvent::characters(&rename_qnames(
+                    &text, scope, &prefixes,
+                ))),
+                None => writer.write(WriterEvent::characters(&text)),
+            },
+            XmlEvent::CData(text) => writer.write(WriterEvent::cdata(&text)),
+            XmlEvent::Comment(text) => writer.write(WriterEvent::comment(&text)),
+            _ => Ok(()),
+        };
+        result.map_err(|e| InvalidDocument(e.to_string()))?;
+    }
+    String::from_utf8(out).map_err(|e| InvalidDocument(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pretty_print() {
+        let raw = r#"<?xml version="1.0"?><SOAP-ENV:Envelope xmlns:SOAP-ENV="http://www.w3.org/2003/05/soap-envelope" xmlns:a="http://www.onvif.org/ver10/error"><SOAP-ENV:Body><SOAP-ENV:Fault><SOAP-ENV:Code><SOAP-ENV:Value>SOAP-ENV:Sender</SOAP-ENV:Value><SOAP-ENV:Subcode><SOAP-ENV:Value>a:InvalidArgVal</SOAP-ENV:Value></SOAP-ENV:Subcode></SOAP-ENV:Code><SOAP-ENV:Reason><SOAP-ENV:Text xml:lang="en">No profile</SOAP-ENV:Text></SOAP-ENV:Reason><SOAP-ENV:Detail><Token xmlns="urn:example" SOAP-ENV:mustUnderstand="true">main</Token></SOAP-ENV:Detail></SOAP-ENV:Fault></SOAP-ENV:Body></SOAP-ENV:Envelope>"#;
+        let expected = r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:ns0="urn:example" xmlns:ter="http://www.onvif.org/ver10/error">
+  <env:Body>
+    <env:Fault>
+      <env:Code>
+        <env:Value>env:Sender</env:Value>
+        <env:Subcode>
+          <env:Value>ter:InvalidArgVal</env:Value>
+        </env:Subcode>
+      </env:Code>
+      <env:Reason>
+        <env:Text xml:lang="en">No profile</env:Text>
+      </env:Reason>
+      <env:Detail>
+        <ns0:Token env:mustUnderstand="true">main</ns0:Token>
+      </env:Detail>
+    </env:Fault>
+  </env:Body>
+</env:Envelope>"#;
+        assert_eq!(pretty_print(raw.as_bytes()).unwrap(), expected);
+        assert!(pretty_print(b"<a><b></a>").is_err());
+    }
+}
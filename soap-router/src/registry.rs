@@ -0,0 +1,280 @@
+use std::{
+    future::Future,
+    net::{SocketAddr, TcpListener},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use futures::FutureExt;
+use url::Url;
+
+use crate::{
+    describe::{DeviceDescription, MountedService},
+    router::SoapRouter,
+    uri::{UriKind, Uris},
+};
+
+/// Service mounted in a [`ServiceRegistry`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegisteredService {
+    /// Namespace of the service WSDL, as reported by `GetServices`
+    pub namespace: String,
+    pub path: String,
+}
+
+/// Shared view of the services of a [`ServiceRegistry`], kept up to date as
+/// services are added. Meant to be put in the state of the device service
+/// before it is itself registered.
+#[derive(Clone, Debug, Default)]
+pub struct ServiceDirectory(Arc<RwLock<Vec<RegisteredService>>>);
+
+impl ServiceDirectory {
+    pub fn services(&self) -> Vec<RegisteredService> {
+        self.0.read().unwrap().clone()
+    }
+
+    /// Address of each service for the request being handled, along with
+    /// its namespace.
+    pub fn xaddrs(&self, uris: &Uris) -> Vec<(String, Url)> {
+        self.0
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|s| Some((s.namespace.clone(), uris.build(UriKind::Service, &s.path)?)))
+            .collect()
+    }
+}
+
+/// Mount several SOAP routers, one per ONVIF service, under a single server
+#[derive(Default)]
+pub struct ServiceRegistry {
+    router: axum::Router,
+    directory: ServiceDirectory,
+    mounted: Vec<MountedService>,
+}
+
+impl ServiceRegistry {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn directory(&self) -> ServiceDirectory {
+        self.directory.clone()
+    }
+
+    /// Serve the given router on the absolute `path`, panics when the path
+    /// is already used.
+    pub fn add_service<S>(mut self, namespace: String, path: String, router: SoapRouter<S>) -> Self
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        self.mounted.push(MountedService {
+            namespace: namespace.clone(),
+            path: path.clone(),
+            description: router.describe(),
+        });
+        self.router = self.router.route_service(&path, router);
+        self.directory
+            .0
+            .write()
+            .unwrap()
+            .push(RegisteredService { namespace, path });
+        self
+    }
+
+    /// Description of the registered services and their operations, for
+    /// fleet management tooling
+    pub fn describe(&self) -> DeviceDescription {
+        DeviceDescription {
+            services: self.mounted.clone(),
+        }
+    }
+
+    pub fn into_axum_router(self) -> axum::Router {
+        self.router
+    }
+
+    /// Serve the registered services on `listener` until `signal` resolves.
+    /// New connections are then refused while in-flight calls are given
+    /// `drain` to complete, the remaining ones are dropped afterwards.
+    pub async fn serve_with_shutdown<F>(
+        self,
+        listener: TcpListener,
+        signal: F,
+        drain: Duration,
+    ) -> Result<(), hyper::Error>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let signal = signal.shared();
+        let server = axum::Server::from_tcp(listener)?
+            .serve(
+                self.router
+                    .into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(signal.clone());
+        tokio::select! {
+            res = server => res,
+            _ = async {
+                signal.await;
+                tokio::time::sleep(drain).await;
+            } => {
+                tracing::warn!(?drain, "SOAP calls still in flight after drain deadline, dropping them");
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+    };
+    use tokio::sync::oneshot;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{forwarded::RequestOrigin, router::SoapMessage, uri::DefaultUriBuilder};
+
+    #[tokio::test]
+    async fn test_registry() {
+        let service = |namespace: &str| {
+            SoapRouter::new(()).add_operation(namespace.to_string(), "Get".to_string(), || async {
+                Ok(SoapMessage::new())
+            })
+        };
+        let registry = ServiceRegistry::new();
+        let directory = registry.directory();
+        let registry = registry
+            .add_service(
+                "http://www.onvif.org/ver10/device/wsdl".to_string(),
+                "/onvif/device_service".to_string(),
+                service("http://www.onvif.org/ver10/device/wsdl"),
+            )
+            .add_service(
+                "http://www.onvif.org/ver10/media/wsdl".to_string(),
+                "/onvif/media_service".to_string(),
+                service("http://www.onvif.org/ver10/media/wsdl"),
+            );
+
+        let uris = Uris::new(
+            Some(RequestOrigin {
+                scheme: "http".to_string(),
+                host: "192.168.1.2:8080".to_string(),
+            }),
+            Arc::new(DefaultUriBuilder),
+        );
+        let xaddrs = directory.xaddrs(&uris);
+        assert_eq!(
+            xaddrs
+                .iter()
+                .map(|(ns, url)| (ns.as_str(), url.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "http://www.onvif.org/ver10/device/wsdl",
+                    "http://192.168.1.2:8080/onvif/device_service"
+                ),
+                (
+                    "http://www.onvif.org/ver10/media/wsdl",
+                    "http://192.168.1.2:8080/onvif/media_service"
+                ),
+            ]
+        );
+
+        let description = registry.describe();
+        assert_eq!(
+            description
+                .services
+                .iter()
+                .map(|s| (s.path.as_str(), s.description.services[0].operations.len()))
+                .collect::<Vec<_>>(),
+            vec![("/onvif/device_service", 1), ("/onvif/media_service", 1)]
+        );
+
+        let app = registry.into_axum_router();
+        for (path, namespace, status) in [
+            (
+                "/onvif/media_service",
+                "http://www.onvif.org/ver10/media/wsdl",
+                StatusCode::OK,
+            ),
+            (
+                "/onvif/media_service",
+                "http://www.onvif.org/ver10/device/wsdl",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                "/onvif/ptz_service",
+                "http://www.onvif.org/ver10/device/wsdl",
+                StatusCode::NOT_FOUND,
+            ),
+        ] {
+            let body = format!(
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="{}">
+                    <env:Body><m:Get/></env:Body>
+                </env:Envelope>"#,
+                namespace
+            );
+            let req = Request::builder()
+                .method("POST")
+                .uri(path)
+                .header(CONTENT_TYPE, "application/soap+xml")
+                .body(Body::from(body))
+                .unwrap();
+            let resp = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(resp.status(), status);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serve_with_shutdown() {
+        let namespace = "http://www.onvif.org/ver10/device/wsdl";
+        let registry = ServiceRegistry::new().add_service(
+            namespace.to_string(),
+            "/onvif/device_service".to_string(),
+            SoapRouter::new(()).add_operation(
+                namespace.to_string(),
+                "SystemReboot".to_string(),
+                || async {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    Ok(SoapMessage::new())
+                },
+            ),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(registry.serve_with_shutdown(
+            listener,
+            rx.map(|_| ()),
+            Duration::from_secs(5),
+        ));
+
+        let call = tokio::spawn(async move {
+            let body = format!(
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="{}">
+                    <env:Body><m:SystemReboot/></env:Body>
+                </env:Envelope>"#,
+                namespace
+            );
+            let req = Request::builder()
+                .method("POST")
+                .uri(format!("http://{}/onvif/device_service", addr))
+                .header(CONTENT_TYPE, "application/soap+xml")
+                .body(hyper::Body::from(body))
+                .unwrap();
+            hyper::Client::new().request(req).await.unwrap().status()
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        tx.send(()).unwrap();
+
+        // The call in flight completes before the server stops
+        assert_eq!(call.await.unwrap(), StatusCode::OK);
+        server.await.unwrap().unwrap();
+        assert!(std::net::TcpStream::connect(addr).is_err());
+    }
+}
@@ -1,23 +1,95 @@
-use std::{collections::HashMap, convert::Infallible, future::Future, io::Write, pin::Pin};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    convert::Infallible,
+    future::Future,
+    io::Write,
+    marker::PhantomData,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use axum::{
-    body::{boxed, Body, Bytes},
-    extract::FromRequest,
-    http::{Request, StatusCode},
+    body::{Body, Bytes},
+    extract::ConnectInfo,
+    http::{
+        header::{
+            ACCEPT_ENCODING, ACCEPT_LANGUAGE, ALLOW, CONTENT_ENCODING, CONTENT_LENGTH,
+            CONTENT_TYPE, VARY,
+        },
+        HeaderValue, Method, Request, StatusCode,
+    },
     response::{IntoResponse, Response},
 };
 use bytes::BufMut;
 use futures::{stream::FuturesOrdered, StreamExt};
+use serde::Serialize;
+use tokio::time::Instant;
+use tower::{timeout::Timeout, Layer, ServiceExt};
 use tower_service::Service;
-use xmltree::Element;
+use tracing::{Instrument, Span};
+use xml::namespace::{NS_NO_PREFIX, NS_XMLNS_PREFIX, NS_XML_PREFIX};
+use xmltree::{Element, Namespace, XMLNode};
 
-use crate::fault::SoapFault;
+use crate::{
+    cancellation::Cancellation,
+    codec::{check_document, invalid_message, DocumentError},
+    compression::{ContentEncoding, DecodeError, MIN_COMPRESSED_SIZE},
+    describe::{Capabilities, Description},
+    entropy::{default_source, uuid, EntropySource},
+    extract::{Extensions, FromSoapRequest, RawEnvelope, ResponseHeaders},
+    fault::{SoapFault, SoapFaultCode},
+    fault_log::FaultLog,
+    forwarded::{RequestOrigin, TrustedProxies},
+    i18n::parse_accept_language,
+    interceptor::{HeaderProcessor, Interceptor, QName},
+    mtom::{
+        media_type_parameters, multipart_response, IncomingAttachments, MultipartReader,
+        ResponseAttachments,
+    },
+    must_understand::{must_understand_fault, not_understood, report},
+    onvif_fault::{OnvifFault, ONVIF_ERROR_NAMESPACE},
+    soap_security::{
+        not_authorized, redact_credentials, route_access, AccessClass, AuthenticatedUser,
+        PreAuthOnly, WSSE_NAMESPACE,
+    },
+    stream::{body_sources, BodySource},
+    uri::{DefaultUriBuilder, UriBuilder, UriKind, Uris},
+    version::{convert_envelope, ContentType, SoapVersion},
+    ws_addressing::{
+        deliver, stamp_reply, AddressPolicy, AddressingHeaders, UNDERSTOOD_HEADERS, WSA_ANONYMOUS,
+        WSA_NAMESPACE, WSA_NONE,
+    },
+    xml_dsig::{RequireSignature, SignatureVerifier},
+};
 
 pub struct SoapRequest {
     pub headers: xmltree::Element,
     pub body: xmltree::Element,
+    /// Languages accepted by the client, most preferred first
+    pub languages: Vec<isolang::Language>,
+    /// Header blocks to add to the response
+    pub response_headers: ResponseHeaders,
+    /// Attachments streamed along the response
+    pub response_attachments: ResponseAttachments,
+    /// SOAP version of the request, responses use the same
+    pub version: SoapVersion,
+    /// Raw bytes of `body` in the request, layers replacing `body` must
+    /// reset it
+    pub body_source: Option<BodySource>,
+    /// Values shared by header processors, layers and handlers, the
+    /// `ConnectInfo`, `RequestOrigin`, `AuthenticatedUser` and `PreAuthOnly`
+    /// of the HTTP request are copied there when available, as are the
+    /// `Uris` and the `RouteInfo` of the operation.
+    pub extensions: Extensions,
+}
+/// SOAP envelope, along with the [`NoContent`] marker of the responses of
+/// one-way operations
+pub struct SoapMessage {
+    envelope: xmltree::Element,
+    no_content: Option<NoContent>,
 }
-pub struct SoapMessage(pub xmltree::Element);
 
 impl Default for SoapMessage {
     fn default() -> Self {
@@ -27,93 +99,216 @@ impl Default for SoapMessage {
 
 impl SoapMessage {
     pub fn new() -> Self {
-        let mut env = Element::new("Enveloppe");
-        env.namespace = Some("http://www.w3.org/2003/05/soap-envelope".to_string());
+        Self::with_version(SoapVersion::Soap12)
+    }
+
+    pub fn with_version(version: SoapVersion) -> Self {
+        let mut env = Element::new("Envelope");
+        env.prefix = Some("env".to_string());
+        env.namespace = Some(version.namespace().to_string());
         let mut namespaces = xmltree::Namespace::empty();
         namespaces.put("xml", "http://www.w3.org/XML/1998/namespace");
-        namespaces.put("env", "http://www.w3.org/2003/05/soap-envelope");
+        namespaces.put("env", version.namespace());
         env.namespaces = Some(namespaces);
         let mut body = Element::new("Body");
         body.prefix = Some("env".to_string());
+        body.namespace = Some(version.namespace().to_string());
         env.children.push(xmltree::XMLNode::Element(body));
-        Self(env)
+        env.into()
+    }
+
+    /// Empty envelope of a one-way operation, answered with a 202 status and
+    /// no body
+    pub fn no_content() -> Self {
+        Self {
+            no_content: Some(NoContent),
+            ..Self::new()
+        }
+    }
+
+    pub fn is_no_content(&self) -> bool {
+        self.no_content.is_some()
+    }
+
+    /// `Envelope` element of the message
+    pub fn envelope(&self) -> &xmltree::Element {
+        &self.envelope
+    }
+
+    pub fn envelope_mut(&mut self) -> &mut xmltree::Element {
+        &mut self.envelope
+    }
+
+    pub fn into_envelope(self) -> xmltree::Element {
+        self.envelope
+    }
+
+    /// SOAP version of the envelope, SOAP 1.2 if unknown
+    pub fn version(&self) -> SoapVersion {
+        self.envelope
+            .namespace
+            .as_deref()
+            .and_then(SoapVersion::from_namespace)
+            .unwrap_or_default()
     }
 
     pub fn get_body(&self) -> &xmltree::Element {
-        self.0
-            .get_child(("Body", "http://www.w3.org/2003/05/soap-envelope"))
+        self.envelope
+            .get_child(("Body", self.version().namespace()))
             .unwrap()
     }
 
     pub fn get_headers(&self) -> Option<&xmltree::Element> {
-        self.0
-            .get_child(("Header", "http://www.w3.org/2003/05/soap-envelope"))
+        self.envelope
+            .get_child(("Header", self.version().namespace()))
     }
 
     pub fn get_mut_body(&mut self) -> &mut xmltree::Element {
-        self.0
-            .get_mut_child(("Body", "http://www.w3.org/2003/05/soap-envelope"))
-            .unwrap()
+        let namespace = self.version().namespace();
+        self.envelope.get_mut_child(("Body", namespace)).unwrap()
     }
 
     pub fn get_mut_headers(&mut self) -> &mut xmltree::Element {
+        let namespace = self.version().namespace();
         if self.get_headers().is_none() {
-            let mut h = Element::new("Headers");
+            let mut h = Element::new("Header");
             h.prefix = Some("env".to_string());
-            self.0.children.insert(0, xmltree::XMLNode::Element(h));
+            h.namespace = Some(namespace.to_string());
+            self.envelope
+                .children
+                .insert(0, xmltree::XMLNode::Element(h));
         }
-        self.0
-            .get_mut_child(("Header", "http://www.w3.org/2003/05/soap-envelope"))
-            .unwrap()
+        self.envelope.get_mut_child(("Header", namespace)).unwrap()
     }
 }
 
 impl From<xmltree::Element> for SoapMessage {
-    fn from(value: xmltree::Element) -> Self {
-        Self(value)
+    fn from(envelope: xmltree::Element) -> Self {
+        Self {
+            envelope,
+            no_content: None,
+        }
     }
 }
 
 impl From<SoapMessage> for xmltree::Element {
     fn from(val: SoapMessage) -> xmltree::Element {
-        val.0
+        val.envelope
+    }
+}
+
+/// Message of the alloc-only model of `soap-core`
+impl TryFrom<soap_core::Message> for SoapMessage {
+    type Error = xmltree::ParseError;
+
+    fn try_from(msg: soap_core::Message) -> Result<Self, Self::Error> {
+        Ok(Self {
+            envelope: msg.to_envelope()?,
+            no_content: msg.is_no_content().then_some(NoContent),
+        })
+    }
+}
+
+impl SoapMessage {
+    /// The message in the alloc-only model of `soap-core`, `None` when the
+    /// envelope isn't a SOAP envelope
+    pub fn to_core(&self) -> Option<soap_core::Message> {
+        match self.no_content {
+            Some(NoContent) => Some(soap_core::Message::no_content(self.version())),
+            None => soap_core::Message::from_envelope(&self.envelope),
+        }
     }
 }
 
-impl<Y, Z> From<(Y, Z)> for SoapMessage
+/// Response of one-way operations, the router replies with a 202 status and
+/// an empty body. Returning `None` from a handler answering an
+/// `Option<SoapMessage>` does the same.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NoContent;
+
+impl From<NoContent> for SoapMessage {
+    fn from(_: NoContent) -> Self {
+        SoapMessage::no_content()
+    }
+}
+
+impl<T> TryFrom<Option<T>> for SoapMessage
+where
+    T: TryInto<SoapMessage>,
+    T::Error: Into<SoapFault>,
+{
+    type Error = SoapFault;
+
+    fn try_from(val: Option<T>) -> Result<Self, SoapFault> {
+        match val {
+            Some(msg) => msg.try_into().map_err(Into::into),
+            None => Ok(SoapMessage::no_content()),
+        }
+    }
+}
+
+impl<Y, Z> TryFrom<(Y, Z)> for SoapMessage
 where
-    Y: Into<SoapMessage>,
-    Z: Into<SoapMessage>,
+    Y: TryInto<SoapMessage>,
+    Y::Error: Into<SoapFault>,
+    Z: TryInto<SoapMessage>,
+    Z::Error: Into<SoapFault>,
 {
-    fn from(val: (Y, Z)) -> SoapMessage {
-        SoapMessage(merge_soap_enveloppe(val.0.into().0, val.1.into().0))
+    type Error = SoapFault;
+
+    fn try_from(val: (Y, Z)) -> Result<Self, SoapFault> {
+        let first: SoapMessage = val.0.try_into().map_err(Into::into)?;
+        let second: SoapMessage = val.1.try_into().map_err(Into::into)?;
+        // One-way only when both are
+        let no_content = first.no_content.and(second.no_content);
+        Ok(SoapMessage {
+            envelope: merge_soap_enveloppe(first.envelope, second.envelope),
+            no_content,
+        })
     }
 }
 
 type BoxedSoapFuture = Pin<Box<dyn Future<Output = Result<SoapMessage, SoapFault>> + Send>>;
-type BoxedSoapHandlerService = tower::util::BoxCloneService<SoapRequest, SoapMessage, SoapFault>;
+/// Type erased service handling a single SOAP operation, this is what
+/// [`SoapRouter::layer`] and [`SoapRouter::route_layer`] wrap.
+pub type BoxedSoapHandlerService =
+    tower::util::BoxCloneSyncService<SoapRequest, SoapMessage, SoapFault>;
 
-#[derive(Clone)]
-struct SoapHandlerService<S, H>
+struct SoapHandlerService<S, H, T>
 where
-    H: SoapHandler<S>,
+    H: SoapHandler<T, S>,
 {
     state: S,
     handler: H,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<S, H, T> Clone for SoapHandlerService<S, H, T>
+where
+    H: SoapHandler<T, S>,
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self::new(self.handler.clone(), self.state.clone())
+    }
 }
 
-impl<S, H> SoapHandlerService<S, H>
+impl<S, H, T> SoapHandlerService<S, H, T>
 where
-    H: SoapHandler<S>,
+    H: SoapHandler<T, S>,
 {
     fn new(handler: H, state: S) -> Self {
-        Self { state, handler }
+        Self {
+            state,
+            handler,
+            _marker: PhantomData,
+        }
     }
 }
 
-impl<S, H> Service<SoapRequest> for SoapHandlerService<S, H>
+impl<S, H, T> Service<SoapRequest> for SoapHandlerService<S, H, T>
 where
-    H: SoapHandler<S>,
+    H: SoapHandler<T, S>,
     S: Clone,
 {
     type Error = SoapFault;
@@ -129,32 +324,157 @@ where
     }
 
     fn call(&mut self, req: SoapRequest) -> Self::Future {
-        self.handler.clone().call(&req, self.state.clone())
+        let call = self.handler.clone().call(&req, self.state.clone());
+        #[cfg(feature = "metrics")]
+        let call = Box::pin(crate::metrics::instrument(&req.body, call));
+        call
     }
 }
 
-pub trait SoapHandler<S>: 'static + Send + Sync + Clone {
+pub trait SoapHandler<T, S>: 'static + Send + Sync + Clone {
     fn call(self, req: &SoapRequest, state: S) -> BoxedSoapFuture;
 }
 
-impl<F, Fut, Res, S> SoapHandler<S> for F
+impl<F, Fut, Res, S> SoapHandler<(), S> for F
 where
     F: FnOnce() -> Fut + Clone + Send + Sync + 'static,
     Fut: Future<Output = Result<Res, SoapFault>> + Send,
-    Res: Into<SoapMessage>,
+    Res: TryInto<SoapMessage>,
+    Res::Error: Into<SoapFault>,
 {
     fn call(self, _req: &SoapRequest, _state: S) -> BoxedSoapFuture {
-        Box::pin(async move { Ok(self().await?.into()) })
+        Box::pin(async move { self().await?.try_into().map_err(Into::into) })
     }
 }
 
+macro_rules! impl_soap_handler {
+    ($($ty:ident),+) => {
+        #[allow(non_snake_case)]
+        impl<F, Fut, Res, S, $($ty,)+> SoapHandler<($($ty,)+), S> for F
+        where
+            F: FnOnce($($ty,)+) -> Fut + Clone + Send + Sync + 'static,
+            Fut: Future<Output = Result<Res, SoapFault>> + Send,
+            Res: TryInto<SoapMessage>,
+            Res::Error: Into<SoapFault>,
+            $($ty: FromSoapRequest<S> + Send + 'static,)+
+        {
+            fn call(self, req: &SoapRequest, state: S) -> BoxedSoapFuture {
+                $(
+                    let $ty = match $ty::from_soap_request(req, &state) {
+                        Ok(v) => v,
+                        Err(e) => return Box::pin(async move { Err(e) }),
+                    };
+                )+
+                Box::pin(async move { self($($ty,)+).await?.try_into().map_err(Into::into) })
+            }
+        }
+    };
+}
+
+impl_soap_handler!(T1);
+impl_soap_handler!(T1, T2);
+impl_soap_handler!(T1, T2, T3);
+impl_soap_handler!(T1, T2, T3, T4);
+impl_soap_handler!(T1, T2, T3, T4, T5);
+impl_soap_handler!(T1, T2, T3, T4, T5, T6);
+impl_soap_handler!(T1, T2, T3, T4, T5, T6, T7);
+impl_soap_handler!(T1, T2, T3, T4, T5, T6, T7, T8);
+impl_soap_handler!(T1, T2, T3, T4, T5, T6, T7, T8, T9);
+impl_soap_handler!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10);
+impl_soap_handler!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11);
+impl_soap_handler!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12);
+impl_soap_handler!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13);
+impl_soap_handler!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14);
+impl_soap_handler!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15);
+impl_soap_handler!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15, T16);
+
+/// Description of an operation registered on a router
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct RouteInfo {
+    /// Body element the route is dispatched on
+    pub operation: Option<QName>,
+    /// WS-Addressing action the route is dispatched on
+    pub action: Option<String>,
+    /// False for optional operations answering `ter:ActionNotSupported`
+    pub supported: bool,
+    /// Access class declared with [`SoapRouter::add_operation_with_access`]
+    pub access: Option<AccessClass>,
+    /// Timeout set with [`SoapRouter::with_operation_timeout`], overriding
+    /// the one of the router
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Clone)]
+struct Route {
+    info: RouteInfo,
+    service: BoxedSoapHandlerService,
+}
+
+/// Produces the WSDL document served on `?wsdl` requests
+type WsdlSource = dyn Fn() -> String + Send + Sync;
+
+/// Registered with [`SoapRouter::on_request`] and [`SoapRouter::on_response`]
+type EnvelopeHook = dyn Fn(&SoapMessage) + Send + Sync;
+
+/// Operation matched in a request, along with its route when it isn't handled
+/// by the fallback
+type DispatchedOperation<'a> = (
+    QName,
+    &'a Element,
+    Option<BodySource>,
+    &'a BoxedSoapHandlerService,
+    Option<&'a RouteInfo>,
+);
+
+/// Interceptor along with the namespace it is restricted to, if any
+type ScopedInterceptor = (Option<String>, Arc<dyn Interceptor>);
+
 #[derive(Clone)]
 pub struct SoapRouter<S>
 where
     S: Send + Sync + 'static,
 {
     state: S,
-    routes: HashMap<(String, String), BoxedSoapHandlerService>,
+    routes: Arc<HashMap<QName, Route>>,
+    action_routes: Arc<HashMap<String, Route>>,
+    interceptors: Arc<Vec<ScopedInterceptor>>,
+    fallback: Option<BoxedSoapHandlerService>,
+    entropy: Arc<dyn EntropySource>,
+    address_policy: Arc<AddressPolicy>,
+    wsdl: Option<Arc<WsdlSource>>,
+    understood: Arc<HashSet<QName>>,
+    header_processors: Arc<Vec<(QName, Arc<dyn HeaderProcessor>)>>,
+    body_limit: usize,
+    max_depth: usize,
+    compression: bool,
+    multi_element_policy: MultiElementPolicy,
+    trusted_proxies: Arc<TrustedProxies>,
+    uri_builder: Arc<dyn UriBuilder>,
+    timeout: Option<Duration>,
+    fault_log: FaultLog,
+    request_hooks: Arc<Vec<Arc<EnvelopeHook>>>,
+    response_hooks: Arc<Vec<Arc<EnvelopeHook>>>,
+}
+
+/// Default size limit of the envelopes, attachments are not counted
+pub const DEFAULT_BODY_LIMIT: usize = 2 * 1024 * 1024;
+/// Default nesting limit of the elements of the envelopes
+pub const DEFAULT_MAX_DEPTH: usize = 64;
+
+/// How requests whose body holds several elements are handled, the
+/// responses of the operations are merged in a single envelope.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MultiElementPolicy {
+    /// Requests with more than one body element get a `ter:InvalidArgs`
+    /// fault
+    RejectMultiple,
+    /// Operations run one after the other, in the request order, and the
+    /// ones following a fault are not run
+    ProcessOrdered,
+    /// Operations run concurrently
+    #[default]
+    ProcessConcurrent,
 }
 
 impl<S> SoapRouter<S>
@@ -164,218 +484,1728 @@ where
     pub fn new(state: S) -> Self {
         SoapRouter {
             state,
-            routes: HashMap::default(),
+            routes: Default::default(),
+            action_routes: Default::default(),
+            interceptors: Default::default(),
+            fallback: None,
+            entropy: default_source(),
+            address_policy: Default::default(),
+            wsdl: None,
+            understood: Arc::new(
+                UNDERSTOOD_HEADERS
+                    .iter()
+                    .map(|name| QName::new(WSA_NAMESPACE, name))
+                    .collect(),
+            ),
+            header_processors: Default::default(),
+            body_limit: DEFAULT_BODY_LIMIT,
+            max_depth: DEFAULT_MAX_DEPTH,
+            compression: false,
+            multi_element_policy: Default::default(),
+            trusted_proxies: Default::default(),
+            uri_builder: Arc::new(DefaultUriBuilder),
+            timeout: None,
+            fault_log: Default::default(),
+            request_hooks: Default::default(),
+            response_hooks: Default::default(),
         }
     }
 
-    pub fn add_operation<H>(mut self, namespace: String, element_name: String, handler: H) -> Self
+    /// Handler called when the request contains no known operation, by default
+    /// a Sender fault with a `rpc:ProcedureNotPresent` subcode is returned.
+    pub fn fallback<H, T>(mut self, handler: H) -> Self
     where
-        H: SoapHandler<S> + 'static + Send + Sync,
+        H: SoapHandler<T, S> + 'static + Send + Sync,
+        T: 'static,
         S: Send + Sync + 'static,
     {
-        self.routes.insert(
-            (namespace, element_name),
-            BoxedSoapHandlerService::new(SoapHandlerService::new(handler, self.state.clone())),
-        );
+        self.fallback = Some(BoxedSoapHandlerService::new(SoapHandlerService::new(
+            handler,
+            self.state.clone(),
+        )));
         self
     }
 
-    async fn parse_request(&self, req: Request<Body>) -> Result<SoapMessage, String> {
-        let state = self.state.clone();
-        let body = Bytes::from_request(req, &state).await.unwrap();
-        let xml_body = xmltree::Element::parse(body.as_ref()).unwrap();
-        if xml_body.name != "Envelope"
-            && xml_body.namespace != Some("http://www.w3.org/2003/05/soap-envelope".to_string())
-        {
-            return Err("Not a SOAP message".to_string());
-        }
-        if xml_body
-            .get_child(("Body", "http://www.w3.org/2003/05/soap-envelope"))
-            .is_none()
-        {
-            return Err("Malformed SOAP Message".to_string());
-        }
-        Ok(xml_body.into())
+    /// Wrap all the operations and the fallback added so far with the given
+    /// layer, operations added afterward are not affected.
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<BoxedSoapHandlerService>,
+        L::Service: Service<SoapRequest, Response = SoapMessage, Error = SoapFault>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        <L::Service as Service<SoapRequest>>::Future: Send + 'static,
+    {
+        self.fallback = self
+            .fallback
+            .map(|f| BoxedSoapHandlerService::new(layer.layer(f)));
+        self.route_layer(layer)
     }
 
-    async fn call_internal(&self, req: Request<Body>) -> Result<Response, Infallible> {
-        let soap_req = match self.parse_request(req).await {
-            Ok(r) => r,
-            Err(_) => {
-                let body = boxed(Body::default());
-                return Ok(Response::builder()
-                    .status(StatusCode::BAD_REQUEST)
-                    .body(body)
-                    .unwrap());
-            }
+    /// Wrap all the operations added so far with the given layer, unlike
+    /// [`SoapRouter::layer`] the fallback is left untouched.
+    pub fn route_layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<BoxedSoapHandlerService>,
+        L::Service: Service<SoapRequest, Response = SoapMessage, Error = SoapFault>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        <L::Service as Service<SoapRequest>>::Future: Send + 'static,
+    {
+        let wrap = |route: &mut Route| {
+            route.service = BoxedSoapHandlerService::new(layer.layer(route.service.clone()));
         };
-        let soap_body = soap_req.get_body();
-        let soap_headers = match soap_req.get_headers() {
-            None => {
-                let mut e = Element::new("Header");
-                e.namespace = Some("http://www.w3.org/2003/05/soap-envelope".to_string());
-                e
-            }
-            Some(h) => h.clone(),
+        Arc::make_mut(&mut self.routes).values_mut().for_each(wrap);
+        Arc::make_mut(&mut self.action_routes)
+            .values_mut()
+            .for_each(wrap);
+        self
+    }
+
+    /// Require the request body of the operations added so far to be signed
+    /// with a trusted certificate, see [`RequireSignature`]. This installs it
+    /// both as the processor of the `wsse:Security` header, verifying the
+    /// signature once per envelope, and as the layer of the operations.
+    pub fn require_signature<V: SignatureVerifier>(self, signature: RequireSignature<V>) -> Self {
+        self.add_header_processor(
+            WSSE_NAMESPACE.to_string(),
+            "Security".to_string(),
+            signature.clone(),
+        )
+        .route_layer(signature)
+    }
+
+    /// Replace the source of random data used for generated identifiers
+    pub fn with_entropy_source<E: EntropySource>(mut self, source: E) -> Self {
+        self.entropy = Arc::new(source);
+        self
+    }
+
+    /// Set which non-anonymous `wsa:ReplyTo` and `wsa:FaultTo` addresses are
+    /// accepted, by default only anonymous ones are. Messages for accepted
+    /// addresses are delivered there and the request is answered with 202.
+    pub fn with_address_policy(mut self, policy: AddressPolicy) -> Self {
+        self.address_policy = Arc::new(policy);
+        self
+    }
+
+    /// Largest accepted envelope, in bytes, larger requests get a Sender
+    /// fault with a 413 status. The attachments of multipart requests, read
+    /// by the handlers, are not counted.
+    pub fn with_body_limit(mut self, bytes: usize) -> Self {
+        self.body_limit = bytes;
+        self
+    }
+
+    /// Deepest accepted nesting of elements in an envelope
+    pub fn with_max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Accept gzip and deflate encoded requests, and compress the responses
+    /// of the clients accepting it. Disabled by default, encoded requests
+    /// then get a 415 status. The body limit applies to the decoded
+    /// envelopes.
+    pub fn with_compression(mut self, enabled: bool) -> Self {
+        self.compression = enabled;
+        self
+    }
+
+    /// Set how requests whose body holds several elements are handled, all
+    /// of the known operations run concurrently by default.
+    pub fn with_multi_element_policy(mut self, policy: MultiElementPolicy) -> Self {
+        self.multi_element_policy = policy;
+        self
+    }
+
+    /// Set the proxies whose `Forwarded` and `X-Forwarded-*` headers are
+    /// honored for the [`RequestOrigin`] of the requests, none by default.
+    pub fn with_trusted_proxies(mut self, proxies: TrustedProxies) -> Self {
+        self.trusted_proxies = Arc::new(proxies);
+        self
+    }
+
+    /// Build the URIs handed out to clients with the given policy, available
+    /// to handlers through the [`Uris`] extractor and also used for the WSDL
+    /// locations.
+    pub fn with_uri_builder<B: UriBuilder>(mut self, builder: B) -> Self {
+        self.uri_builder = Arc::new(builder);
+        self
+    }
+
+    /// Give up on operations taking longer than `timeout`, answering a
+    /// `env:Receiver/ter:Action` fault. The deadline is also handed to the
+    /// handlers through their [`Cancellation`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Override the timeout of an operation added earlier, along with the
+    /// one of the action route of its `{namespace}/{element_name}` action.
+    /// Panics when neither route exists.
+    pub fn with_operation_timeout(
+        mut self,
+        namespace: String,
+        element_name: String,
+        timeout: Duration,
+    ) -> Self {
+        let action = format!("{}/{}", namespace, element_name);
+        let operation = QName {
+            namespace,
+            name: element_name,
         };
-        let mut fut = FuturesOrdered::new();
-        for elem in soap_body.children.iter() {
-            let elem = elem.as_element();
-            if elem.is_none() {
-                continue;
-            }
-            let elem = elem.unwrap();
-            if let Some(handler) = self.routes.get(&(
-                elem.namespace.clone().unwrap_or_default(),
-                elem.name.clone(),
-            )) {
-                fut.push_back(handler.clone().call(SoapRequest {
-                    headers: soap_headers.clone(),
-                    body: elem.clone(),
-                }));
-            }
+        let mut found = false;
+        if let Some(route) = Arc::make_mut(&mut self.routes).get_mut(&operation) {
+            route.info.timeout = Some(timeout);
+            found = true;
         }
-        if fut.is_empty() {
-            // Handle operations not found
-            todo!()
+        if let Some(route) = Arc::make_mut(&mut self.action_routes).get_mut(&action) {
+            route.info.timeout = Some(timeout);
+            found = true;
         }
-        let soap_reponses: Vec<xmltree::Element> = fut.map(|e| e.unwrap().0).collect().await;
+        if !found {
+            panic!("Operation {} is not handled by the router", operation);
+        }
+        self
+    }
 
-        let merged_response = soap_reponses
-            .into_iter()
-            .reduce(merge_soap_enveloppe)
-            .unwrap();
+    /// Record the faults sent by the router in the given log, to share one
+    /// between several routers. Each router has its own by default.
+    pub fn with_fault_log(mut self, log: FaultLog) -> Self {
+        self.fault_log = log;
+        self
+    }
 
-        let mut buf = vec![].writer();
-        merged_response.write(buf.by_ref()).unwrap();
-        Ok(buf.into_inner().into_response())
+    /// Log of the faults sent by the router
+    pub fn fault_log(&self) -> FaultLog {
+        self.fault_log.clone()
     }
-}
 
-fn merge_soap_enveloppe(mut accumulator: Element, element: Element) -> Element {
-    for child in element.children {
-        match child.as_element() {
-            None => accumulator.children.push(child),
-            Some(e) => {
-                let acc_child = match e.namespace.clone() {
-                    None => accumulator.get_mut_child(e.name.clone()),
-                    Some(n) => accumulator.get_mut_child((e.name.clone(), n)),
-                };
-                match acc_child {
-                    None => accumulator.children.push(child),
-                    Some(a) => {
-                        if a.attributes == e.attributes {
-                            a.children.extend(e.children.iter().cloned())
-                        } else {
-                            accumulator.children.push(child);
-                        }
-                    }
-                }
-            }
-        }
+    /// Mount the router at the given path of an axum router
+    pub fn axum_route(self, path: &str) -> axum::Router
+    where
+        S: 'static,
+    {
+        axum::Router::new().route_service(path, self)
     }
-    accumulator
-}
 
-impl<S> Service<Request<Body>> for SoapRouter<S>
-where
-    S: Clone + Send + Sync + 'static,
-{
-    type Response = Response;
-    type Error = Infallible;
-    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+    /// Serve the requests of any path with the router
+    pub fn into_axum_router(self) -> axum::Router
+    where
+        S: 'static,
+    {
+        axum::Router::new().fallback_service(self)
+    }
 
-    fn call(&mut self, req: Request<Body>) -> Self::Future {
-        let cs = self.clone();
-        Box::pin(async move { cs.call_internal(req).await })
+    /// Serve the given WSDL document on `GET ?wsdl` requests, the location of
+    /// its `soap:address` elements is replaced by the address the document
+    /// was requested on.
+    pub fn with_wsdl(self, wsdl: String) -> Self {
+        self.with_wsdl_generator(move || wsdl.clone())
     }
 
-    fn poll_ready(
-        &mut self,
-        _cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Result<(), Self::Error>> {
-        Ok(()).into()
+    /// Same as [`SoapRouter::with_wsdl`] with a document generated on each
+    /// request.
+    pub fn with_wsdl_generator<F>(mut self, generator: F) -> Self
+    where
+        F: Fn() -> String + Send + Sync + 'static,
+    {
+        self.wsdl = Some(Arc::new(generator));
+        self
     }
-}
 
-#[cfg(test)]
-mod tests {
+    /// Declare a header block as understood, requests carrying other header
+    /// blocks with `mustUnderstand` set get a MustUnderstand fault.
+    pub fn add_understood_header(mut self, namespace: String, name: String) -> Self {
+        Arc::make_mut(&mut self.understood).insert(QName { namespace, name });
+        self
+    }
 
-    use super::*;
+    /// Process the header blocks with the given name once per envelope, before
+    /// its operations, the header is then considered understood. Processors
+    /// run in the order they were added.
+    pub fn add_header_processor<P: HeaderProcessor>(
+        mut self,
+        namespace: String,
+        name: String,
+        processor: P,
+    ) -> Self {
+        let qname = QName { namespace, name };
+        Arc::make_mut(&mut self.understood).insert(qname.clone());
+        Arc::make_mut(&mut self.header_processors).push((qname, Arc::new(processor)));
+        self
+    }
 
-    #[test]
-    fn test_merge_xml() {
-        let xml1_raw = r#"<?xml version="1.0" ?>
-        <soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
-            <soap:Header>
-            </soap:Header>
-            <soap:Body>
-                <m:GetStockPrice>
-                    <m:StockName>T</m:StockName>
-                </m:GetStockPrice>
-            </soap:Body>
-        </soap:Envelope>
-        "#;
+    /// Call `hook` with every request envelope once parsed, meant to record
+    /// the traffic for debugging. WS-Security passwords and nonces are
+    /// redacted beforehand.
+    pub fn on_request<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&SoapMessage) + Send + Sync + 'static,
+    {
+        Arc::make_mut(&mut self.request_hooks).push(Arc::new(hook));
+        self
+    }
 
-        let xml2_raw = r#"<?xml version="1.0" ?>
-        <soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
-            <soap:Header>
-            </soap:Header>
-            <soap:Body>
-                <m:GetStockPrice>
-                    <m:StockName>Y</m:StockName>
-                </m:GetStockPrice>
-            </soap:Body>
-        </soap:Envelope>
-        "#;
-        let expected_raw = r#"<?xml version="1.0"?>
-        <soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
-            <soap:Header>
-            </soap:Header>
-            <soap:Body>
-                <m:GetStockPrice>
-                    <m:StockName>T</m:StockName>
-                </m:GetStockPrice>
-                <m:GetStockPrice>
-                    <m:StockName>Y</m:StockName>
-                </m:GetStockPrice>
-            </soap:Body>
-        </soap:Envelope>
-        "#;
+    /// Call `hook` with the envelopes sent in response to the requests seen
+    /// by the [`SoapRouter::on_request`] hooks, faults included.
+    pub fn on_response<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&SoapMessage) + Send + Sync + 'static,
+    {
+        Arc::make_mut(&mut self.response_hooks).push(Arc::new(hook));
+        self
+    }
 
-        let xml1 = Element::parse(xml1_raw.as_bytes()).unwrap();
-        let xml2 = Element::parse(xml2_raw.as_bytes()).unwrap();
-        let expected = Element::parse(expected_raw.as_bytes()).unwrap();
+    /// Add an interceptor run around every operation of this router
+    pub fn intercept<I: Interceptor>(mut self, interceptor: I) -> Self {
+        Arc::make_mut(&mut self.interceptors).push((None, Arc::new(interceptor)));
+        self
+    }
 
-        assert_eq!(merge_soap_enveloppe(xml1, xml2), expected)
+    /// Add an interceptor run around the operations of the given namespace
+    pub fn intercept_namespace<I: Interceptor>(
+        mut self,
+        namespace: String,
+        interceptor: I,
+    ) -> Self {
+        Arc::make_mut(&mut self.interceptors).push((Some(namespace), Arc::new(interceptor)));
+        self
     }
 
-    #[tokio::test]
-    async fn test_router() {
-        let mut router = SoapRouter::new(())
-            .add_operation("http://www.example.org".to_string(), "GetStockPrice".to_string(), || { async move { 
-                let xml1_raw = r#"<?xml version="1.0" ?>
-        <soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
-            <soap:Header>
-            </soap:Header>
-            <soap:Body>
-                <m:GetStockPriceResponse>
-                    <m:StockPrice>3.60</m:StockPrice>
-                </m:GetStockPriceResponse>
-            </soap:Body>
-        </soap:Envelope>
-        "#;
-        Ok(Element::parse(xml1_raw.as_bytes()).unwrap())
-            }});
+    pub fn add_operation<H, T>(self, namespace: String, element_name: String, handler: H) -> Self
+    where
+        H: SoapHandler<T, S> + 'static + Send + Sync,
+        T: 'static,
+        S: Send + Sync + 'static,
+    {
+        self.insert_route(namespace, element_name, true, handler)
+    }
 
-        let in_raw = r#"<?xml version="1.0"?>
-            <soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
-                <soap:Header>
-                </soap:Header>
-                <soap:Body>
-                    <m:GetStockPrice>
-                        <m:StockName>T</m:StockName>
-                    </m:GetStockPrice>
+    /// Add an operation along with its access class, enforced by the
+    /// [`AccessControl`](crate::soap_security::AccessControl) layer.
+    pub fn add_operation_with_access<H, T>(
+        self,
+        namespace: String,
+        element_name: String,
+        access: AccessClass,
+        handler: H,
+    ) -> Self
+    where
+        H: SoapHandler<T, S> + 'static + Send + Sync,
+        T: 'static,
+        S: Send + Sync + 'static,
+    {
+        let operation = QName {
+            namespace: namespace.clone(),
+            name: element_name.clone(),
+        };
+        let mut router = self.insert_route(namespace, element_name, true, handler);
+        if let Some(route) = Arc::make_mut(&mut router.routes).get_mut(&operation) {
+            route.info.access = Some(access);
+        }
+        router
+    }
+
+    fn insert_route<H, T>(
+        mut self,
+        namespace: String,
+        element_name: String,
+        supported: bool,
+        handler: H,
+    ) -> Self
+    where
+        H: SoapHandler<T, S> + 'static + Send + Sync,
+        T: 'static,
+        S: Send + Sync + 'static,
+    {
+        let operation = QName {
+            namespace,
+            name: element_name,
+        };
+        let route = self.make_route(
+            RouteInfo {
+                operation: Some(operation.clone()),
+                action: None,
+                supported,
+                access: None,
+                timeout: None,
+            },
+            handler,
+        );
+        Arc::make_mut(&mut self.routes).insert(operation, route);
+        self
+    }
+
+    fn make_route<H, T>(&self, info: RouteInfo, handler: H) -> Route
+    where
+        H: SoapHandler<T, S> + 'static + Send + Sync,
+        T: 'static,
+        S: Send + Sync + 'static,
+    {
+        Route {
+            info,
+            service: BoxedSoapHandlerService::new(SoapHandlerService::new(
+                handler,
+                self.state.clone(),
+            )),
+        }
+    }
+
+    /// Add an operation dispatched on the WS-Addressing `wsa:Action` header
+    /// rather than on the body element, action routes take precedence over
+    /// body element routes.
+    pub fn add_action_route<H, T>(self, action: String, handler: H) -> Self
+    where
+        H: SoapHandler<T, S> + 'static + Send + Sync,
+        T: 'static,
+        S: Send + Sync + 'static,
+    {
+        self.insert_action_route(action, None, handler)
+    }
+
+    /// Add an action route along with its access class, see
+    /// [`SoapRouter::add_operation_with_access`].
+    pub fn add_action_route_with_access<H, T>(
+        self,
+        action: String,
+        access: AccessClass,
+        handler: H,
+    ) -> Self
+    where
+        H: SoapHandler<T, S> + 'static + Send + Sync,
+        T: 'static,
+        S: Send + Sync + 'static,
+    {
+        self.insert_action_route(action, Some(access), handler)
+    }
+
+    fn insert_action_route<H, T>(
+        mut self,
+        action: String,
+        access: Option<AccessClass>,
+        handler: H,
+    ) -> Self
+    where
+        H: SoapHandler<T, S> + 'static + Send + Sync,
+        T: 'static,
+        S: Send + Sync + 'static,
+    {
+        let route = self.make_route(
+            RouteInfo {
+                operation: None,
+                action: Some(action.clone()),
+                supported: true,
+                access,
+                timeout: None,
+            },
+            handler,
+        );
+        Arc::make_mut(&mut self.action_routes).insert(action, route);
+        self
+    }
+
+    /// Add the operations and action routes of another router, along with
+    /// its interceptors, understood headers and header processors. The
+    /// interceptors of `other` applying to all of its operations are scoped
+    /// to the namespaces of those; its fallback is used when this router
+    /// has none and its other settings are ignored.
+    ///
+    /// Panics when both routers handle the same operation or action.
+    pub fn merge<S2>(self, other: SoapRouter<S2>) -> Self
+    where
+        S2: Send + Sync + 'static,
+    {
+        self.absorb(String::new(), other)
+    }
+
+    /// Same as [`SoapRouter::merge`], with `ns_prefix` prepended to the
+    /// namespaces of the operations and interceptors of `other`. Meant for
+    /// modules registering their operations relative to the namespace of
+    /// the service they are part of.
+    pub fn nest_namespace<S2>(self, ns_prefix: String, other: SoapRouter<S2>) -> Self
+    where
+        S2: Send + Sync + 'static,
+    {
+        self.absorb(ns_prefix, other)
+    }
+
+    fn absorb<S2>(mut self, ns_prefix: String, other: SoapRouter<S2>) -> Self
+    where
+        S2: Send + Sync + 'static,
+    {
+        let mut namespaces = BTreeSet::new();
+        let routes = Arc::make_mut(&mut self.routes);
+        for (operation, route) in other.routes.iter() {
+            let operation = QName {
+                namespace: format!("{}{}", ns_prefix, operation.namespace),
+                name: operation.name.clone(),
+            };
+            if routes.contains_key(&operation) {
+                panic!("Operation {} is already handled by the router", operation);
+            }
+            let mut route = route.clone();
+            route.info.operation = Some(operation.clone());
+            namespaces.insert(operation.namespace.clone());
+            routes.insert(operation, route);
+        }
+        let action_routes = Arc::make_mut(&mut self.action_routes);
+        for (action, route) in other.action_routes.iter() {
+            if action_routes.contains_key(action) {
+                panic!("Action {} is already handled by the router", action);
+            }
+            action_routes.insert(action.clone(), route.clone());
+        }
+        let interceptors = Arc::make_mut(&mut self.interceptors);
+        for (scope, interceptor) in other.interceptors.iter() {
+            match scope {
+                Some(namespace) => interceptors.push((
+                    Some(format!("{}{}", ns_prefix, namespace)),
+                    interceptor.clone(),
+                )),
+                None => interceptors.extend(
+                    namespaces
+                        .iter()
+                        .map(|namespace| (Some(namespace.clone()), interceptor.clone())),
+                ),
+            }
+        }
+        Arc::make_mut(&mut self.understood).extend(other.understood.iter().cloned());
+        Arc::make_mut(&mut self.header_processors).extend(other.header_processors.iter().cloned());
+        Arc::make_mut(&mut self.request_hooks).extend(other.request_hooks.iter().cloned());
+        Arc::make_mut(&mut self.response_hooks).extend(other.response_hooks.iter().cloned());
+        self.fallback = self.fallback.or(other.fallback);
+        self
+    }
+
+    /// Registered operations, ordered by namespace and name then by action
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        let mut routes: Vec<RouteInfo> = self
+            .routes
+            .values()
+            .chain(self.action_routes.values())
+            .map(|r| r.info.clone())
+            .collect();
+        routes.sort_by(|a, b| {
+            let key = |r: &RouteInfo| {
+                (
+                    r.operation.is_none(),
+                    r.operation
+                        .as_ref()
+                        .map(|o| (o.namespace.clone(), o.name.clone())),
+                    r.action.clone(),
+                )
+            };
+            key(a).cmp(&key(b))
+        });
+        routes
+    }
+
+    /// Description of the operations and protocol features of the router
+    pub fn describe(&self) -> Description {
+        let mut understood_headers: Vec<QName> = self.understood.iter().cloned().collect();
+        understood_headers.sort_by(|a, b| (&a.namespace, &a.name).cmp(&(&b.namespace, &b.name)));
+        Description::new(
+            self.routes(),
+            Capabilities {
+                soap_versions: vec![SoapVersion::Soap11, SoapVersion::Soap12],
+                wsdl: self.wsdl.is_some(),
+                fallback: self.fallback.is_some(),
+                understood_headers,
+            },
+        )
+    }
+
+    /// Add an optional operation, when no handler is given the operation
+    /// answers with a `ter:ActionNotSupported` fault.
+    pub fn add_optional_operation<H, T>(
+        self,
+        namespace: String,
+        element_name: String,
+        handler: Option<H>,
+    ) -> Self
+    where
+        H: SoapHandler<T, S> + 'static + Send + Sync,
+        T: 'static,
+        S: Send + Sync + 'static,
+    {
+        match handler {
+            Some(h) => self.add_operation(namespace, element_name, h),
+            None => self.insert_route(namespace, element_name, false, || async {
+                Err::<SoapMessage, _>(action_not_supported())
+            }),
+        }
+    }
+
+    /// Parse the envelope, along with the action given by the SOAP 1.2
+    /// `action` media type parameter or the SOAP 1.1 `SOAPAction` header and
+    /// the location of the body entries.
+    /// The attachments of MTOM/XOP requests are left in the body for the
+    /// handlers, see [`RequestAttachments`](crate::mtom::RequestAttachments).
+    async fn parse_request(
+        &self,
+        req: Request<Body>,
+        extensions: &mut Extensions,
+    ) -> Result<(SoapMessage, Option<String>, Vec<BodySource>), ParseError> {
+        let content_type = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .map(|c| c.to_str().unwrap_or_default().to_string());
+        let multipart = content_type
+            .as_deref()
+            .map(media_type_parameters)
+            .filter(|(media_type, _)| media_type == "multipart/related");
+        let (hint, mut action) = match (&multipart, content_type) {
+            (_, None) => (SoapVersion::default(), None),
+            (Some((_, params)), _) => {
+                let start_info = params
+                    .iter()
+                    .find(|(name, _)| name == "start-info")
+                    .and_then(|(_, value)| ContentType::parse(value));
+                match start_info {
+                    Some(c) => (c.version, c.action),
+                    None => (SoapVersion::default(), None),
+                }
+            }
+            (None, Some(content_type)) => match ContentType::parse(&content_type) {
+                Some(c) if c.is_supported_charset() => (c.version, c.action),
+                Some(c) => return Err(ParseError::UnsupportedMediaType(c.version)),
+                None => return Err(ParseError::UnsupportedMediaType(SoapVersion::default())),
+            },
+        };
+        if hint == SoapVersion::Soap11 {
+            action = req
+                .headers()
+                .get("SOAPAction")
+                .and_then(|a| a.to_str().ok())
+                .map(|a| a.trim().trim_matches('"').to_string())
+                .filter(|a| !a.is_empty());
+        }
+        let encoding = match req.headers().get(CONTENT_ENCODING) {
+            None => ContentEncoding::Identity,
+            Some(value) => match value.to_str().ok().and_then(ContentEncoding::parse) {
+                Some(e) if e == ContentEncoding::Identity || self.compression => e,
+                _ => return Err(ParseError::UnsupportedEncoding(hint)),
+            },
+        };
+        let body = match multipart {
+            Some(_) if encoding != ContentEncoding::Identity => {
+                return Err(ParseError::UnsupportedEncoding(hint))
+            }
+            Some((_, params)) => {
+                let boundary = params
+                    .into_iter()
+                    .find(|(name, _)| name == "boundary")
+                    .map(|(_, value)| value)
+                    .ok_or(ParseError::Malformed(hint))?;
+                let mut reader = MultipartReader::new(req.into_body(), &boundary);
+                // The root part comes first, the start parameter is not
+                // supported.
+                reader
+                    .next_part()
+                    .await
+                    .ok()
+                    .flatten()
+                    .ok_or(ParseError::Malformed(hint))?;
+                let mut envelope = vec![];
+                while let Some(chunk) = reader
+                    .chunk()
+                    .await
+                    .map_err(|_| ParseError::Malformed(hint))?
+                {
+                    if envelope.len() + chunk.len() > self.body_limit {
+                        return Err(ParseError::TooLarge(hint));
+                    }
+                    envelope.extend_from_slice(&chunk);
+                }
+                extensions.insert(IncomingAttachments(Arc::new(Mutex::new(Some(reader)))));
+                Bytes::from(envelope)
+            }
+            None => {
+                let length = req
+                    .headers()
+                    .get(CONTENT_LENGTH)
+                    .and_then(|l| l.to_str().ok())
+                    .and_then(|l| l.parse::<usize>().ok());
+                if length.is_some_and(|l| l > self.body_limit) {
+                    return Err(ParseError::TooLarge(hint));
+                }
+                let mut body = req.into_body();
+                let mut buf = vec![];
+                while let Some(chunk) = body.next().await {
+                    let chunk = chunk.map_err(|_| ParseError::Malformed(hint))?;
+                    if buf.len() + chunk.len() > self.body_limit {
+                        return Err(ParseError::TooLarge(hint));
+                    }
+                    buf.extend_from_slice(&chunk);
+                }
+                match encoding.decode(&buf, self.body_limit) {
+                    Ok(decoded) => Bytes::from(decoded),
+                    Err(DecodeError::TooLarge) => return Err(ParseError::TooLarge(hint)),
+                    Err(DecodeError::Invalid) => return Err(ParseError::Malformed(hint)),
+                }
+            }
+        };
+        #[cfg(feature = "trace-envelopes")]
+        tracing::debug!(
+            envelope = %String::from_utf8_lossy(&body),
+            "SOAP request envelope"
+        );
+        match check_document(&body, self.max_depth) {
+            Ok(()) => (),
+            Err(DocumentError::TooDeep) => return Err(ParseError::TooDeep(hint)),
+            Err(DocumentError::Invalid) => return Err(ParseError::Malformed(hint)),
+        }
+        let xml_body =
+            xmltree::Element::parse(body.as_ref()).map_err(|_| ParseError::Malformed(hint))?;
+        extensions.insert(RawEnvelope(body.clone()));
+        let version = match xml_body
+            .namespace
+            .as_deref()
+            .and_then(SoapVersion::from_namespace)
+        {
+            Some(v) if xml_body.name == "Envelope" => v,
+            _ if xml_body.name == "Envelope" => return Err(ParseError::VersionMismatch),
+            _ => return Err(ParseError::Malformed(hint)),
+        };
+        if xml_body.get_child(("Body", version.namespace())).is_none() {
+            return Err(ParseError::Malformed(version));
+        }
+        Ok((xml_body.into(), action, body_sources(&body)))
+    }
+
+    async fn call_internal(&self, req: Request<Body>) -> Result<Response, Infallible> {
+        let peer = req.extensions().get::<ConnectInfo<SocketAddr>>().copied();
+        let origin = RequestOrigin::from_headers(
+            req.headers(),
+            peer.map(|p| p.0.ip()),
+            &self.trusted_proxies,
+        );
+        let uris = Uris::new(origin.clone(), self.uri_builder.clone());
+        if let Some(wsdl) = self.wsdl.as_ref().filter(|_| is_wsdl_request(&req)) {
+            return Ok(wsdl_response(&wsdl(), &req, &uris));
+        }
+        // Probes from clients and load balancers, there is no envelope to parse
+        if req.method() == Method::OPTIONS || req.method() == Method::HEAD {
+            let allow = if self.wsdl.is_some() {
+                "POST, GET, HEAD, OPTIONS"
+            } else {
+                "POST, HEAD, OPTIONS"
+            };
+            let status = match *req.method() {
+                Method::OPTIONS => StatusCode::NO_CONTENT,
+                _ => StatusCode::OK,
+            };
+            return Ok((status, [(ALLOW, allow)]).into_response());
+        }
+        let languages = req
+            .headers()
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|h| h.to_str().ok())
+            .map(parse_accept_language)
+            .unwrap_or_default();
+        let response_encoding = req
+            .headers()
+            .get(ACCEPT_ENCODING)
+            .and_then(|h| h.to_str().ok())
+            .and_then(ContentEncoding::negotiate)
+            .filter(|_| self.compression);
+        let mut extensions = Extensions::default();
+        if let Some(info) = peer {
+            extensions.insert(info);
+        }
+        if let Some(origin) = origin {
+            extensions.insert(origin);
+        }
+        extensions.insert(uris);
+        if let Some(user) = req.extensions().get::<AuthenticatedUser>() {
+            Span::current().record("user", user.0.as_str());
+            extensions.insert(user.clone());
+        }
+        if let Some(marker) = req.extensions().get::<PreAuthOnly>() {
+            extensions.insert(*marker);
+        }
+        let cancellation = Cancellation::new(None);
+        let _cancel_on_drop = cancellation.guard();
+        extensions.insert(cancellation);
+        let (soap_req, http_action, body_sources) = match self
+            .parse_request(req, &mut extensions)
+            .await
+        {
+            Ok(r) => r,
+            Err(e) => return Ok(e.into_response(&languages, self.compression, &self.fault_log)),
+        };
+        if !self.request_hooks.is_empty() {
+            let mut redacted = SoapMessage::from(soap_req.envelope().clone());
+            redact_credentials(redacted.envelope_mut());
+            self.request_hooks.iter().for_each(|hook| hook(&redacted));
+        }
+        let version = soap_req.version();
+        let operation = soap_req
+            .get_body()
+            .children
+            .iter()
+            .find_map(|c| c.as_element())
+            .map(|e| QName {
+                namespace: e.namespace.clone().unwrap_or_default(),
+                name: e.name.clone(),
+            });
+        if let Some(operation) = &operation {
+            Span::current().record("operation", operation.to_string());
+        }
+        let soap_headers = match soap_req.get_headers() {
+            None => {
+                let mut e = Element::new("Header");
+                e.namespace = Some(version.namespace().to_string());
+                e
+            }
+            Some(h) => h.clone(),
+        };
+        let addressing = AddressingHeaders::from_headers(&soap_headers);
+        if let Some(message_id) = &addressing.message_id {
+            Span::current().record("message_id", message_id.as_str());
+        }
+        let response_attachments = ResponseAttachments::default();
+
+        // Only trust the ReplyTo and FaultTo addresses once the policy
+        // accepted them
+        let not_understood = not_understood(&soap_headers, version, &self.understood);
+        let (outcome, trusted) = match self.address_policy.check(&addressing) {
+            _ if !not_understood.is_empty() => (Err(must_understand_fault()), false),
+            Err(fault) => (Err(fault), false),
+            Ok(()) => (
+                self.dispatch(
+                    &soap_req,
+                    &soap_headers,
+                    // WS-Addressing takes precedence over the HTTP binding
+                    addressing.action.as_deref().or(http_action.as_deref()),
+                    &languages,
+                    &response_attachments,
+                    &body_sources,
+                    &extensions,
+                )
+                .await,
+                true,
+            ),
+        };
+        let (mut msg, status) = match outcome {
+            Ok(msg) => {
+                Span::current().record("outcome", "ok");
+                (msg, None)
+            }
+            Err(fault) => {
+                self.fault_log.record(&fault, operation);
+                let status = fault.status(version);
+                (fault.into_message(version, &languages), Some(status))
+            }
+        };
+        let is_fault = status.is_some();
+        if !is_fault && msg.is_no_content() {
+            return Ok(StatusCode::ACCEPTED.into_response());
+        }
+        if !not_understood.is_empty() {
+            report(msg.get_mut_headers(), version, &not_understood);
+        }
+        let message_id = format!("urn:uuid:{}", uuid(self.entropy.as_ref()));
+        stamp_reply(&mut msg, &addressing, is_fault, message_id);
+        // Faults go to FaultTo when given, to ReplyTo otherwise
+        let destination = match (is_fault, &addressing.fault_to) {
+            (true, Some(fault_to)) => Some(fault_to),
+            _ => addressing.reply_to.as_ref(),
+        }
+        .filter(|address| trusted && *address != WSA_ANONYMOUS)
+        .map(|address| {
+            let action = msg
+                .get_headers()
+                .and_then(|h| h.get_child(("Action", WSA_NAMESPACE)))
+                .and_then(|a| a.get_text())
+                .unwrap_or_default()
+                .to_string();
+            (address.clone(), action)
+        });
+        let msg = convert_envelope(msg.into_envelope(), version);
+        if !self.response_hooks.is_empty() {
+            let mut redacted = SoapMessage::from(msg.clone());
+            redact_credentials(redacted.envelope_mut());
+            self.response_hooks.iter().for_each(|hook| hook(&redacted));
+        }
+
+        let mut buf = vec![].writer();
+        msg.write(buf.by_ref()).unwrap();
+        #[cfg(feature = "trace-envelopes")]
+        tracing::debug!(
+            envelope = %String::from_utf8_lossy(buf.get_ref()),
+            "SOAP response envelope"
+        );
+        let attachments = response_attachments.take();
+        if let Some((address, action)) = destination {
+            if address != WSA_NONE {
+                let message = match attachments.is_empty() || is_fault {
+                    true => message_response(buf.into_inner(), version),
+                    false => {
+                        let boundary = format!("uuid:{}", uuid(self.entropy.as_ref()));
+                        multipart_response(&boundary, version, buf.into_inner(), attachments)
+                    }
+                };
+                tokio::spawn(deliver(address, action, version, message));
+            }
+            return Ok(StatusCode::ACCEPTED.into_response());
+        }
+        if !attachments.is_empty() && !is_fault {
+            let boundary = format!("uuid:{}", uuid(self.entropy.as_ref()));
+            return Ok(multipart_response(
+                &boundary,
+                version,
+                buf.into_inner(),
+                attachments,
+            ));
+        }
+        let body = buf.into_inner();
+        let mut response = match response_encoding.filter(|_| body.len() >= MIN_COMPRESSED_SIZE) {
+            Some(encoding) => {
+                let mut response = message_response(encoding.encode(&body), version);
+                response.headers_mut().insert(
+                    CONTENT_ENCODING,
+                    HeaderValue::from_static(encoding.as_str()),
+                );
+                response
+            }
+            None => message_response(body, version),
+        };
+        if self.compression {
+            response
+                .headers_mut()
+                .insert(VARY, HeaderValue::from_static("accept-encoding"));
+        }
+        Ok(match status {
+            Some(status) => (status, response).into_response(),
+            None => response,
+        })
+    }
+
+    fn process_headers(
+        &self,
+        headers: &Element,
+        request: &mut SoapRequest,
+    ) -> Result<(), SoapFault> {
+        for (qname, processor) in self.header_processors.iter() {
+            for block in headers
+                .children
+                .iter()
+                .filter_map(|c| c.as_element())
+                .filter(|b| {
+                    b.name == qname.name && b.namespace.as_deref() == Some(&qname.namespace)
+                })
+            {
+                processor.process(block, request)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the operations of the body following the multi-element policy,
+    /// the responses are merged in the order of the request body elements
+    /// whatever the order they complete in, and the first fault in that
+    /// order wins.
+    #[allow(clippy::too_many_arguments)]
+    async fn dispatch(
+        &self,
+        soap_req: &SoapMessage,
+        soap_headers: &Element,
+        action: Option<&str>,
+        languages: &[isolang::Language],
+        response_attachments: &ResponseAttachments,
+        body_sources: &[BodySource],
+        extensions: &Extensions,
+    ) -> Result<SoapMessage, SoapFault> {
+        let version = soap_req.version();
+        let soap_body = soap_req.get_body();
+        if self.multi_element_policy == MultiElementPolicy::RejectMultiple
+            && soap_body
+                .children
+                .iter()
+                .filter(|c| c.as_element().is_some())
+                .count()
+                > 1
+        {
+            return Err(OnvifFault::InvalidArgs(None).into());
+        }
+        // Body entries are matched with their raw bytes by position
+        let source = |index: usize, elem: &Element| {
+            body_sources.get(index).filter(|s| s.matches(elem)).cloned()
+        };
+        let first_element = || {
+            let elem = soap_body
+                .children
+                .iter()
+                .find_map(|c| c.as_element())
+                .unwrap_or(soap_body);
+            let operation = QName {
+                namespace: elem.namespace.clone().unwrap_or_default(),
+                name: elem.name.clone(),
+            };
+            (operation, elem, source(0, elem))
+        };
+
+        let mut operations: Vec<DispatchedOperation> =
+            match action.and_then(|a| self.action_routes.get(a)) {
+                Some(route) => {
+                    let (operation, elem, source) = first_element();
+                    vec![(operation, elem, source, &route.service, Some(&route.info))]
+                }
+                None => soap_body
+                    .children
+                    .iter()
+                    .filter_map(|c| c.as_element())
+                    .enumerate()
+                    .filter_map(|(index, elem)| {
+                        let operation = QName {
+                            namespace: elem.namespace.clone().unwrap_or_default(),
+                            name: elem.name.clone(),
+                        };
+                        self.routes.get(&operation).map(|route| {
+                            let source = source(index, elem);
+                            (operation, elem, source, &route.service, Some(&route.info))
+                        })
+                    })
+                    .collect(),
+            };
+        if operations.is_empty() {
+            // No known operation, hand the first element to the fallback
+            match &self.fallback {
+                Some(handler) => {
+                    let (operation, elem, source) = first_element();
+                    operations.push((operation, elem, source, handler, None))
+                }
+                None => return Err(procedure_not_present()),
+            }
+        }
+
+        // Header processors aren't idempotent (nonce caches...), they run once
+        // for the whole envelope and every operation sees the headers and
+        // extensions they produced.
+        let envelope_headers = ResponseHeaders::default();
+        let mut processed = SoapRequest {
+            headers: soap_headers.clone(),
+            body: operations[0].1.clone(),
+            languages: languages.to_vec(),
+            response_headers: envelope_headers.clone(),
+            response_attachments: response_attachments.clone(),
+            version,
+            body_source: operations[0].2.clone(),
+            extensions: extensions.clone(),
+        };
+        self.process_headers(soap_headers, &mut processed)?;
+        if let Some(user) = processed.extensions.get::<AuthenticatedUser>() {
+            Span::current().record("user", user.0.as_str());
+        }
+
+        let mut calls = vec![];
+        let mut first = Some((processed.body, processed.body_source));
+        for (operation, elem, body_source, handler, info) in operations {
+            let interceptors: Vec<Arc<dyn Interceptor>> = self
+                .interceptors
+                .iter()
+                .filter(|(ns, _)| ns.as_ref().is_none_or(|ns| *ns == operation.namespace))
+                .map(|(_, i)| i.clone())
+                .collect();
+            let response_headers = ResponseHeaders::default();
+            let (body, body_source) = first.take().unwrap_or_else(|| (elem.clone(), body_source));
+            let mut request = SoapRequest {
+                headers: processed.headers.clone(),
+                body,
+                languages: languages.to_vec(),
+                response_headers: response_headers.clone(),
+                response_attachments: response_attachments.clone(),
+                version,
+                body_source,
+                extensions: processed.extensions.clone(),
+            };
+            if let Some(info) = info {
+                request.extensions.insert(info.clone());
+            }
+            let timeout = info.and_then(|i| i.timeout).or(self.timeout);
+            if let Some(timeout) = timeout {
+                let cancellation = request
+                    .extensions
+                    .get::<Cancellation>()
+                    .cloned()
+                    .unwrap_or_default()
+                    .with_deadline(Instant::now() + timeout);
+                request.extensions.insert(cancellation);
+            }
+            // Rejected credentials only give access to the PreAuth operations
+            let rejected = request.extensions.get::<PreAuthOnly>().is_some()
+                && info.and_then(route_access) != Some(AccessClass::PreAuth);
+            calls.push(async move {
+                let call = match rejected {
+                    true => Err(not_authorized()),
+                    false => interceptors
+                        .iter()
+                        .try_for_each(|i| i.before(&operation, &request.headers)),
+                }
+                .map(|_| -> BoxedSoapFuture {
+                    match timeout {
+                        Some(timeout) => {
+                            let call = Timeout::new(handler.clone(), timeout).oneshot(request);
+                            Box::pin(async move {
+                                call.await.map_err(|e| match e.downcast::<SoapFault>() {
+                                    Ok(fault) => *fault,
+                                    Err(_) => operation_timed_out(),
+                                })
+                            })
+                        }
+                        None => Box::pin(handler.clone().oneshot(request)),
+                    }
+                });
+                let result = match call {
+                    Ok(call) => call.await.map(|mut msg| {
+                        let headers = response_headers.take();
+                        if !headers.is_empty() {
+                            msg.get_mut_headers()
+                                .children
+                                .extend(headers.into_iter().map(xmltree::XMLNode::Element));
+                        }
+                        msg
+                    }),
+                    Err(e) => Err(e),
+                };
+                interceptors
+                    .iter()
+                    .for_each(|i| i.after(&operation, &result));
+                result
+            });
+        }
+        let results = match self.multi_element_policy {
+            MultiElementPolicy::ProcessOrdered => {
+                let mut results = vec![];
+                for call in calls {
+                    let result = call.await;
+                    let failed = result.is_err();
+                    results.push(result);
+                    if failed {
+                        break;
+                    }
+                }
+                results
+            }
+            _ => {
+                calls
+                    .into_iter()
+                    .collect::<FuturesOrdered<_>>()
+                    .collect::<Vec<_>>()
+                    .await
+            }
+        };
+        let soap_reponses = results
+            .into_iter()
+            .collect::<Result<Vec<SoapMessage>, SoapFault>>()?;
+
+        // One-way only when all the operations are
+        let no_content = soap_reponses
+            .iter()
+            .all(SoapMessage::is_no_content)
+            .then_some(NoContent);
+        let mut msg = SoapMessage {
+            envelope: soap_reponses
+                .into_iter()
+                .map(SoapMessage::into_envelope)
+                .reduce(merge_soap_enveloppe)
+                .unwrap(),
+            no_content,
+        };
+        let headers = envelope_headers.take();
+        if !headers.is_empty() {
+            msg.get_mut_headers()
+                .children
+                .extend(headers.into_iter().map(xmltree::XMLNode::Element));
+        }
+        Ok(msg)
+    }
+}
+
+fn operation_timed_out() -> SoapFault {
+    SoapFault::builder(SoapFaultCode::Receiver)
+        .subcode(url::Url::parse(ONVIF_ERROR_NAMESPACE).unwrap(), "Action")
+        .builtin_reason("OperationTimedOut")
+        .build()
+}
+
+fn action_not_supported() -> SoapFault {
+    OnvifFault::ActionNotSupported(None).into()
+}
+
+fn message_response(body: Vec<u8>, version: SoapVersion) -> Response {
+    ([(CONTENT_TYPE, ContentType::response(version))], body).into_response()
+}
+
+const WSDL_SOAP11_NAMESPACE: &str = "http://schemas.xmlsoap.org/wsdl/soap/";
+const WSDL_SOAP12_NAMESPACE: &str = "http://schemas.xmlsoap.org/wsdl/soap12/";
+
+fn is_wsdl_request(req: &Request<Body>) -> bool {
+    (req.method() == Method::GET || req.method() == Method::HEAD)
+        && req
+            .uri()
+            .query()
+            .is_some_and(|q| q.split('&').any(|p| p.eq_ignore_ascii_case("wsdl")))
+}
+
+fn wsdl_response(wsdl: &str, req: &Request<Body>, uris: &Uris) -> Response {
+    let location = uris
+        .build(UriKind::Service, req.uri().path())
+        .map(|url| url.to_string());
+    let body = match (location, Element::parse(wsdl.as_bytes())) {
+        (Some(location), Ok(mut doc)) => {
+            set_address_location(&mut doc, &location);
+            let mut buf = vec![].writer();
+            doc.write(buf.by_ref()).unwrap();
+            buf.into_inner()
+        }
+        _ => wsdl.as_bytes().to_vec(),
+    };
+    ([(CONTENT_TYPE, "text/xml; charset=utf-8")], body).into_response()
+}
+
+fn set_address_location(elem: &mut Element, location: &str) {
+    let is_address = elem.name == "address"
+        && matches!(
+            elem.namespace.as_deref(),
+            Some(WSDL_SOAP11_NAMESPACE) | Some(WSDL_SOAP12_NAMESPACE)
+        );
+    if is_address {
+        elem.attributes
+            .insert("location".to_string(), location.to_string());
+    }
+    elem.children
+        .iter_mut()
+        .filter_map(|c| c.as_mut_element())
+        .for_each(|c| set_address_location(c, location));
+}
+
+/// Reasons a request could not be read as a SOAP envelope, along with the
+/// version to answer with.
+#[derive(Debug)]
+enum ParseError {
+    UnsupportedMediaType(SoapVersion),
+    Malformed(SoapVersion),
+    TooLarge(SoapVersion),
+    TooDeep(SoapVersion),
+    UnsupportedEncoding(SoapVersion),
+    VersionMismatch,
+}
+
+impl ParseError {
+    fn into_response(
+        self,
+        languages: &[isolang::Language],
+        compression: bool,
+        fault_log: &FaultLog,
+    ) -> Response {
+        let unsupported_encoding = matches!(self, ParseError::UnsupportedEncoding(_));
+        let (status, version, fault) = match self {
+            ParseError::UnsupportedMediaType(version) => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                version,
+                invalid_message("UnsupportedMediaType"),
+            ),
+            ParseError::Malformed(version) => (
+                StatusCode::BAD_REQUEST,
+                version,
+                invalid_message("MalformedMessage"),
+            ),
+            ParseError::TooLarge(version) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                version,
+                invalid_message("LimitExceeded"),
+            ),
+            ParseError::UnsupportedEncoding(version) => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                version,
+                invalid_message("UnsupportedMediaType"),
+            ),
+            ParseError::TooDeep(version) => (
+                StatusCode::BAD_REQUEST,
+                version,
+                invalid_message("LimitExceeded"),
+            ),
+            ParseError::VersionMismatch => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                SoapVersion::Soap12,
+                SoapFault::builder(SoapFaultCode::VersionMismatch)
+                    .builtin_reason("VersionMismatch")
+                    .build(),
+            ),
+        };
+        fault_log.record(&fault, None);
+        let msg = convert_envelope(
+            fault.into_message(version, languages).into_envelope(),
+            version,
+        );
+        let mut buf = vec![].writer();
+        msg.write(buf.by_ref()).unwrap();
+        #[cfg(feature = "trace-envelopes")]
+        tracing::debug!(
+            envelope = %String::from_utf8_lossy(buf.get_ref()),
+            "SOAP response envelope"
+        );
+        let mut response = (status, message_response(buf.into_inner(), version)).into_response();
+        if unsupported_encoding {
+            let accepted = match compression {
+                true => "gzip, deflate",
+                false => "identity",
+            };
+            response
+                .headers_mut()
+                .insert(ACCEPT_ENCODING, HeaderValue::from_static(accepted));
+        }
+        response
+    }
+}
+
+fn procedure_not_present() -> SoapFault {
+    SoapFault::builder(SoapFaultCode::Sender)
+        .subcode(
+            url::Url::parse("http://www.w3.org/2003/05/soap-rpc").unwrap(),
+            "ProcedureNotPresent",
+        )
+        .builtin_reason("ProcedureNotPresent")
+        .build()
+}
+
+/// Merge the Header and Body blocks of `element` into those of
+/// `accumulator`. All the namespaces end up declared on the accumulator
+/// root, the prefixes of `element` being renamed when taken by another
+/// namespace there.
+fn merge_soap_enveloppe(mut accumulator: Element, element: Element) -> Element {
+    let mut table = accumulator
+        .namespaces
+        .take()
+        .unwrap_or_else(Namespace::empty);
+    // Unprefixed elements would otherwise move to the default namespace
+    table.0.remove(NS_NO_PREFIX);
+    adopt_namespaces(&mut accumulator, &mut table, &Namespace::empty());
+    let envelope_namespace = accumulator.namespace.clone().unwrap_or_default();
+    let envelope_prefix = accumulator.prefix.clone();
+    for (name, position) in [("Header", 0), ("Body", usize::MAX)] {
+        let Some(source) = element.get_child((name, envelope_namespace.as_str())) else {
+            continue;
+        };
+        let mut blocks = source.children.clone();
+        if blocks.iter().all(|b| b.as_element().is_none()) {
+            continue;
+        }
+        if accumulator
+            .get_child((name, envelope_namespace.as_str()))
+            .is_none()
+        {
+            let mut container = Element::new(name);
+            container.prefix = envelope_prefix.clone();
+            container.namespace = Some(envelope_namespace.clone());
+            let position = position.min(accumulator.children.len());
+            accumulator
+                .children
+                .insert(position, XMLNode::Element(container));
+        }
+        let container = accumulator
+            .get_mut_child((name, envelope_namespace.as_str()))
+            .unwrap();
+        // Declarations in scope of the blocks in their own envelope, and the
+        // ones the container keeps in the merged one
+        let mut inherited = element.namespaces.clone().unwrap_or_else(Namespace::empty);
+        inherited
+            .0
+            .extend(source.namespaces.iter().flat_map(|n| n.0.clone()));
+        let local = container
+            .namespaces
+            .clone()
+            .unwrap_or_else(Namespace::empty);
+        for block in blocks.iter_mut() {
+            if let XMLNode::Element(block) = block {
+                let mut scope = inherited.clone();
+                scope
+                    .0
+                    .extend(block.namespaces.take().into_iter().flat_map(|n| n.0));
+                block.namespaces = Some(scope);
+                adopt_namespaces(block, &mut table, &local);
+            }
+        }
+        container.children.extend(blocks);
+    }
+    accumulator.namespaces = Some(table);
+    accumulator
+}
+
+/// Lift the namespace declarations in scope of the tree to `table` and give
+/// the elements a prefix bound to their namespace. Declarations clashing
+/// with the table stay on their element, so that the prefixes of QName
+/// values such as `xsi:type` keep their meaning, `local` holds the ones kept
+/// on the ancestors.
+fn adopt_namespaces(elem: &mut Element, table: &mut Namespace, local: &Namespace) {
+    let stack = local.0.clone().into_iter().collect();
+    adopt_scoped_namespaces(elem, table, local, &stack)
+}
+
+fn adopt_scoped_namespaces(
+    elem: &mut Element,
+    table: &mut Namespace,
+    local: &Namespace,
+    // Bindings declared by the ancestors, the writer doesn't declare again a
+    // binding hidden by another one
+    stack: &BTreeSet<(String, String)>,
+) {
+    let mut local = local.clone();
+    let mut stack = stack.clone();
+    let mut declared = Namespace::empty();
+    for (prefix, uri) in elem.namespaces.take().into_iter().flat_map(|n| n.0) {
+        if [NS_NO_PREFIX, NS_XML_PREFIX, NS_XMLNS_PREFIX].contains(&prefix.as_str()) {
+            continue;
+        }
+        match local.get(&prefix).or_else(|| table.get(&prefix)) {
+            Some(bound) if bound == uri => (),
+            Some(_) => {
+                let binding = (prefix, uri);
+                if table.get(&binding.0) != Some(binding.1.as_str())
+                    && stack.insert(binding.clone())
+                {
+                    local.force_put(binding.0.clone(), binding.1.clone());
+                    declared.force_put(binding.0, binding.1);
+                }
+            }
+            None => {
+                table.put(prefix, uri);
+            }
+        }
+    }
+    elem.prefix = elem
+        .namespace
+        .as_deref()
+        .filter(|namespace| !namespace.is_empty())
+        .map(|namespace| declared_prefix(table, &local, namespace, elem.prefix.as_deref()));
+    elem.namespaces = (!declared.is_empty()).then_some(declared);
+    for child in elem.children.iter_mut() {
+        if let XMLNode::Element(child) = child {
+            adopt_scoped_namespaces(child, table, &local, &stack);
+        }
+    }
+}
+
+/// Prefix bound to `namespace` where `local` overrides `table`, declaring a
+/// new one in `table` if needed
+fn declared_prefix(
+    table: &mut Namespace,
+    local: &Namespace,
+    namespace: &str,
+    preferred: Option<&str>,
+) -> String {
+    let bound = |prefix: &str| local.get(prefix).or_else(|| table.get(prefix));
+    let usable = |prefix: &&str| !prefix.is_empty() && *prefix != NS_XMLNS_PREFIX;
+    if let Some(prefix) = preferred
+        .into_iter()
+        .chain(local.0.keys().map(String::as_str))
+        .chain(table.0.keys().map(String::as_str))
+        .filter(usable)
+        .find(|prefix| bound(prefix) == Some(namespace))
+    {
+        return prefix.to_string();
+    }
+    let prefix = preferred
+        .filter(|p| bound(p).is_none() && *p != NS_XML_PREFIX && *p != NS_XMLNS_PREFIX)
+        .map(|p| p.to_string())
+        .unwrap_or_else(|| {
+            (0..)
+                .map(|i| format!("ns{}", i))
+                .find(|p| bound(p).is_none())
+                .unwrap()
+        });
+    table.put(prefix.clone(), namespace);
+    prefix
+}
+
+impl<S> Service<Request<Body>> for SoapRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let cs = self.clone();
+        let span = tracing::info_span!(
+            "soap_request",
+            operation = tracing::field::Empty,
+            message_id = tracing::field::Empty,
+            user = tracing::field::Empty,
+            outcome = tracing::field::Empty,
+        );
+        Box::pin(async move { cs.call_internal(req).await }.instrument(span))
+    }
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::extract::{FromRef, RawBody, ResponseHeaders, State};
+    use crate::mtom::ResponseAttachments;
+    use axum::http::header::HOST;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_merge_xml() {
+        let xml1_raw = r#"<?xml version="1.0" ?>
+        <soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+            <soap:Header>
+            </soap:Header>
+            <soap:Body>
+                <m:GetStockPrice>
+                    <m:StockName>T</m:StockName>
+                </m:GetStockPrice>
+            </soap:Body>
+        </soap:Envelope>
+        "#;
+
+        let xml2_raw = r#"<?xml version="1.0" ?>
+        <soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+            <soap:Header>
+            </soap:Header>
+            <soap:Body>
+                <m:GetStockPrice>
+                    <m:StockName>Y</m:StockName>
+                </m:GetStockPrice>
+            </soap:Body>
+        </soap:Envelope>
+        "#;
+        let expected_raw = r#"<?xml version="1.0"?>
+        <soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+            <soap:Header>
+            </soap:Header>
+            <soap:Body>
+                <m:GetStockPrice>
+                    <m:StockName>T</m:StockName>
+                </m:GetStockPrice>
+                <m:GetStockPrice>
+                    <m:StockName>Y</m:StockName>
+                </m:GetStockPrice>
+            </soap:Body>
+        </soap:Envelope>
+        "#;
+
+        let xml1 = Element::parse(xml1_raw.as_bytes()).unwrap();
+        let xml2 = Element::parse(xml2_raw.as_bytes()).unwrap();
+        let expected = Element::parse(expected_raw.as_bytes()).unwrap();
+
+        let mut buf = vec![].writer();
+        merge_soap_enveloppe(xml1, xml2)
+            .write(buf.by_ref())
+            .unwrap();
+        let merged = Element::parse(buf.into_inner().as_slice()).unwrap();
+        assert_eq!(merged, expected)
+    }
+
+    #[test]
+    fn test_merge_qname_values() {
+        const XSI: &str = "http://www.w3.org/2001/XMLSchema-instance";
+        let first = r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:tt="urn:other">
+                <env:Body><tt:Reply/></env:Body>
+            </env:Envelope>"#;
+        let second = format!(
+            r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:xsi="{XSI}" xmlns:tt="http://www.onvif.org/ver10/schema" xmlns:m="http://www.example.org">
+                <env:Body>
+                    <m:Reply><m:Config xsi:type="tt:VideoEncoderConfiguration"/></m:Reply>
+                </env:Body>
+            </env:Envelope>"#
+        );
+        let merged = merge_soap_enveloppe(
+            Element::parse(first.as_bytes()).unwrap(),
+            Element::parse(second.as_bytes()).unwrap(),
+        );
+        let mut buf = vec![].writer();
+        merged.write(buf.by_ref()).unwrap();
+        let merged = SoapMessage::from(Element::parse(buf.into_inner().as_slice()).unwrap());
+
+        let config = merged
+            .get_body()
+            .get_child(("Reply", "http://www.example.org"))
+            .and_then(|r| r.get_child(("Config", "http://www.example.org")))
+            .unwrap();
+        let value = config.attributes.get("type").unwrap();
+        let (prefix, _) = value.split_once(':').unwrap();
+        let scope = config.namespaces.as_ref().unwrap();
+        assert_eq!(scope.get(prefix), Some("http://www.onvif.org/ver10/schema"));
+        assert!(scope.0.iter().any(|(_, uri)| uri == XSI));
+        assert!(merged
+            .get_body()
+            .get_child(("Reply", "urn:other"))
+            .is_some());
+    }
+
+    #[test]
+    fn test_merge_namespaces() {
+        use crate::version::SOAP12_NAMESPACE;
+
+        const PREFIXES: &[&str] = &["a", "b", "m"];
+        const NAMESPACES: &[&str] = &["urn:a", "urn:b", "urn:c", "urn:d"];
+
+        // Xorshift, to explore random envelopes reproducibly
+        struct Rng(u64);
+        impl Rng {
+            fn below(&mut self, n: usize) -> usize {
+                self.0 ^= self.0 << 13;
+                self.0 ^= self.0 >> 7;
+                self.0 ^= self.0 << 17;
+                (self.0 % n as u64) as usize
+            }
+            fn pick<'a>(&mut self, values: &[&'a str]) -> &'a str {
+                values[self.below(values.len())]
+            }
+        }
+
+        fn random_element(
+            rng: &mut Rng,
+            scope: &mut Vec<(String, String)>,
+            depth: usize,
+        ) -> String {
+            let declaration = match rng.below(3) {
+                0 => {
+                    let (prefix, ns) = (rng.pick(PREFIXES), rng.pick(NAMESPACES));
+                    scope.push((prefix.to_string(), ns.to_string()));
+                    format!(r#" xmlns:{}="{}""#, prefix, ns)
+                }
+                1 => {
+                    let ns = rng.pick(NAMESPACES);
+                    scope.push((String::new(), ns.to_string()));
+                    format!(r#" xmlns="{}""#, ns)
+                }
+                _ => String::new(),
+            };
+            let prefixes: Vec<String> = scope
+                .iter()
+                .map(|(p, _)| p.clone())
+                .filter(|p| !p.is_empty())
+                .collect();
+            let name = rng.pick(&["A", "B", "C"]);
+            let qname = match rng.below(prefixes.len() + 1) {
+                i if i < prefixes.len() => format!("{}:{}", prefixes[i], name),
+                _ => name.to_string(),
+            };
+            let mut children = String::new();
+            if depth < 3 {
+                for _ in 0..rng.below(3) {
+                    children.push_str(&random_element(rng, scope, depth + 1));
+                }
+            }
+            if !declaration.is_empty() {
+                scope.pop();
+            }
+            format!("<{0}{1}>{2}</{0}>", qname, declaration, children)
+        }
+
+        fn random_envelope(rng: &mut Rng) -> String {
+            let envelope = rng.pick(&["soap", "env", "a"]);
+            let mut scope = vec![(envelope.to_string(), SOAP12_NAMESPACE.to_string())];
+            let mut declarations = format!(r#"xmlns:{}="{}""#, envelope, SOAP12_NAMESPACE);
+            let prefix = rng.pick(PREFIXES);
+            if prefix != envelope {
+                let ns = rng.pick(NAMESPACES);
+                declarations.push_str(&format!(r#" xmlns:{}="{}""#, prefix, ns));
+                scope.push((prefix.to_string(), ns.to_string()));
+            }
+            let mut blocks = |rng: &mut Rng| {
+                (0..rng.below(3))
+                    .map(|_| random_element(rng, &mut scope, 0))
+                    .collect::<String>()
+            };
+            let header = match rng.below(2) {
+                0 => String::new(),
+                _ => format!("<{0}:Header>{1}</{0}:Header>", envelope, blocks(rng)),
+            };
+            format!(
+                "<{0}:Envelope {1}>{2}<{0}:Body>{3}</{0}:Body></{0}:Envelope>",
+                envelope,
+                declarations,
+                header,
+                blocks(rng)
+            )
+        }
+
+        fn qualified_names(elem: &Element, names: &mut Vec<(Option<String>, String)>) {
+            for child in elem.children.iter().filter_map(|c| c.as_element()) {
+                names.push((child.namespace.clone(), child.name.clone()));
+                qualified_names(child, names);
+            }
+        }
+
+        let container_names = |envelope: &Element, name: &str| {
+            let mut names = vec![];
+            if let Some(container) = envelope.get_child((name, SOAP12_NAMESPACE)) {
+                qualified_names(container, &mut names);
+            }
+            names
+        };
+
+        let mut rng = Rng(0x5eed);
+        for _ in 0..200 {
+            let envelopes: Vec<Element> = (0..2 + rng.below(2))
+                .map(|_| Element::parse(random_envelope(&mut rng).as_bytes()).unwrap())
+                .collect();
+            let merged = envelopes
+                .clone()
+                .into_iter()
+                .reduce(merge_soap_enveloppe)
+                .unwrap();
+            let mut buf = vec![].writer();
+            merged.write(buf.by_ref()).unwrap();
+            let merged = Element::parse(buf.into_inner().as_slice()).unwrap();
+            for name in ["Header", "Body"] {
+                let expected: Vec<_> = envelopes
+                    .iter()
+                    .flat_map(|e| container_names(e, name))
+                    .collect();
+                assert_eq!(container_names(&merged, name), expected);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_router() {
+        let mut router = SoapRouter::new(())
+            .add_operation("http://www.example.org".to_string(), "GetStockPrice".to_string(), || { async move { 
+                let xml1_raw = r#"<?xml version="1.0" ?>
+        <soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+            <soap:Header>
+            </soap:Header>
+            <soap:Body>
+                <m:GetStockPriceResponse>
+                    <m:StockPrice>3.60</m:StockPrice>
+                </m:GetStockPriceResponse>
+            </soap:Body>
+        </soap:Envelope>
+        "#;
+        Ok(Element::parse(xml1_raw.as_bytes()).unwrap())
+            }});
+
+        let in_raw = r#"<?xml version="1.0"?>
+            <soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                <soap:Header>
+                </soap:Header>
+                <soap:Body>
+                    <m:GetStockPrice>
+                        <m:StockName>T</m:StockName>
+                    </m:GetStockPrice>
                     <m:GetStockPrice>
                         <m:StockName>Y</m:StockName>
                     </m:GetStockPrice>
@@ -398,15 +2228,2355 @@ mod tests {
         "#;
         let req: Request<Body> = Request::builder()
             .uri("/")
-            .body(in_raw.as_bytes().into())
+            .body(in_raw.as_bytes().into())
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
+
+        assert!(resp.status().is_success());
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let xml_body = Element::parse(body.as_ref()).unwrap();
+        let expected = Element::parse(expected_raw.as_bytes()).unwrap();
+        assert_eq!(xml_body, expected)
+    }
+
+    #[derive(Clone)]
+    struct AppState {
+        price: PriceState,
+    }
+
+    #[derive(Clone)]
+    struct PriceState(String);
+
+    impl FromRef<AppState> for PriceState {
+        fn from_ref(input: &AppState) -> Self {
+            input.price.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_state_extractor() {
+        let state = AppState {
+            price: PriceState("4.20".to_string()),
+        };
+        let mut router = SoapRouter::new(state).add_operation(
+            "http://www.example.org".to_string(),
+            "GetStockPrice".to_string(),
+            |State(price): State<PriceState>| async move {
+                let raw = format!(
+                    r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                        <soap:Body><m:GetStockPriceResponse><m:StockPrice>{}</m:StockPrice></m:GetStockPriceResponse></soap:Body>
+                    </soap:Envelope>"#,
+                    price.0
+                );
+                Ok(Element::parse(raw.as_bytes()).unwrap())
+            },
+        );
+
+        let in_raw = r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                <soap:Body><m:GetStockPrice><m:StockName>T</m:StockName></m:GetStockPrice></soap:Body>
+            </soap:Envelope>"#;
+        let req: Request<Body> = Request::builder()
+            .uri("/")
+            .body(in_raw.as_bytes().into())
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
+        assert!(resp.status().is_success());
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let xml_body = Element::parse(body.as_ref()).unwrap();
+        let price = xml_body
+            .get_child(("Body", "http://www.w3.org/2003/05/soap-envelope"))
+            .and_then(|b| b.get_child("GetStockPriceResponse"))
+            .and_then(|r| r.get_child("StockPrice"))
+            .and_then(|p| p.get_text())
+            .unwrap();
+        assert_eq!(price, "4.20");
+    }
+
+    #[tokio::test]
+    async fn test_raw_body_extractor() {
+        let mut router = SoapRouter::new(()).add_operation(
+            "http://www.example.org".to_string(),
+            "Echo".to_string(),
+            |RawBody(body): RawBody| async move {
+                let mut msg = SoapMessage::new();
+                msg.get_mut_body()
+                    .children
+                    .push(xmltree::XMLNode::Element(body));
+                Ok(msg)
+            },
+        );
+
+        let in_raw = r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org" xmlns:v="http://vendor.example.org">
+                <soap:Body><m:Echo><v:Extension v:attr="1">data</v:Extension></m:Echo></soap:Body>
+            </soap:Envelope>"#;
+        let req: Request<Body> = Request::builder()
+            .uri("/")
+            .body(in_raw.as_bytes().into())
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
+        assert!(resp.status().is_success());
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let xml_body = Element::parse(body.as_ref()).unwrap();
+        let ext = xml_body
+            .get_child(("Body", "http://www.w3.org/2003/05/soap-envelope"))
+            .and_then(|b| b.get_child(("Echo", "http://www.example.org")))
+            .and_then(|e| e.get_child(("Extension", "http://vendor.example.org")))
+            .unwrap();
+        assert_eq!(ext.get_text().unwrap(), "data");
+        assert_eq!(ext.attributes.get("attr").unwrap(), "1");
+    }
+
+    #[tokio::test]
+    async fn test_response_headers() {
+        let mut router = SoapRouter::new(()).add_operation(
+            "http://www.example.org".to_string(),
+            "Ping".to_string(),
+            |headers: ResponseHeaders| async move {
+                let mut vendor = Element::new("Trace");
+                vendor.namespace = Some("http://vendor.example.org".to_string());
+                vendor.prefix = Some("v".to_string());
+                let mut ns = xmltree::Namespace::empty();
+                ns.put("v", "http://vendor.example.org");
+                vendor.namespaces = Some(ns);
+                vendor
+                    .children
+                    .push(xmltree::XMLNode::Text("42".to_string()));
+                headers.push(vendor);
+                Ok(SoapMessage::new())
+            },
+        );
+
+        let in_raw = r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                <soap:Body><m:Ping/></soap:Body>
+            </soap:Envelope>"#;
+        let req: Request<Body> = Request::builder()
+            .uri("/")
+            .body(in_raw.as_bytes().into())
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
+        assert!(resp.status().is_success());
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let xml_body = Element::parse(body.as_ref()).unwrap();
+        let trace = xml_body
+            .get_child(("Header", "http://www.w3.org/2003/05/soap-envelope"))
+            .and_then(|h| h.get_child(("Trace", "http://vendor.example.org")))
+            .unwrap();
+        assert_eq!(trace.get_text().unwrap(), "42");
+    }
+
+    struct Licensing {
+        after_calls: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl Interceptor for Licensing {
+        fn before(&self, operation: &QName, _headers: &Element) -> Result<(), SoapFault> {
+            if operation.name == "Unlicensed" {
+                return Err(SoapFault::builder(crate::fault::SoapFaultCode::Sender)
+                    .subcode(
+                        url::Url::parse("http://vendor.example.org").unwrap(),
+                        "NotLicensed",
+                    )
+                    .reason_en("Not licensed")
+                    .build());
+            }
+            Ok(())
+        }
+
+        fn after(&self, _operation: &QName, _result: &Result<SoapMessage, SoapFault>) {
+            self.after_calls
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_interceptor() {
+        let global_calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let other_calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut router = SoapRouter::new(())
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Licensed".to_string(),
+                || async move { Ok(SoapMessage::new()) },
+            )
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Unlicensed".to_string(),
+                || async move { Ok(SoapMessage::new()) },
+            )
+            .intercept(Licensing {
+                after_calls: global_calls.clone(),
+            })
+            .intercept_namespace(
+                "http://other.example.org".to_string(),
+                Licensing {
+                    after_calls: other_calls.clone(),
+                },
+            );
+
+        let in_raw = r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                <soap:Body><m:Licensed/></soap:Body>
+            </soap:Envelope>"#;
+        let req: Request<Body> = Request::builder()
+            .uri("/")
+            .body(in_raw.as_bytes().into())
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let xml_body = Element::parse(body.as_ref()).unwrap();
+        assert!(SoapMessage::from(xml_body)
+            .get_body()
+            .get_child(("Fault", "http://www.w3.org/2003/05/soap-envelope"))
+            .is_none());
+
+        let in_raw = r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                <soap:Body><m:Unlicensed/></soap:Body>
+            </soap:Envelope>"#;
+        let req: Request<Body> = Request::builder()
+            .uri("/")
+            .body(in_raw.as_bytes().into())
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let xml_body = Element::parse(body.as_ref()).unwrap();
+        let code = SoapMessage::from(xml_body)
+            .get_body()
+            .get_child(("Fault", "http://www.w3.org/2003/05/soap-envelope"))
+            .and_then(|f| f.get_child(("Code", "http://www.w3.org/2003/05/soap-envelope")))
+            .cloned()
+            .unwrap();
+        assert_eq!(
+            code.get_child("Value").unwrap().get_text().unwrap(),
+            "env:Sender"
+        );
+        let subcode = code
+            .get_child("Subcode")
+            .and_then(|s| s.get_child("Value"))
+            .and_then(|v| v.get_text())
+            .unwrap();
+        assert!(subcode.ends_with(":NotLicensed"));
+
+        assert_eq!(global_calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(other_calls.load(std::sync::atomic::Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_attachment() {
+        let mut router = SoapRouter::new(()).add_operation(
+            "http://www.example.org".to_string(),
+            "GetBackup".to_string(),
+            |attachments: ResponseAttachments| async move {
+                let chunks = futures::stream::iter([
+                    Ok::<_, std::io::Error>(Bytes::from_static(b"first chunk,")),
+                    Ok(Bytes::from_static(b"second chunk")),
+                ]);
+                let include = attachments.add("application/octet-stream", chunks);
+                let mut response = Element::new("GetBackupResponse");
+                response.namespace = Some("http://www.example.org".to_string());
+                response.children.push(xmltree::XMLNode::Element(include));
+                let mut msg = SoapMessage::new();
+                msg.get_mut_body()
+                    .children
+                    .push(xmltree::XMLNode::Element(response));
+                Ok(msg)
+            },
+        );
+
+        let in_raw = r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                <soap:Body><m:GetBackup/></soap:Body>
+            </soap:Envelope>"#;
+        let req: Request<Body> = Request::builder()
+            .uri("/")
+            .body(in_raw.as_bytes().into())
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
+        assert!(resp.status().is_success());
+        assert!(resp.headers()[axum::http::header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("multipart/related;"));
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("href=\"cid:attachment0@soap-router\""));
+        assert!(body.contains(
+            "Content-ID: <attachment0@soap-router>\r\n\r\nfirst chunk,second chunk\r\n--"
+        ));
+        assert!(body.trim_end().ends_with("--"));
+    }
+
+    #[tokio::test]
+    async fn test_streamed_upload() {
+        use crate::mtom::RequestAttachments;
+
+        let mut router = SoapRouter::new(()).add_operation(
+            "http://www.example.org".to_string(),
+            "Upgrade".to_string(),
+            |RawBody(body): RawBody, mut attachments: RequestAttachments| async move {
+                let href = body
+                    .get_child("Include")
+                    .and_then(|i| i.attributes.get("href"))
+                    .cloned()
+                    .unwrap();
+                let part = attachments.next_part().await?.unwrap();
+                assert_eq!(format!("cid:{}", part.content_id), href);
+                let mut size = 0;
+                while let Some(chunk) = attachments.chunk().await? {
+                    size += chunk.len();
+                }
+                let mut msg = SoapMessage::new();
+                let mut resp = Element::new("Size");
+                resp.children.push(xmltree::XMLNode::Text(size.to_string()));
+                msg.get_mut_body()
+                    .children
+                    .push(xmltree::XMLNode::Element(resp));
+                Ok(msg)
+            },
+        );
+
+        let envelope = r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                <env:Body><m:Upgrade><xop:Include xmlns:xop="http://www.w3.org/2004/08/xop/include" href="cid:firmware@example.org"/></m:Upgrade></env:Body>
+            </env:Envelope>"#;
+        let head = format!(
+            "--sep\r\nContent-Type: application/xop+xml; type=\"application/soap+xml\"\r\n\
+             Content-ID: <root>\r\n\r\n{envelope}\r\n--sep\r\n\
+             Content-Type: application/octet-stream\r\nContent-ID: <firmware@example.org>\r\n\r\n"
+        );
+        // The firmware is generated as it is sent
+        let parts = futures::stream::iter([Bytes::from(head)])
+            .chain(futures::stream::repeat(Bytes::from_static(&[0x5a; 4096])).take(256))
+            .chain(futures::stream::iter([Bytes::from_static(
+                b"\r\n--sep--\r\n",
+            )]))
+            .map(Ok::<_, std::io::Error>);
+        let req: Request<Body> = Request::builder()
+            .uri("/")
+            .header(
+                CONTENT_TYPE,
+                r#"multipart/related; type="application/xop+xml"; start="<root>"; start-info="application/soap+xml"; boundary="sep""#,
+            )
+            .body(Body::wrap_stream(parts))
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let msg = SoapMessage::from(Element::parse(body.as_ref()).unwrap());
+        assert_eq!(
+            msg.get_body()
+                .get_child("Size")
+                .and_then(|s| s.get_text())
+                .as_deref(),
+            Some("1048576")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_optional_operation() {
+        let mut router = SoapRouter::new(())
+            .add_optional_operation(
+                "http://www.example.org".to_string(),
+                "Supported".to_string(),
+                Some(|| async move { Ok(SoapMessage::new()) }),
+            )
+            .add_optional_operation(
+                "http://www.example.org".to_string(),
+                "Unsupported".to_string(),
+                None::<fn() -> futures::future::Ready<Result<SoapMessage, SoapFault>>>,
+            );
+
+        for (operation, expected) in [("Supported", None), ("Unsupported", Some("env:Receiver"))] {
+            let in_raw = format!(
+                r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                    <soap:Body><m:{}/></soap:Body>
+                </soap:Envelope>"#,
+                operation
+            );
+            let req: Request<Body> = Request::builder().uri("/").body(in_raw.into()).unwrap();
+            let resp = router.call(req).await.unwrap();
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let msg = SoapMessage::from(Element::parse(body.as_ref()).unwrap());
+            let code = msg
+                .get_body()
+                .get_child(("Fault", "http://www.w3.org/2003/05/soap-envelope"))
+                .and_then(|f| f.get_child("Code"));
+            assert_eq!(
+                code.and_then(|c| c.get_child("Value"))
+                    .and_then(|v| v.get_text())
+                    .as_deref(),
+                expected
+            );
+            if expected.is_some() {
+                let subcode = code
+                    .and_then(|c| c.get_child("Subcode"))
+                    .and_then(|s| s.get_child("Value"))
+                    .and_then(|v| v.get_text())
+                    .unwrap();
+                assert!(subcode.ends_with(":ActionNotSupported"));
+            }
+        }
+    }
+
+    async fn soap_call<S>(router: &mut SoapRouter<S>, raw: &str) -> SoapMessage
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let req: Request<Body> = Request::builder()
+            .uri("/")
+            .body(raw.to_string().into())
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        SoapMessage::from(Element::parse(body.as_ref()).unwrap())
+    }
+
+    fn fault_codes(msg: &SoapMessage) -> Vec<String> {
+        let mut codes = vec![];
+        let mut code = msg
+            .get_body()
+            .get_child(("Fault", "http://www.w3.org/2003/05/soap-envelope"))
+            .and_then(|f| f.get_child("Code"));
+        while let Some(c) = code {
+            codes.extend(
+                c.get_child("Value")
+                    .and_then(|v| v.get_text())
+                    .map(|v| v.to_string()),
+            );
+            code = c.get_child("Subcode");
+        }
+        codes
+    }
+
+    #[tokio::test]
+    async fn test_fallback() {
+        let in_raw = r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                <soap:Body><m:Unknown/></soap:Body>
+            </soap:Envelope>"#;
+
+        let mut router = SoapRouter::new(());
+        let codes = fault_codes(&soap_call(&mut router, in_raw).await);
+        assert_eq!(codes[0], "env:Sender");
+        assert!(codes[1].ends_with(":ProcedureNotPresent"));
+
+        let mut router = SoapRouter::new(()).fallback(|RawBody(body): RawBody| async move {
+            let mut msg = SoapMessage::new();
+            let mut resp = Element::new("Fallback");
+            resp.attributes.insert("operation".to_string(), body.name);
+            msg.get_mut_body()
+                .children
+                .push(xmltree::XMLNode::Element(resp));
+            Ok(msg)
+        });
+        let msg = soap_call(&mut router, in_raw).await;
+        assert_eq!(
+            msg.get_body()
+                .get_child("Fallback")
+                .and_then(|f| f.attributes.get("operation"))
+                .unwrap(),
+            "Unknown"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_operation_ordering() {
+        // Odd operations are slower than the following even ones
+        let handler = |RawBody(body): RawBody| async move {
+            let index: u64 = body.get_text().unwrap().parse().unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis((index % 2) * 30)).await;
+            if body.attributes.contains_key("fail") {
+                return Err(crate::codec::invalid_message(match index {
+                    1 => "InvalidBody",
+                    _ => "MissingHeader",
+                }));
+            }
+            let mut msg = SoapMessage::new();
+            let mut resp = Element::new("Done");
+            resp.children
+                .push(xmltree::XMLNode::Text(index.to_string()));
+            msg.get_mut_body()
+                .children
+                .push(xmltree::XMLNode::Element(resp));
+            Ok(msg)
+        };
+        let mut router = SoapRouter::new(()).add_operation(
+            "http://www.example.org".to_string(),
+            "Op".to_string(),
+            handler,
+        );
+
+        let msg = soap_call(
+            &mut router,
+            r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                <soap:Body><m:Op>1</m:Op><m:Op>2</m:Op><m:Op>3</m:Op><m:Op>4</m:Op><m:Op>5</m:Op></soap:Body>
+            </soap:Envelope>"#,
+        )
+        .await;
+        let order: Vec<String> = msg
+            .get_body()
+            .children
+            .iter()
+            .filter_map(|c| c.as_element())
+            .map(|e| e.get_text().unwrap().to_string())
+            .collect();
+        assert_eq!(order, vec!["1", "2", "3", "4", "5"]);
+
+        // The slow failing operation comes first, its fault is reported
+        let msg = soap_call(
+            &mut router,
+            r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                <soap:Body><m:Op fail="">1</m:Op><m:Op fail="">2</m:Op></soap:Body>
+            </soap:Envelope>"#,
+        )
+        .await;
+        let fault = SoapFault::try_from(msg).unwrap();
+        assert_eq!(fault.reason(&[]), "Invalid message body");
+    }
+
+    #[tokio::test]
+    async fn test_multi_element_policy() {
+        // Odd operations are slower than the following even ones
+        let handler = |State(done): State<Arc<Mutex<Vec<u64>>>>, RawBody(body): RawBody| async move {
+            let index: u64 = body.get_text().unwrap().parse().unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis((index % 2) * 30)).await;
+            done.lock().unwrap().push(index);
+            match body.attributes.contains_key("fail") {
+                true => Err(crate::codec::invalid_message("InvalidBody")),
+                false => Ok(SoapMessage::new()),
+            }
+        };
+        let raw = r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                <soap:Body><m:Op>1</m:Op><m:Op fail="">2</m:Op><m:Op>3</m:Op></soap:Body>
+            </soap:Envelope>"#;
+
+        for (policy, expected, codes) in [
+            (
+                MultiElementPolicy::ProcessConcurrent,
+                vec![2, 1, 3],
+                vec!["Sender"],
+            ),
+            (
+                MultiElementPolicy::ProcessOrdered,
+                vec![1, 2],
+                vec!["Sender"],
+            ),
+            (
+                MultiElementPolicy::RejectMultiple,
+                vec![],
+                vec!["Sender", "InvalidArgs"],
+            ),
+        ] {
+            let done = Arc::new(Mutex::new(vec![]));
+            let mut router = SoapRouter::new(done.clone())
+                .add_operation(
+                    "http://www.example.org".to_string(),
+                    "Op".to_string(),
+                    handler,
+                )
+                .with_multi_element_policy(policy);
+            let msg = soap_call(&mut router, raw).await;
+            let local_names: Vec<_> = fault_codes(&msg)
+                .iter()
+                .map(|c| c.rsplit(':').next().unwrap().to_string())
+                .collect();
+            assert_eq!(local_names, codes);
+            assert_eq!(*done.lock().unwrap(), expected);
+        }
+
+        let mut router = SoapRouter::new(Arc::new(Mutex::new(vec![])))
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Op".to_string(),
+                handler,
+            )
+            .with_multi_element_policy(MultiElementPolicy::RejectMultiple);
+        let msg = soap_call(
+            &mut router,
+            r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                <soap:Body><m:Op>1</m:Op></soap:Body>
+            </soap:Envelope>"#,
+        )
+        .await;
+        assert!(fault_codes(&msg).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_soap11() {
+        let mut router = SoapRouter::new(()).add_operation(
+            "http://www.example.org".to_string(),
+            "GetStockPrice".to_string(),
+            || async move {
+                let raw = r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                        <soap:Body><m:GetStockPriceResponse><m:StockPrice>3.60</m:StockPrice></m:GetStockPriceResponse></soap:Body>
+                    </soap:Envelope>"#;
+                Ok(Element::parse(raw.as_bytes()).unwrap())
+            },
+        );
+
+        let in_raw = r#"<soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/" xmlns:m="http://www.example.org">
+                <soap:Body><m:GetStockPrice><m:StockName>T</m:StockName></m:GetStockPrice></soap:Body>
+            </soap:Envelope>"#;
+        let req: Request<Body> = Request::builder()
+            .uri("/")
+            .body(in_raw.as_bytes().into())
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
+        assert_eq!(resp.headers()[CONTENT_TYPE], "text/xml; charset=utf-8");
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let msg = SoapMessage::from(Element::parse(body.as_ref()).unwrap());
+        assert_eq!(msg.version(), SoapVersion::Soap11);
+        assert!(msg
+            .get_body()
+            .get_child(("GetStockPriceResponse", "http://www.example.org"))
+            .is_some());
+
+        let in_raw = r#"<soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/" xmlns:m="http://www.example.org">
+                <soap:Body><m:Unknown/></soap:Body>
+            </soap:Envelope>"#;
+        let msg = soap_call(&mut router, in_raw).await;
+        assert_eq!(msg.version(), SoapVersion::Soap11);
+        let fault = msg
+            .get_body()
+            .get_child(("Fault", "http://schemas.xmlsoap.org/soap/envelope/"))
+            .unwrap();
+        assert!(fault
+            .get_child("faultcode")
+            .and_then(|c| c.get_text())
+            .unwrap()
+            .ends_with(":Client.ProcedureNotPresent"));
+        assert_eq!(
+            fault
+                .get_child("faultstring")
+                .and_then(|c| c.get_text())
+                .unwrap(),
+            "Procedure not present"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_requests() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let router = SoapRouter::new(()).add_operation(
+            "http://www.example.org".to_string(),
+            "GetStockPrice".to_string(),
+            |RawBody(body): RawBody| async move {
+                let name = body.get_child("StockName").unwrap().get_text().unwrap();
+                let raw = format!(
+                    r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                        <soap:Body><m:GetStockPriceResponse><m:StockPrice>{}</m:StockPrice></m:GetStockPriceResponse></soap:Body>
+                    </soap:Envelope>"#,
+                    name
+                );
+                Ok(Element::parse(raw.as_bytes()).unwrap())
+            },
+        );
+        let app = axum::Router::new().route_service("/onvif/device_service", router);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let mut pipelined = vec![];
+        for name in ["First", "Second", "Third"] {
+            let body = format!(
+                r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                    <soap:Body><m:GetStockPrice><m:StockName>{}</m:StockName></m:GetStockPrice></soap:Body>
+                </soap:Envelope>"#,
+                name
+            );
+            pipelined.extend(
+                format!(
+                    "POST /onvif/device_service HTTP/1.1\r\nHost: {}\r\nContent-Type: application/soap+xml\r\nContent-Length: {}\r\n\r\n{}",
+                    addr,
+                    body.len(),
+                    body
+                )
+                .into_bytes(),
+            );
+        }
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&pipelined).await.unwrap();
+
+        let mut received = String::new();
+        let mut buf = [0u8; 4096];
+        while received.matches("</m:StockPrice>").count() < 3 {
+            let n = tokio::time::timeout(std::time::Duration::from_secs(5), stream.read(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_ne!(n, 0, "connection closed early");
+            received.push_str(std::str::from_utf8(&buf[..n]).unwrap());
+        }
+
+        assert_eq!(received.matches("HTTP/1.1 200 OK").count(), 3);
+        let first = received.find(">First<").unwrap();
+        let second = received.find(">Second<").unwrap();
+        let third = received.find(">Third<").unwrap();
+        assert!(first < second && second < third);
+    }
+
+    #[test]
+    fn test_routes_introspection() {
+        let router = SoapRouter::new(())
+            .add_operation(
+                "http://www.example.org/b".to_string(),
+                "Second".to_string(),
+                || async move { Ok(SoapMessage::new()) },
+            )
+            .add_operation(
+                "http://www.example.org/a".to_string(),
+                "First".to_string(),
+                || async move { Ok(SoapMessage::new()) },
+            )
+            .add_optional_operation(
+                "http://www.example.org/b".to_string(),
+                "Optional".to_string(),
+                None::<fn() -> futures::future::Ready<Result<SoapMessage, SoapFault>>>,
+            );
+
+        let routes = router.routes();
+        assert_eq!(
+            routes
+                .iter()
+                .map(|r| (r.operation.as_ref().unwrap().to_string(), r.supported))
+                .collect::<Vec<_>>(),
+            vec![
+                ("{http://www.example.org/a}First".to_string(), true),
+                ("{http://www.example.org/b}Optional".to_string(), false),
+                ("{http://www.example.org/b}Second".to_string(), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_core_message() {
+        let mut core = soap_core::Message::new(SoapVersion::Soap11);
+        core.push_body(r#"<m:Get xmlns:m="urn:m"/>"#);
+        let msg = SoapMessage::try_from(core.clone()).unwrap();
+        assert_eq!(msg.version(), SoapVersion::Soap11);
+        assert!(msg.get_body().get_child(("Get", "urn:m")).is_some());
+        let core = msg.to_core().unwrap();
+        assert_eq!(core.body().len(), 1);
+        assert!(core.body()[0].starts_with("<m:Get"));
+
+        assert!(SoapMessage::no_content().to_core().unwrap().is_no_content());
+        assert!(SoapMessage::from(Element::new("Body")).to_core().is_none());
+    }
+
+    #[test]
+    fn test_describe() {
+        let router = SoapRouter::new(())
+            .add_operation(
+                "http://www.example.org/b".to_string(),
+                "Second".to_string(),
+                || async move { Ok(SoapMessage::new()) },
+            )
+            .add_operation(
+                "http://www.example.org/a".to_string(),
+                "First".to_string(),
+                || async move { Ok(SoapMessage::new()) },
+            )
+            .add_action_route("http://www.example.org/Ping".to_string(), || async move {
+                Ok(SoapMessage::new())
+            })
+            .with_wsdl("<definitions/>".to_string());
+
+        let description = router.describe();
+        assert_eq!(
+            description
+                .services
+                .iter()
+                .map(|s| (s.namespace.as_str(), s.operations.len()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("http://www.example.org/a", 1),
+                ("http://www.example.org/b", 1)
+            ]
+        );
+        assert_eq!(
+            description.actions[0].action.as_deref(),
+            Some("http://www.example.org/Ping")
+        );
+        assert!(description.capabilities.wsdl);
+        assert!(!description.capabilities.fallback);
+
+        let json: serde_json::Value = serde_json::from_str(&description.to_json()).unwrap();
+        assert_eq!(
+            json["services"][0]["operations"][0]["operation"]["name"],
+            "First"
+        );
+        assert_eq!(
+            json["capabilities"]["soap_versions"],
+            serde_json::json!(["Soap11", "Soap12"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_merge() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let called = Arc::new(AtomicBool::new(false));
+        let network = SoapRouter::new(called.clone())
+            .add_operation(
+                String::new(),
+                "SetHostname".to_string(),
+                |State(called): State<Arc<AtomicBool>>| async move {
+                    called.store(true, Ordering::SeqCst);
+                    Ok(SoapMessage::new())
+                },
+            )
+            .add_optional_operation(
+                String::new(),
+                "Unlicensed".to_string(),
+                None::<fn() -> futures::future::Ready<Result<SoapMessage, SoapFault>>>,
+            )
+            .intercept(Licensing {
+                after_calls: calls.clone(),
+            });
+        let system = SoapRouter::new(()).add_operation(
+            "http://www.onvif.org/ver10/device/wsdl".to_string(),
+            "Unlicensed".to_string(),
+            || async move { Ok(SoapMessage::new()) },
+        );
+        let mut router = SoapRouter::new(())
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Unlicensed".to_string(),
+                || async move { Ok(SoapMessage::new()) },
+            )
+            .merge(SoapRouter::new(()).nest_namespace(
+                "http://www.onvif.org/ver10/device/wsdl".to_string(),
+                network,
+            ));
+
+        assert_eq!(
+            router
+                .routes()
+                .iter()
+                .map(|r| r.operation.as_ref().unwrap().to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "{http://www.example.org}Unlicensed",
+                "{http://www.onvif.org/ver10/device/wsdl}SetHostname",
+                "{http://www.onvif.org/ver10/device/wsdl}Unlicensed",
+            ]
+        );
+        for (namespace, name, status) in [
+            ("http://www.example.org", "Unlicensed", StatusCode::OK),
+            (
+                "http://www.onvif.org/ver10/device/wsdl",
+                "SetHostname",
+                StatusCode::OK,
+            ),
+            // Stopped by the interceptor of the nested router
+            (
+                "http://www.onvif.org/ver10/device/wsdl",
+                "Unlicensed",
+                StatusCode::BAD_REQUEST,
+            ),
+        ] {
+            let body = format!(
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="{}">
+                    <env:Body><m:{}/></env:Body>
+                </env:Envelope>"#,
+                namespace, name
+            );
+            let req = Request::builder().uri("/").body(Body::from(body)).unwrap();
+            let resp = router.call(req).await.unwrap();
+            assert_eq!(resp.status(), status);
+        }
+        assert!(called.load(Ordering::SeqCst));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let merged =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| router.merge(system)));
+        assert!(merged.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ws_addressing() {
+        use crate::ws_addressing::{WSA_FAULT_ACTION, WSA_NAMESPACE};
+
+        let mut router = SoapRouter::new(())
+            .add_action_route(
+                "http://www.example.org/Ping".to_string(),
+                |RawBody(body): RawBody| async move {
+                    let mut msg = SoapMessage::new();
+                    let mut pong = Element::new("Pong");
+                    pong.children.push(xmltree::XMLNode::Text(body.name));
+                    msg.get_mut_body()
+                        .children
+                        .push(xmltree::XMLNode::Element(pong));
+                    Ok(msg)
+                },
+            )
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Ping".to_string(),
+                || async move { Ok(SoapMessage::new()) },
+            );
+
+        let msg = soap_call(
+            &mut router,
+            r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope"
+                xmlns:wsa="http://www.w3.org/2005/08/addressing" xmlns:m="http://www.example.org">
+                <env:Header>
+                    <wsa:Action>http://www.example.org/Ping</wsa:Action>
+                    <wsa:MessageID>urn:uuid:1234</wsa:MessageID>
+                </env:Header>
+                <env:Body><m:Ping/></env:Body>
+            </env:Envelope>"#,
+        )
+        .await;
+        let text = |name: &str| {
+            msg.get_headers()
+                .and_then(|h| h.get_child((name, WSA_NAMESPACE)))
+                .and_then(|e| e.get_text())
+                .map(|t| t.to_string())
+        };
+        assert_eq!(
+            msg.get_body()
+                .get_child("Pong")
+                .and_then(|p| p.get_text())
+                .as_deref(),
+            Some("Ping")
+        );
+        assert_eq!(
+            text("Action").as_deref(),
+            Some("http://www.example.org/PingResponse")
+        );
+        assert_eq!(text("RelatesTo").as_deref(), Some("urn:uuid:1234"));
+        assert!(text("MessageID").unwrap().starts_with("urn:uuid:"));
+
+        let msg = soap_call(
+            &mut router,
+            r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope"
+                xmlns:wsa="http://www.w3.org/2005/08/addressing" xmlns:m="http://www.example.org">
+                <env:Header>
+                    <wsa:Action>http://www.example.org/Unknown</wsa:Action>
+                    <wsa:MessageID>urn:uuid:5678</wsa:MessageID>
+                </env:Header>
+                <env:Body><m:Unknown/></env:Body>
+            </env:Envelope>"#,
+        )
+        .await;
+        assert!(fault_codes(&msg)[1].ends_with(":ProcedureNotPresent"));
+        let action = msg
+            .get_headers()
+            .and_then(|h| h.get_child(("Action", WSA_NAMESPACE)))
+            .and_then(|e| e.get_text())
+            .map(|t| t.to_string());
+        assert_eq!(action.as_deref(), Some(WSA_FAULT_ACTION));
+    }
+
+    #[tokio::test]
+    async fn test_layers() {
+        use tower::{layer::layer_fn, service_fn};
+
+        // Reject requests lacking an Authorization header block
+        let auth = layer_fn(|inner: BoxedSoapHandlerService| {
+            service_fn(move |req: SoapRequest| {
+                let mut inner = inner.clone();
+                async move {
+                    if req.headers.get_child("Authorization").is_none() {
+                        return Err(action_not_supported());
+                    }
+                    inner.call(req).await
+                }
+            })
+        });
+        let mut router = SoapRouter::new(())
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Protected".to_string(),
+                || async move { Ok(SoapMessage::new()) },
+            )
+            .fallback(|| async move { Ok(SoapMessage::new()) })
+            .route_layer(auth)
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Public".to_string(),
+                || async move { Ok(SoapMessage::new()) },
+            );
+        let call = |op: &str, auth: bool| {
+            format!(
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                    <env:Header>{}</env:Header>
+                    <env:Body><m:{}/></env:Body>
+                </env:Envelope>"#,
+                if auth { "<Authorization/>" } else { "" },
+                op
+            )
+        };
+
+        let msg = soap_call(&mut router, &call("Protected", false)).await;
+        assert!(fault_codes(&msg)[1].ends_with(":ActionNotSupported"));
+        let msg = soap_call(&mut router, &call("Protected", true)).await;
+        assert!(fault_codes(&msg).is_empty());
+        // Added after the layer
+        let msg = soap_call(&mut router, &call("Public", false)).await;
+        assert!(fault_codes(&msg).is_empty());
+        // route_layer leaves the fallback alone
+        let msg = soap_call(&mut router, &call("Unknown", false)).await;
+        assert!(fault_codes(&msg).is_empty());
+
+        let mut router = router.layer(layer_fn(|inner: BoxedSoapHandlerService| {
+            service_fn(move |req: SoapRequest| {
+                let mut inner = inner.clone();
+                async move {
+                    let mut msg = inner.call(req).await?;
+                    msg.get_mut_headers()
+                        .children
+                        .push(xmltree::XMLNode::Element(Element::new("Layered")));
+                    Ok::<_, SoapFault>(msg)
+                }
+            })
+        }));
+        for op in ["Public", "Unknown"] {
+            let msg = soap_call(&mut router, &call(op, false)).await;
+            assert!(msg.get_headers().unwrap().get_child("Layered").is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reply_to() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let receiver = axum::Router::new().route(
+            "/replies",
+            axum::routing::post(
+                move |headers: axum::http::HeaderMap, body: bytes::Bytes| async move {
+                    tx.send((headers, body)).unwrap();
+                },
+            ),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(receiver.into_make_service()),
+        );
+
+        let reply_to = format!("http://{}/replies", addr);
+        let mut router = SoapRouter::new(())
+            .with_address_policy(AddressPolicy::AllowList(vec![
+                url::Url::parse(&reply_to).unwrap()
+            ]))
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Known".to_string(),
+                || async move { Ok(SoapMessage::new()) },
+            );
+        let request = |op: &str| {
+            format!(
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope"
+                    xmlns:wsa="http://www.w3.org/2005/08/addressing" xmlns:m="http://www.example.org">
+                    <env:Header>
+                        <wsa:Action>http://www.example.org/{op}</wsa:Action>
+                        <wsa:MessageID>urn:uuid:{op}</wsa:MessageID>
+                        <wsa:ReplyTo><wsa:Address>{reply_to}</wsa:Address></wsa:ReplyTo>
+                    </env:Header>
+                    <env:Body><m:{op}/></env:Body>
+                </env:Envelope>"#
+            )
+        };
+
+        let req: Request<Body> = Request::builder()
+            .uri("/")
+            .body(request("Known").into())
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        assert!(hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap()
+            .is_empty());
+        let (headers, body) = rx.recv().await.unwrap();
+        assert!(headers[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .contains("action=\"http://www.example.org/KnownResponse\""));
+        let delivered = SoapMessage::from(Element::parse(body.as_ref()).unwrap());
+        assert!(fault_codes(&delivered).is_empty());
+
+        // Faults go to ReplyTo as well when there is no FaultTo
+        let req: Request<Body> = Request::builder()
+            .uri("/")
+            .body(request("Unknown").into())
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        let (_, body) = rx.recv().await.unwrap();
+        let delivered = SoapMessage::from(Element::parse(body.as_ref()).unwrap());
+        assert!(fault_codes(&delivered)[1].ends_with(":ProcedureNotPresent"));
+        assert_eq!(
+            delivered
+                .get_headers()
+                .and_then(|h| h.get_child(("RelatesTo", WSA_NAMESPACE)))
+                .and_then(|r| r.get_text())
+                .as_deref(),
+            Some("urn:uuid:Unknown")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fault_to() {
+        use crate::ws_addressing::WSA_NAMESPACE;
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let (reply_tx, mut replies) = tokio::sync::mpsc::unbounded_channel();
+        let receiver = axum::Router::new()
+            .route(
+                "/faults",
+                axum::routing::post(move |body: bytes::Bytes| async move {
+                    tx.send(body).unwrap();
+                }),
+            )
+            .route(
+                "/replies",
+                axum::routing::post(
+                    move |headers: axum::http::HeaderMap, body: bytes::Bytes| async move {
+                        reply_tx.send((headers, body)).unwrap();
+                    },
+                ),
+            );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(receiver.into_make_service()),
+        );
+
+        let fault_to = format!("http://{}/faults", addr);
+        let reply_to = format!("http://{}/replies", addr);
+        let mut router = SoapRouter::new(())
+            .with_address_policy(AddressPolicy::AllowList(vec![
+                url::Url::parse(&fault_to).unwrap(),
+                url::Url::parse(&reply_to).unwrap(),
+            ]))
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Known".to_string(),
+                || async move { Ok(SoapMessage::new()) },
+            );
+        let request = |op: &str| {
+            format!(
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope"
+                    xmlns:wsa="http://www.w3.org/2005/08/addressing" xmlns:m="http://www.example.org">
+                    <env:Header>
+                        <wsa:Action>http://www.example.org/{op}</wsa:Action>
+                        <wsa:MessageID>urn:uuid:{op}</wsa:MessageID>
+                        <wsa:FaultTo><wsa:Address>{fault_to}</wsa:Address></wsa:FaultTo>
+                    </env:Header>
+                    <env:Body><m:{op}/></env:Body>
+                </env:Envelope>"#
+            )
+        };
+
+        let msg = soap_call(&mut router, &request("Known")).await;
+        assert!(fault_codes(&msg).is_empty());
+
+        let req: Request<Body> = Request::builder()
+            .uri("/")
+            .body(request("Unknown").into())
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        assert!(hyper::body::to_bytes(resp.into_body())
+            .await
+            .unwrap()
+            .is_empty());
+
+        let delivered =
+            SoapMessage::from(Element::parse(rx.recv().await.unwrap().as_ref()).unwrap());
+        assert!(fault_codes(&delivered)[1].ends_with(":ProcedureNotPresent"));
+        assert_eq!(
+            delivered
+                .get_headers()
+                .and_then(|h| h.get_child(("RelatesTo", WSA_NAMESPACE)))
+                .and_then(|r| r.get_text())
+                .as_deref(),
+            Some("urn:uuid:Unknown")
+        );
+
+        // Replies go to ReplyTo, faults still go to FaultTo
+        let request = |op: &str| {
+            format!(
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope"
+                    xmlns:wsa="http://www.w3.org/2005/08/addressing" xmlns:m="http://www.example.org">
+                    <env:Header>
+                        <wsa:Action>http://www.example.org/{op}</wsa:Action>
+                        <wsa:MessageID>urn:uuid:{op}</wsa:MessageID>
+                        <wsa:ReplyTo><wsa:Address>{reply_to}</wsa:Address></wsa:ReplyTo>
+                        <wsa:FaultTo><wsa:Address>{fault_to}</wsa:Address></wsa:FaultTo>
+                    </env:Header>
+                    <env:Body><m:{op}/></env:Body>
+                </env:Envelope>"#
+            )
+        };
+        for op in ["Known", "Unknown"] {
+            let req: Request<Body> = Request::builder()
+                .uri("/")
+                .body(request(op).into())
+                .unwrap();
+            let resp = router.call(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        }
+        let (headers, body) = replies.recv().await.unwrap();
+        assert!(headers[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .contains("action=\"http://www.example.org/KnownResponse\""));
+        let delivered = SoapMessage::from(Element::parse(body.as_ref()).unwrap());
+        assert!(fault_codes(&delivered).is_empty());
+        let delivered =
+            SoapMessage::from(Element::parse(rx.recv().await.unwrap().as_ref()).unwrap());
+        assert!(fault_codes(&delivered)[1].ends_with(":ProcedureNotPresent"));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_requests() {
+        let mut router = SoapRouter::new(()).add_operation(
+            "http://www.example.org".to_string(),
+            "Test".to_string(),
+            || async move { Ok(SoapMessage::new()) },
+        );
+
+        for (content_type, body, status, code) in [
+            (
+                None,
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope"><env:Body>"#,
+                StatusCode::BAD_REQUEST,
+                "Sender",
+            ),
+            (None, "", StatusCode::BAD_REQUEST, "Sender"),
+            (
+                Some("application/soap+xml"),
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope"/>"#,
+                StatusCode::BAD_REQUEST,
+                "Sender",
+            ),
+            (
+                Some("application/json"),
+                "{}",
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Sender",
+            ),
+            (
+                None,
+                r#"<env:Envelope xmlns:env="urn:not-soap"><env:Body/></env:Envelope>"#,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "VersionMismatch",
+            ),
+        ] {
+            let mut req = Request::builder().uri("/");
+            if let Some(content_type) = content_type {
+                req = req.header(CONTENT_TYPE, content_type);
+            }
+            let resp = router.call(req.body(body.into()).unwrap()).await.unwrap();
+            assert_eq!(resp.status(), status, "{}", body);
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let msg = SoapMessage::from(Element::parse(body.as_ref()).unwrap());
+            assert!(fault_codes(&msg)[0].ends_with(code));
+        }
+
+        // Invalid UTF-8 in the envelope
+        let mut raw =
+            br#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope"><env:Body>"#
+                .to_vec();
+        raw.extend([0xff, 0xfe]);
+        raw.extend(b"</env:Body></env:Envelope>");
+        let req: Request<Body> = Request::builder()
+            .uri("/")
+            .header(CONTENT_TYPE, "text/xml; charset=utf-8")
+            .body(raw.into())
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(resp.headers()[CONTENT_TYPE], "text/xml; charset=utf-8");
+    }
+
+    #[tokio::test]
+    async fn test_content_type() {
+        let mut router = SoapRouter::new(())
+            .add_action_route("http://www.example.org/Ping".to_string(), || async move {
+                let mut msg = SoapMessage::new();
+                msg.get_mut_body()
+                    .children
+                    .push(xmltree::XMLNode::Element(Element::new("Pong")));
+                Ok(msg)
+            })
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Ping".to_string(),
+                || async move { Ok(SoapMessage::new()) },
+            );
+
+        for (namespace, header, value, routed) in [
+            (
+                "http://www.w3.org/2003/05/soap-envelope",
+                CONTENT_TYPE.as_str(),
+                r#"application/soap+xml; charset=utf-8; action="http://www.example.org/Ping""#,
+                true,
+            ),
+            (
+                "http://schemas.xmlsoap.org/soap/envelope/",
+                "SOAPAction",
+                r#""http://www.example.org/Ping""#,
+                true,
+            ),
+            (
+                "http://www.w3.org/2003/05/soap-envelope",
+                CONTENT_TYPE.as_str(),
+                "application/soap+xml",
+                false,
+            ),
+        ] {
+            let raw = format!(
+                r#"<env:Envelope xmlns:env="{namespace}" xmlns:m="http://www.example.org">
+                    <env:Body><m:Ping/></env:Body>
+                </env:Envelope>"#
+            );
+            let mut req = Request::builder().uri("/").header(header, value);
+            if header == "SOAPAction" {
+                req = req.header(CONTENT_TYPE, "text/xml");
+            }
+            let resp = router.call(req.body(raw.into()).unwrap()).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let msg = SoapMessage::from(Element::parse(body.as_ref()).unwrap());
+            assert_eq!(
+                msg.get_body().get_child("Pong").is_some(),
+                routed,
+                "{}",
+                value
+            );
+        }
+
+        let req: Request<Body> = Request::builder()
+            .uri("/")
+            .header(CONTENT_TYPE, "text/xml; charset=iso-8859-1")
+            .body(
+                r#"<env:Envelope xmlns:env="http://schemas.xmlsoap.org/soap/envelope/"><env:Body/></env:Envelope>"#
+                    .into(),
+            )
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(resp.headers()[CONTENT_TYPE], "text/xml; charset=utf-8");
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let mut router = SoapRouter::new(())
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Test".to_string(),
+                || async move { Ok(SoapMessage::new()) },
+            )
+            .with_body_limit(1024)
+            .with_max_depth(8);
+        let envelope = |content: &str| {
+            format!(
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                    <env:Body><m:Test>{content}</m:Test></env:Body>
+                </env:Envelope>"#
+            )
+        };
+        let laughs = r#"<?xml version="1.0"?>
+            <!DOCTYPE lolz [<!ENTITY lol "lol"><!ENTITY lol2 "&lol;&lol;&lol;&lol;&lol;&lol;">]>
+            <env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                <env:Body><m:Test>&lol2;</m:Test></env:Body>
+            </env:Envelope>"#;
+
+        for (body, chunked, status) in [
+            (envelope("<a><b><c>ok</c></b></a>"), false, StatusCode::OK),
+            (
+                envelope(&"x".repeat(2048)),
+                false,
+                StatusCode::PAYLOAD_TOO_LARGE,
+            ),
+            (
+                envelope(&"x".repeat(2048)),
+                true,
+                StatusCode::PAYLOAD_TOO_LARGE,
+            ),
+            (
+                envelope(&format!("{}{}", "<a>".repeat(8), "</a>".repeat(8))),
+                false,
+                StatusCode::BAD_REQUEST,
+            ),
+            (laughs.to_string(), false, StatusCode::BAD_REQUEST),
+        ] {
+            let req = Request::builder().uri("/");
+            let req = match chunked {
+                // No Content-Length to reject the request early
+                true => req.body(Body::wrap_stream(futures::stream::iter(
+                    body.into_bytes()
+                        .chunks(100)
+                        .map(|c| Ok::<_, std::io::Error>(c.to_vec()))
+                        .collect::<Vec<_>>(),
+                ))),
+                false => req
+                    .header(CONTENT_LENGTH, body.len())
+                    .body(Body::from(body)),
+            };
+            let resp = router.call(req.unwrap()).await.unwrap();
+            assert_eq!(resp.status(), status);
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let msg = SoapMessage::from(Element::parse(body.as_ref()).unwrap());
+            if status != StatusCode::OK {
+                assert_eq!(fault_codes(&msg)[0], "env:Sender");
+            }
+        }
+
+        // The rejected requests are logged
+        assert_eq!(
+            router.fault_log().counts(),
+            BTreeMap::from([("Sender".to_string(), 4)])
+        );
+        assert_eq!(router.fault_log().recent().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_compression() {
+        let router = SoapRouter::new(()).add_operation(
+            "http://www.example.org".to_string(),
+            "Test".to_string(),
+            |RawBody(body): RawBody| async move {
+                let mut msg = SoapMessage::new();
+                msg.get_mut_body()
+                    .children
+                    .push(xmltree::XMLNode::Element(body));
+                Ok(msg)
+            },
+        );
+        let envelope = format!(
+            r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                <env:Body><m:Test>{}</m:Test></env:Body>
+            </env:Envelope>"#,
+            "<m:Item>value</m:Item>".repeat(100)
+        );
+        let request = |encoding: ContentEncoding| {
+            Request::builder()
+                .uri("/")
+                .header(CONTENT_ENCODING, encoding.as_str())
+                .header(ACCEPT_ENCODING, "gzip;q=0.5, deflate")
+                .body(Body::from(encoding.encode(envelope.as_bytes())))
+                .unwrap()
+        };
+
+        // Disabled by default
+        let resp = router
+            .clone()
+            .call(request(ContentEncoding::Gzip))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(resp.headers()[ACCEPT_ENCODING], "identity");
+        let resp = router
+            .clone()
+            .call(request(ContentEncoding::Identity))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(CONTENT_ENCODING).is_none());
+
+        let mut router = router.with_compression(true).with_body_limit(4096);
+        for encoding in [ContentEncoding::Gzip, ContentEncoding::Deflate] {
+            let resp = router.call(request(encoding)).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.headers()[CONTENT_ENCODING], "deflate");
+            assert_eq!(resp.headers()[VARY], "accept-encoding");
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let body = ContentEncoding::Deflate
+                .decode(&body, DEFAULT_BODY_LIMIT)
+                .unwrap();
+            let msg = SoapMessage::from(Element::parse(body.as_slice()).unwrap());
+            assert_eq!(msg.get_body().children.len(), 1);
+        }
+
+        // The limit applies to the decoded envelope
+        let large = format!(
+            r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope"><env:Body>{}</env:Body></env:Envelope>"#,
+            "x".repeat(8192)
+        );
+        let req = Request::builder()
+            .uri("/")
+            .header(CONTENT_ENCODING, "gzip")
+            .body(Body::from(ContentEncoding::Gzip.encode(large.as_bytes())))
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(resp.headers().get(CONTENT_ENCODING).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_one_way() {
+        let mut router =
+            SoapRouter::new(())
+                .add_operation(
+                    "http://www.example.org".to_string(),
+                    "Notify".to_string(),
+                    || async move { Ok(NoContent) },
+                )
+                .add_operation(
+                    "http://www.example.org".to_string(),
+                    "Maybe".to_string(),
+                    |RawBody(body): RawBody| async move {
+                        Ok(body.get_text().map(|_| SoapMessage::new()))
+                    },
+                )
+                .add_operation(
+                    "http://www.example.org".to_string(),
+                    "Echo".to_string(),
+                    || async move {
+                        // Processing instructions of the envelope are only data
+                        let mut msg = SoapMessage::new();
+                        msg.envelope_mut()
+                            .children
+                            .push(XMLNode::ProcessingInstruction(
+                                "soap-router-no-content".to_string(),
+                                None,
+                            ));
+                        Ok(msg)
+                    },
+                );
+        for (body, status) in [
+            ("<m:Notify/>", StatusCode::ACCEPTED),
+            ("<m:Notify/><m:Notify/><m:Maybe/>", StatusCode::ACCEPTED),
+            ("<m:Maybe>reply</m:Maybe>", StatusCode::OK),
+            ("<m:Notify/><m:Maybe>reply</m:Maybe>", StatusCode::OK),
+            ("<m:Echo/>", StatusCode::OK),
+        ] {
+            let raw = format!(
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                    <env:Body>{}</env:Body>
+                </env:Envelope>"#,
+                body
+            );
+            let req = Request::builder().uri("/").body(Body::from(raw)).unwrap();
+            let resp = router.call(req).await.unwrap();
+            assert_eq!(resp.status(), status);
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            match status {
+                StatusCode::ACCEPTED => assert!(body.is_empty()),
+                _ => {
+                    let msg = SoapMessage::from(Element::parse(body.as_ref()).unwrap());
+                    assert!(!msg.is_no_content());
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fault_status() {
+        let mut router = SoapRouter::new(())
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Sender".to_string(),
+                || async move { Err::<SoapMessage, _>(SoapFault::sender("Bad request")) },
+            )
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Receiver".to_string(),
+                || async move { Err::<SoapMessage, _>(SoapFault::receiver("Device busy")) },
+            )
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Encoding".to_string(),
+                || async move {
+                    Err::<SoapMessage, _>(
+                        SoapFault::builder(SoapFaultCode::DataEncodingUnknown)
+                            .reason_en("Unknown encoding")
+                            .build(),
+                    )
+                },
+            )
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Custom".to_string(),
+                || async move {
+                    Err::<SoapMessage, _>(
+                        SoapFault::receiver("Try later")
+                            .with_status(StatusCode::SERVICE_UNAVAILABLE),
+                    )
+                },
+            );
+
+        for (namespace, operation, status) in [
+            (
+                "http://www.w3.org/2003/05/soap-envelope",
+                "Sender",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                "http://www.w3.org/2003/05/soap-envelope",
+                "Receiver",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                "http://www.w3.org/2003/05/soap-envelope",
+                "Encoding",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                "http://www.w3.org/2003/05/soap-envelope",
+                "Custom",
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                "http://schemas.xmlsoap.org/soap/envelope/",
+                "Sender",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ] {
+            let raw = format!(
+                r#"<env:Envelope xmlns:env="{namespace}" xmlns:m="http://www.example.org">
+                    <env:Body><m:{operation}/></env:Body>
+                </env:Envelope>"#
+            );
+            let req: Request<Body> = Request::builder().uri("/").body(raw.into()).unwrap();
+            let resp = router.call(req).await.unwrap();
+            assert_eq!(resp.status(), status, "{} {}", namespace, operation);
+        }
+
+        // VersionMismatch faults list the supported envelopes
+        let req: Request<Body> = Request::builder()
+            .uri("/")
+            .body(r#"<env:Envelope xmlns:env="urn:not-soap"><env:Body/></env:Envelope>"#.into())
             .unwrap();
         let resp = router.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let msg = SoapMessage::from(Element::parse(body.as_ref()).unwrap());
+        let upgrade = msg
+            .get_headers()
+            .and_then(|h| h.get_child(("Upgrade", "http://www.w3.org/2003/05/soap-envelope")))
+            .unwrap();
+        assert_eq!(upgrade.children.len(), 2);
+
+        let resp = SoapFault::sender("Bad request").into_response();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            resp.headers()[CONTENT_TYPE],
+            "application/soap+xml; charset=utf-8"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wsdl() {
+        let wsdl = r#"<wsdl:definitions xmlns:wsdl="http://schemas.xmlsoap.org/wsdl/"
+            xmlns:soap="http://schemas.xmlsoap.org/wsdl/soap12/">
+            <wsdl:service name="DeviceService">
+                <wsdl:port name="DevicePort" binding="tds:DeviceBinding">
+                    <soap:address location="http://localhost/"/>
+                </wsdl:port>
+            </wsdl:service>
+        </wsdl:definitions>"#;
+        let mut router = SoapRouter::new(()).with_wsdl(wsdl.to_string());
 
+        let req: Request<Body> = Request::builder()
+            .method(Method::GET)
+            .uri("/onvif/device_service?wsdl")
+            .header(HOST, "192.168.1.2:8080")
+            .body(Body::empty())
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
         assert!(resp.status().is_success());
+        assert_eq!(resp.headers()[CONTENT_TYPE], "text/xml; charset=utf-8");
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let doc = Element::parse(body.as_ref()).unwrap();
+        let address = doc
+            .get_child("service")
+            .and_then(|s| s.get_child("port"))
+            .and_then(|p| p.get_child(("address", "http://schemas.xmlsoap.org/wsdl/soap12/")))
+            .unwrap();
+        assert_eq!(
+            address.attributes["location"],
+            "http://192.168.1.2:8080/onvif/device_service"
+        );
+
+        // Only GET ?wsdl serves the document
+        let req: Request<Body> = Request::builder()
+            .method(Method::GET)
+            .uri("/onvif/device_service?other")
+            .body(Body::empty())
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // Behind a trusted proxy the external address is used
+        let mut router =
+            router.with_trusted_proxies(TrustedProxies::Addresses(vec![[10, 0, 0, 1].into()]));
+        for (peer, location) in [
+            (
+                [10, 0, 0, 1],
+                "https://camera.example.org/onvif/device_service",
+            ),
+            (
+                [10, 0, 0, 7],
+                "http://192.168.1.2:8080/onvif/device_service",
+            ),
+        ] {
+            let mut req: Request<Body> = Request::builder()
+                .method(Method::GET)
+                .uri("/onvif/device_service?wsdl")
+                .header(HOST, "192.168.1.2:8080")
+                .header("X-Forwarded-Proto", "https")
+                .header("X-Forwarded-Host", "camera.example.org")
+                .body(Body::empty())
+                .unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from((peer, 4242))));
+            let resp = router.call(req).await.unwrap();
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let doc = Element::parse(body.as_ref()).unwrap();
+            let address = doc
+                .get_child("service")
+                .and_then(|s| s.get_child("port"))
+                .and_then(|p| p.get_child("address"))
+                .unwrap();
+            assert_eq!(address.attributes["location"], location);
+        }
 
+        // The locations follow the URI policy
+        let mut router =
+            router.with_uri_builder(|origin: &RequestOrigin, kind: UriKind, path: &str| {
+                DefaultUriBuilder.build(origin, kind, &format!("/camera{}", path))
+            });
+        let req: Request<Body> = Request::builder()
+            .method(Method::GET)
+            .uri("/onvif/device_service?wsdl")
+            .header(HOST, "192.168.1.2:8080")
+            .body(Body::empty())
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
         let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
-        let xml_body = Element::parse(body.as_ref()).unwrap();
-        let expected = Element::parse(expected_raw.as_bytes()).unwrap();
-        assert_eq!(xml_body, expected)
+        let doc = Element::parse(body.as_ref()).unwrap();
+        let address = doc
+            .get_child("service")
+            .and_then(|s| s.get_child("port"))
+            .and_then(|p| p.get_child("address"))
+            .unwrap();
+        assert_eq!(
+            address.attributes["location"],
+            "http://192.168.1.2:8080/camera/onvif/device_service"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_probes() {
+        let mut router = SoapRouter::new(());
+        for (method, status) in [
+            (Method::OPTIONS, StatusCode::NO_CONTENT),
+            (Method::HEAD, StatusCode::OK),
+        ] {
+            let req: Request<Body> = Request::builder()
+                .method(method)
+                .uri("/")
+                .body(Body::empty())
+                .unwrap();
+            let resp = router.call(req).await.unwrap();
+            assert_eq!(resp.status(), status);
+            assert_eq!(resp.headers()[ALLOW], "POST, HEAD, OPTIONS");
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            assert!(body.is_empty());
+        }
+
+        let mut router = router.with_wsdl("<definitions/>".to_string());
+        let req: Request<Body> = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/")
+            .body(Body::empty())
+            .unwrap();
+        let resp = router.call(req).await.unwrap();
+        assert_eq!(resp.headers()[ALLOW], "POST, GET, HEAD, OPTIONS");
+    }
+
+    #[tokio::test]
+    async fn test_must_understand() {
+        let mut router = SoapRouter::new(())
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Test".to_string(),
+                || async move { Ok(SoapMessage::new()) },
+            )
+            .add_understood_header("http://www.example.org".to_string(), "Known".to_string());
+        let call = |headers: &str| {
+            format!(
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope"
+                    xmlns:m="http://www.example.org" xmlns:wsa="http://www.w3.org/2005/08/addressing">
+                    <env:Header>{}</env:Header>
+                    <env:Body><m:Test/></env:Body>
+                </env:Envelope>"#,
+                headers
+            )
+        };
+
+        for headers in [
+            r#"<m:Unknown/>"#,
+            r#"<m:Unknown env:mustUnderstand="false"/>"#,
+            r#"<m:Known env:mustUnderstand="true"/>"#,
+            r#"<wsa:Action env:mustUnderstand="1">http://www.example.org/Test</wsa:Action>"#,
+            r#"<m:Unknown env:mustUnderstand="true" env:role="http://example.org/other"/>"#,
+        ] {
+            let msg = soap_call(&mut router, &call(headers)).await;
+            assert!(fault_codes(&msg).is_empty(), "{}", headers);
+        }
+
+        let msg = soap_call(
+            &mut router,
+            &call(r#"<m:Unknown env:mustUnderstand="true"/><m:Known env:mustUnderstand="true"/>"#),
+        )
+        .await;
+        assert_eq!(fault_codes(&msg), vec!["env:MustUnderstand"]);
+        let not_understood: Vec<_> = msg
+            .get_headers()
+            .unwrap()
+            .children
+            .iter()
+            .filter_map(|c| c.as_element())
+            .filter(|e| e.name == "NotUnderstood")
+            .collect();
+        assert_eq!(not_understood.len(), 1);
+        let qname = &not_understood[0].attributes["qname"];
+        let (prefix, name) = qname.split_once(':').unwrap();
+        assert_eq!(name, "Unknown");
+        assert_eq!(
+            not_understood[0].namespaces.as_ref().unwrap().get(prefix),
+            Some("http://www.example.org")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_header_processor() {
+        let mut router = SoapRouter::new(())
+            .add_header_processor(
+                "http://www.example.org".to_string(),
+                "Token".to_string(),
+                |header: &Element, request: &mut SoapRequest| {
+                    if header.get_text().as_deref() != Some("secret") {
+                        return Err(action_not_supported());
+                    }
+                    // Let the handler know the caller was checked
+                    request
+                        .body
+                        .attributes
+                        .insert("checked".to_string(), "true".to_string());
+                    Ok(())
+                },
+            )
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Test".to_string(),
+                |RawBody(body): RawBody| async move {
+                    let mut msg = SoapMessage::new();
+                    let mut elem = Element::new("Checked");
+                    elem.children.push(xmltree::XMLNode::Text(
+                        body.attributes.get("checked").cloned().unwrap_or_default(),
+                    ));
+                    msg.get_mut_body()
+                        .children
+                        .push(xmltree::XMLNode::Element(elem));
+                    Ok(msg)
+                },
+            );
+        let call = |token: &str| {
+            format!(
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                    <env:Header><m:Token env:mustUnderstand="true">{}</m:Token></env:Header>
+                    <env:Body><m:Test/></env:Body>
+                </env:Envelope>"#,
+                token
+            )
+        };
+
+        let msg = soap_call(&mut router, &call("secret")).await;
+        assert_eq!(
+            msg.get_body()
+                .get_child("Checked")
+                .and_then(|c| c.get_text())
+                .as_deref(),
+            Some("true")
+        );
+        let msg = soap_call(&mut router, &call("wrong")).await;
+        assert!(fault_codes(&msg)[1].ends_with(":ActionNotSupported"));
+    }
+
+    #[tokio::test]
+    async fn test_header_processor_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let mut router = SoapRouter::new(())
+            .add_header_processor(
+                "http://www.example.org".to_string(),
+                "Token".to_string(),
+                move |_: &Element, request: &mut SoapRequest| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    request.response_headers.push(Element::new("Processed"));
+                    Ok(())
+                },
+            )
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "A".to_string(),
+                || async move { Ok(SoapMessage::new()) },
+            )
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "B".to_string(),
+                || async move { Ok(SoapMessage::new()) },
+            );
+
+        let msg = soap_call(
+            &mut router,
+            r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                <env:Header><m:Token/></env:Header>
+                <env:Body><m:A/><m:B/></env:Body>
+            </env:Envelope>"#,
+        )
+        .await;
+        assert!(fault_codes(&msg).is_empty());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        let processed = msg
+            .get_headers()
+            .unwrap()
+            .children
+            .iter()
+            .filter_map(|c| c.as_element())
+            .filter(|e| e.name == "Processed")
+            .count();
+        assert_eq!(processed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_cancellation() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let mut router = SoapRouter::new(cancelled.clone()).add_operation(
+            "http://www.example.org".to_string(),
+            "Slow".to_string(),
+            |State(flag): State<Arc<AtomicBool>>, cancellation: Cancellation| async move {
+                tokio::spawn(async move {
+                    cancellation.cancelled().await;
+                    flag.store(true, Ordering::SeqCst);
+                });
+                std::future::pending::<Result<SoapMessage, SoapFault>>().await
+            },
+        );
+        let req: Request<Body> = Request::builder()
+            .uri("/")
+            .body(
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                    <env:Body><m:Slow/></env:Body>
+                </env:Envelope>"#
+                    .into(),
+            )
+            .unwrap();
+        // The server gives up on the request
+        let abandoned =
+            tokio::time::timeout(std::time::Duration::from_millis(50), router.call(req)).await;
+        assert!(abandoned.is_err());
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        assert!(cancelled.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout() {
+        let handler = |RawBody(body): RawBody, cancellation: Cancellation| async move {
+            let secs: u64 = body.get_text().unwrap().parse().unwrap();
+            let remaining = cancellation.deadline().unwrap() - Instant::now();
+            tokio::time::sleep(Duration::from_secs(secs)).await;
+            let mut msg = SoapMessage::new();
+            let mut resp = Element::new("Remaining");
+            resp.children
+                .push(xmltree::XMLNode::Text(remaining.as_secs().to_string()));
+            msg.get_mut_body()
+                .children
+                .push(xmltree::XMLNode::Element(resp));
+            Ok(msg)
+        };
+        let raw = |name: &str, secs: u64| {
+            format!(
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                    <env:Body><m:{0}>{1}</m:{0}></env:Body>
+                </env:Envelope>"#,
+                name, secs
+            )
+        };
+        let mut router = SoapRouter::new(())
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Capture".to_string(),
+                handler,
+            )
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Reboot".to_string(),
+                handler,
+            )
+            .add_action_route("http://www.example.org/Upgrade".to_string(), handler)
+            .with_timeout(Duration::from_secs(5))
+            .with_operation_timeout(
+                "http://www.example.org".to_string(),
+                "Reboot".to_string(),
+                Duration::from_secs(30),
+            )
+            .with_operation_timeout(
+                "http://www.example.org".to_string(),
+                "Upgrade".to_string(),
+                Duration::from_secs(60),
+            );
+
+        let msg = soap_call(&mut router, &raw("Capture", 1)).await;
+        assert_eq!(msg.get_body().children.len(), 1);
+        let msg = soap_call(&mut router, &raw("Reboot", 20)).await;
+        let remaining = msg
+            .get_body()
+            .get_child("Remaining")
+            .and_then(|r| r.get_text())
+            .unwrap();
+        assert_eq!(remaining, "30");
+        let msg = soap_call(
+            &mut router,
+            r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope"
+                xmlns:wsa="http://www.w3.org/2005/08/addressing" xmlns:m="http://www.example.org">
+                <env:Header><wsa:Action>http://www.example.org/Upgrade</wsa:Action></env:Header>
+                <env:Body><m:Firmware>40</m:Firmware></env:Body>
+            </env:Envelope>"#,
+        )
+        .await;
+        let remaining = msg
+            .get_body()
+            .get_child("Remaining")
+            .and_then(|r| r.get_text())
+            .unwrap();
+        assert_eq!(remaining, "60");
+
+        // Overriding the timeout of an unknown operation is a mistake
+        let unknown = std::panic::catch_unwind(|| {
+            SoapRouter::new(()).with_operation_timeout(
+                "http://www.example.org".to_string(),
+                "Unknown".to_string(),
+                Duration::from_secs(1),
+            )
+        });
+        assert!(unknown.is_err());
+
+        let req = Request::builder()
+            .uri("/")
+            .body(Body::from(raw("Capture", 10)))
+            .unwrap();
+        let started = Instant::now();
+        let resp = router.call(req).await.unwrap();
+        assert_eq!(started.elapsed(), Duration::from_secs(5));
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let msg = SoapMessage::from(Element::parse(body.as_ref()).unwrap());
+        let fault = SoapFault::try_from(msg).unwrap();
+        assert!(matches!(fault.code(), SoapFaultCode::Receiver));
+        assert_eq!(fault.sub_codes()[0].1, "Action");
+        assert_eq!(fault.reason(&[]), "Operation timed out");
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use tokio::io::AsyncWriteExt;
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let router = SoapRouter::new(cancelled.clone()).add_operation(
+            "http://www.example.org".to_string(),
+            "Slow".to_string(),
+            |State(flag): State<Arc<AtomicBool>>, cancellation: Cancellation| async move {
+                tokio::spawn(async move {
+                    cancellation.cancelled().await;
+                    flag.store(true, Ordering::SeqCst);
+                });
+                std::future::pending::<Result<SoapMessage, SoapFault>>().await
+            },
+        );
+        let app = axum::Router::new().route_service("/", router);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let body = r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org"><env:Body><m:Slow/></env:Body></env:Envelope>"#;
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                format!(
+                    "POST / HTTP/1.1\r\nHost: {addr}\r\nContent-Length: {}\r\n\r\n{body}",
+                    body.len()
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!cancelled.load(Ordering::SeqCst));
+        drop(stream);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(cancelled.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_extensions() {
+        use crate::extract::Extension;
+
+        #[derive(Clone)]
+        struct User(String);
+
+        let mut router = SoapRouter::new(())
+            .add_header_processor(
+                "http://www.example.org".to_string(),
+                "User".to_string(),
+                |header: &Element, request: &mut SoapRequest| {
+                    let name = header.get_text().unwrap_or_default().to_string();
+                    request.extensions.insert(User(name));
+                    Ok(())
+                },
+            )
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "WhoAmI".to_string(),
+                |Extension(user): Extension<User>,
+                 peer: Option<Extension<ConnectInfo<SocketAddr>>>| async move {
+                    let mut msg = SoapMessage::new();
+                    let mut elem = Element::new("User");
+                    elem.children.push(xmltree::XMLNode::Text(format!(
+                        "{}@{}",
+                        user.0,
+                        peer.map(|p| p.0 .0.ip().to_string()).unwrap_or_default()
+                    )));
+                    msg.get_mut_body()
+                        .children
+                        .push(xmltree::XMLNode::Element(elem));
+                    Ok(msg)
+                },
+            );
+        let call = |header: &str| {
+            format!(
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                    <env:Header>{}</env:Header>
+                    <env:Body><m:WhoAmI/></env:Body>
+                </env:Envelope>"#,
+                header
+            )
+        };
+
+        let mut req: Request<Body> = Request::builder()
+            .uri("/")
+            .body(call("<m:User>admin</m:User>").into())
+            .unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4242))));
+        let resp = router.call(req).await.unwrap();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let msg = SoapMessage::from(Element::parse(body.as_ref()).unwrap());
+        assert_eq!(
+            msg.get_body()
+                .get_child("User")
+                .and_then(|u| u.get_text())
+                .as_deref(),
+            Some("admin@10.0.0.1")
+        );
+
+        let msg = soap_call(&mut router, &call("")).await;
+        assert_eq!(fault_codes(&msg), vec!["env:Receiver"]);
+    }
+
+    type RecordedSpan = (
+        &'static tracing::Metadata<'static>,
+        BTreeMap<String, String>,
+    );
+
+    /// Fields of the spans created, tracking the entered ones so that
+    /// `Span::current` works
+    #[derive(Clone, Default)]
+    struct SpanRecorder {
+        spans: Arc<Mutex<Vec<RecordedSpan>>>,
+        entered: Arc<Mutex<Vec<tracing::span::Id>>>,
+    }
+
+    struct FieldVisitor<'a>(&'a mut BTreeMap<String, String>);
+
+    impl tracing::field::Visit for FieldVisitor<'_> {
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    impl tracing::Subscriber for SpanRecorder {
+        fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut spans = self.spans.lock().unwrap();
+            let mut fields = BTreeMap::new();
+            span.record(&mut FieldVisitor(&mut fields));
+            spans.push((span.metadata(), fields));
+            tracing::span::Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            values.record(&mut FieldVisitor(
+                &mut spans[span.into_u64() as usize - 1].1,
+            ));
+        }
+
+        fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
+
+        fn event(&self, _event: &tracing::Event<'_>) {}
+
+        fn enter(&self, span: &tracing::span::Id) {
+            self.entered.lock().unwrap().push(span.clone());
+        }
+
+        fn exit(&self, _span: &tracing::span::Id) {
+            self.entered.lock().unwrap().pop();
+        }
+
+        fn current_span(&self) -> tracing_core::span::Current {
+            match self.entered.lock().unwrap().last() {
+                Some(id) => {
+                    let metadata = self.spans.lock().unwrap()[id.into_u64() as usize - 1].0;
+                    tracing_core::span::Current::new(id.clone(), metadata)
+                }
+                None => tracing_core::span::Current::none(),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tracing() {
+        let recorder = SpanRecorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+        let mut router = SoapRouter::new(())
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Get".to_string(),
+                || async { Ok(SoapMessage::new()) },
+            )
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Fail".to_string(),
+                || async {
+                    Err::<SoapMessage, _>(
+                        OnvifFault::invalid_arg_val("NoProfile", "No such profile").into(),
+                    )
+                },
+            );
+        let call = |operation: &str| {
+            format!(
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org" xmlns:wsa="http://www.w3.org/2005/08/addressing">
+                    <env:Header><wsa:MessageID>urn:uuid:{operation}</wsa:MessageID></env:Header>
+                    <env:Body><m:{operation}/></env:Body>
+                </env:Envelope>"#
+            )
+        };
+
+        let mut req: Request<Body> = Request::builder()
+            .uri("/")
+            .body(call("Get").into())
+            .unwrap();
+        req.extensions_mut()
+            .insert(AuthenticatedUser("admin".to_string()));
+        router.call(req).await.unwrap();
+        soap_call(&mut router, &call("Fail")).await;
+        soap_call(&mut router, "<env:Envelope").await;
+
+        let spans = recorder.spans.lock().unwrap().clone();
+        let field = |span: usize, name: &str| spans[span].1.get(name).map(String::as_str);
+        assert_eq!(spans.len(), 3);
+        assert_eq!(field(0, "operation"), Some("{http://www.example.org}Get"));
+        assert_eq!(field(0, "message_id"), Some("urn:uuid:Get"));
+        assert_eq!(field(0, "user"), Some("admin"));
+        assert_eq!(field(0, "outcome"), Some("ok"));
+        assert_eq!(field(1, "operation"), Some("{http://www.example.org}Fail"));
+        assert_eq!(field(1, "user"), None);
+        assert_eq!(field(1, "outcome"), Some("Sender/InvalidArgVal/NoProfile"));
+        assert_eq!(field(2, "operation"), None);
+        assert_eq!(field(2, "outcome"), Some("Sender"));
+    }
+
+    #[tokio::test]
+    async fn test_envelope_hooks() {
+        use crate::soap_security::{REDACTED, WSSE_NAMESPACE};
+
+        let requests = Arc::new(Mutex::new(vec![]));
+        let responses = Arc::new(Mutex::new(vec![]));
+        let record = |log: &Arc<Mutex<Vec<String>>>| {
+            let log = log.clone();
+            move |msg: &SoapMessage| {
+                let mut buf = vec![];
+                msg.envelope().write(&mut buf).unwrap();
+                log.lock().unwrap().push(String::from_utf8(buf).unwrap());
+            }
+        };
+        let mut router = SoapRouter::new(())
+            .add_understood_header(WSSE_NAMESPACE.to_string(), "Security".to_string())
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Get".to_string(),
+                |RawBody(body): RawBody| async move {
+                    match body.attributes.contains_key("fail") {
+                        true => Err(SoapFault::sender("Failed")),
+                        false => Ok(SoapMessage::new()),
+                    }
+                },
+            )
+            .on_request(record(&requests))
+            .on_response(record(&responses));
+
+        let call = |attributes: &str| {
+            format!(
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org" xmlns:wsse="{WSSE_NAMESPACE}">
+                    <env:Header><wsse:Security><wsse:UsernameToken>
+                        <wsse:Username>admin</wsse:Username>
+                        <wsse:Password Type="PasswordDigest">c2VjcmV0LWRpZ2VzdA==</wsse:Password>
+                        <wsse:Nonce>bm9uY2U=</wsse:Nonce>
+                    </wsse:UsernameToken></wsse:Security></env:Header>
+                    <env:Body><m:Get {attributes}/></env:Body>
+                </env:Envelope>"#
+            )
+        };
+        soap_call(&mut router, &call("")).await;
+        let msg = soap_call(&mut router, &call(r#"fail="true""#)).await;
+        assert_eq!(fault_codes(&msg), vec!["env:Sender"]);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        for request in requests.iter() {
+            assert!(request.contains("admin"));
+            assert!(!request.contains("c2VjcmV0LWRpZ2VzdA==") && !request.contains("bm9uY2U="));
+            assert_eq!(request.matches(REDACTED).count(), 2);
+        }
+        let responses = responses.lock().unwrap();
+        assert_eq!(responses.len(), 2);
+        assert!(responses[1].contains("Failed"));
     }
 }
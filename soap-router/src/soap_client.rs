@@ -0,0 +1,291 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::http::{header::CONTENT_TYPE, Request};
+use bytes::{BufMut, Bytes};
+use hyper::{client::HttpConnector, Body, Client};
+use xmltree::{Element, XMLNode};
+use yaserde::{YaDeserialize, YaSerialize};
+
+use crate::{
+    codec::{body_message, from_element},
+    entropy::{default_source, uuid, EntropySource},
+    fault::SoapFault,
+    router::SoapMessage,
+    version::{convert_envelope, SoapVersion},
+    ws_addressing::header,
+};
+
+/// Errors returned by [`SoapClient`]
+#[derive(Debug)]
+pub enum ClientError {
+    /// The request could not be sent or the response could not be read
+    Transport(String),
+    /// The response is not a SOAP envelope holding the expected element
+    InvalidResponse,
+    /// The remote endpoint answered with a fault
+    Fault(SoapFault),
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Transport(e) => write!(f, "SOAP transport error: {}", e),
+            ClientError::InvalidResponse => write!(f, "Invalid SOAP response"),
+            ClientError::Fault(fault) => fault.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+/// Client for outgoing SOAP calls, connections to a given endpoint are
+/// pooled and reused across calls.
+#[derive(Clone)]
+pub struct SoapClient {
+    client: Client<HttpConnector>,
+    version: SoapVersion,
+    entropy: Arc<dyn EntropySource>,
+}
+
+impl Default for SoapClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SoapClient {
+    pub fn new() -> Self {
+        Self {
+            client: Client::builder()
+                .pool_idle_timeout(Duration::from_secs(90))
+                .build_http(),
+            version: SoapVersion::default(),
+            entropy: default_source(),
+        }
+    }
+
+    /// SOAP version of the sent envelopes, SOAP 1.2 by default
+    pub fn with_version(mut self, version: SoapVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Replace the source of random data used for message identifiers
+    pub fn with_entropy_source<E: EntropySource>(mut self, source: E) -> Self {
+        self.entropy = Arc::new(source);
+        self
+    }
+
+    /// Call the operation identified by `action` on the endpoint at
+    /// `address` with a typed request, and deserialize the first element of
+    /// the response body.
+    pub async fn call<Req, Resp>(
+        &self,
+        address: &str,
+        action: &str,
+        request: &Req,
+    ) -> Result<Resp, ClientError>
+    where
+        Req: YaSerialize,
+        Resp: YaDeserialize,
+    {
+        let message =
+            body_message(request).map_err(|fault| ClientError::Transport(fault.to_string()))?;
+        let response = self.send(address, action, message).await?;
+        let elem = response
+            .get_body()
+            .children
+            .iter()
+            .find_map(|c| c.as_element())
+            .ok_or(ClientError::InvalidResponse)?;
+        from_element(elem).map_err(|_| ClientError::InvalidResponse)
+    }
+
+    /// Send a message stamped with the WS-Addressing `wsa:Action`,
+    /// `wsa:MessageID` and `wsa:To` headers, and return the response.
+    pub async fn send(
+        &self,
+        address: &str,
+        action: &str,
+        mut message: SoapMessage,
+    ) -> Result<SoapMessage, ClientError> {
+        let message_id = format!("urn:uuid:{}", uuid(self.entropy.as_ref()));
+        message.get_mut_headers().children.extend(
+            [
+                header("Action", action),
+                header("MessageID", &message_id),
+                header("To", address),
+            ]
+            .into_iter()
+            .map(XMLNode::Element),
+        );
+        let envelope = convert_envelope(message.into_envelope(), self.version);
+        let mut buf = vec![].writer();
+        envelope
+            .write(&mut buf)
+            .map_err(|e| ClientError::Transport(e.to_string()))?;
+        let body = self.post(address, action, buf.into_inner()).await?;
+
+        let response: SoapMessage = Element::parse(body.as_ref())
+            .map_err(|_| ClientError::InvalidResponse)?
+            .into();
+        let version = response.version();
+        if response.envelope().name != "Envelope"
+            || response.envelope().namespace.as_deref() != Some(version.namespace())
+            || response
+                .envelope()
+                .get_child(("Body", version.namespace()))
+                .is_none()
+        {
+            return Err(ClientError::InvalidResponse);
+        }
+        match response
+            .get_body()
+            .get_child(("Fault", version.namespace()))
+        {
+            Some(fault) => Err(SoapFault::try_from(fault)
+                .map(ClientError::Fault)
+                .unwrap_or(ClientError::InvalidResponse)),
+            None => Ok(response),
+        }
+    }
+
+    /// POST an already serialized envelope and return the response body
+    pub(crate) async fn post(
+        &self,
+        address: &str,
+        action: &str,
+        envelope: Vec<u8>,
+    ) -> Result<Bytes, ClientError> {
+        self.post_body(address, action, None, Body::from(envelope))
+            .await
+    }
+
+    /// POST a message of the given content type, a bare envelope by default,
+    /// and return the response body
+    pub(crate) async fn post_body(
+        &self,
+        address: &str,
+        action: &str,
+        content_type: Option<String>,
+        body: Body,
+    ) -> Result<Bytes, ClientError> {
+        let req = match (self.version, content_type) {
+            (SoapVersion::Soap11, content_type) => Request::post(address)
+                .header(
+                    CONTENT_TYPE,
+                    content_type.unwrap_or(self.version.content_type().to_string()),
+                )
+                .header("SOAPAction", format!("\"{}\"", action)),
+            (SoapVersion::Soap12, Some(content_type)) => {
+                Request::post(address).header(CONTENT_TYPE, content_type)
+            }
+            (SoapVersion::Soap12, None) => Request::post(address).header(
+                CONTENT_TYPE,
+                format!("{}; action=\"{}\"", self.version.content_type(), action),
+            ),
+        }
+        .body(body)
+        .map_err(|e| ClientError::Transport(e.to_string()))?;
+
+        let resp = self
+            .client
+            .request(req)
+            .await
+            .map_err(|e| ClientError::Transport(e.to_string()))?;
+        hyper::body::to_bytes(resp.into_body())
+            .await
+            .map_err(|e| ClientError::Transport(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        extract::RawBody,
+        router::SoapRouter,
+        version::SOAP11_NAMESPACE,
+        ws_addressing::{AddressingHeaders, WSA_NAMESPACE},
+    };
+
+    async fn serve(router: SoapRouter<()>) -> String {
+        let app = axum::Router::new().route_service("/service", router);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        format!("http://{}/service", addr)
+    }
+
+    fn request(name: &str) -> SoapMessage {
+        let mut msg = SoapMessage::new();
+        let mut elem = Element::new(name);
+        elem.namespace = Some("http://www.example.org".to_string());
+        let mut namespaces = xmltree::Namespace::empty();
+        namespaces.put("", "http://www.example.org");
+        elem.namespaces = Some(namespaces);
+        msg.get_mut_body().children.push(XMLNode::Element(elem));
+        msg
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        let address = serve(SoapRouter::new(()).add_operation(
+            "http://www.example.org".to_string(),
+            "Echo".to_string(),
+            |addressing: AddressingHeaders, RawBody(body): RawBody| async move {
+                let mut msg = SoapMessage::new();
+                let mut elem = Element::new("EchoResponse");
+                elem.children
+                    .push(XMLNode::Text(addressing.to.unwrap_or_default()));
+                msg.get_mut_body().children.push(XMLNode::Element(elem));
+                assert_eq!(body.name, "Echo");
+                Ok(msg)
+            },
+        ))
+        .await;
+
+        for version in [SoapVersion::Soap11, SoapVersion::Soap12] {
+            let client = SoapClient::new().with_version(version);
+            let resp = client
+                .send(&address, "http://www.example.org/Echo", request("Echo"))
+                .await
+                .unwrap();
+            assert_eq!(resp.version(), version);
+            assert_eq!(
+                resp.get_body()
+                    .get_child("EchoResponse")
+                    .and_then(|e| e.get_text())
+                    .as_deref(),
+                Some(address.as_str())
+            );
+            assert!(resp
+                .get_headers()
+                .and_then(|h| h.get_child(("RelatesTo", WSA_NAMESPACE)))
+                .is_some());
+
+            match client
+                .send(&address, "http://www.example.org/Other", request("Other"))
+                .await
+            {
+                Err(ClientError::Fault(fault)) => {
+                    assert_eq!(fault.sub_codes()[0].1, "ProcedureNotPresent");
+                    // SOAP 1.1 faultcodes don't carry the subcode namespaces
+                    assert_eq!(
+                        fault.sub_codes()[0].0.as_str(),
+                        match version {
+                            SoapVersion::Soap11 => SOAP11_NAMESPACE,
+                            SoapVersion::Soap12 => "http://www.w3.org/2003/05/soap-rpc",
+                        }
+                    );
+                    assert_eq!(fault.reason(&[]), "Procedure not present");
+                }
+                r => panic!("unexpected result {:?}", r.map(SoapMessage::into_envelope)),
+            }
+        }
+    }
+}
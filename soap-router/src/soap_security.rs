@@ -0,0 +1,851 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use tower::Layer;
+use tower_service::Service;
+use xmltree::{Element, XMLNode};
+
+use crate::{
+    fault::SoapFault,
+    interceptor::{HeaderProcessor, QName},
+    onvif_fault::OnvifFault,
+    router::{BoxedSoapHandlerService, RouteInfo, SoapMessage, SoapRequest},
+};
+
+pub const WSSE_NAMESPACE: &str =
+    "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-secext-1.0.xsd";
+pub const WSU_NAMESPACE: &str =
+    "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd";
+pub const PASSWORD_DIGEST: &str =
+    "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-username-token-profile-1.0#PasswordDigest";
+pub const PASSWORD_TEXT: &str =
+    "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-username-token-profile-1.0#PasswordText";
+
+pub const DEVICE_NAMESPACE: &str = "http://www.onvif.org/ver10/device/wsdl";
+
+/// Device service operations of the `PRE_AUTH` access class, which must be
+/// callable without authentication: clients need the device time to compute
+/// their digests and the services to find where to send them.
+pub const PRE_AUTH_OPERATIONS: &[&str] = &[
+    "GetEndpointReference",
+    "GetServices",
+    "GetServiceCapabilities",
+    "GetCapabilities",
+    "GetHostname",
+    "GetSystemDateAndTime",
+    "GetWsdlUrl",
+];
+
+/// Whether `operation` is one of the device service [`PRE_AUTH_OPERATIONS`]
+pub fn is_pre_auth(operation: &QName) -> bool {
+    operation.namespace == DEVICE_NAMESPACE
+        && PRE_AUTH_OPERATIONS.contains(&operation.name.as_str())
+}
+
+/// Access class of a route, the declared one or `PreAuth` for the body
+/// element routes of the [`PRE_AUTH_OPERATIONS`]
+pub(crate) fn route_access(info: &RouteInfo) -> Option<AccessClass> {
+    info.access.or_else(|| {
+        info.operation
+            .as_ref()
+            .filter(|o| is_pre_auth(o))
+            .map(|_| AccessClass::PreAuth)
+    })
+}
+
+/// Access class of the route of a request, requests handled by the fallback
+/// have none
+fn access_class(req: &SoapRequest) -> Option<AccessClass> {
+    req.extensions.get::<RouteInfo>().and_then(route_access)
+}
+
+/// Marks requests whose credentials were rejected, the router then only lets
+/// their `PreAuth` operations through, anonymously, and answers the other
+/// ones with `ter:NotAuthorized`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PreAuthOnly;
+
+/// ONVIF user levels, ordered from the least to the most privileged
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum UserLevel {
+    Anonymous,
+    User,
+    Operator,
+    Administrator,
+}
+
+/// ONVIF access classes of the operations
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+pub enum AccessClass {
+    PreAuth,
+    ReadSystem,
+    ReadSystemSensitive,
+    ReadSystemSecret,
+    WriteSystem,
+    Unrecoverable,
+    ReadMedia,
+    Actuate,
+}
+
+impl AccessClass {
+    /// Lowest user level allowed by the default access policy of the ONVIF
+    /// core specification.
+    pub fn required_level(&self) -> UserLevel {
+        match self {
+            AccessClass::PreAuth => UserLevel::Anonymous,
+            AccessClass::ReadSystem | AccessClass::ReadMedia => UserLevel::User,
+            AccessClass::ReadSystemSensitive | AccessClass::Actuate => UserLevel::Operator,
+            AccessClass::ReadSystemSecret
+            | AccessClass::WriteSystem
+            | AccessClass::Unrecoverable => UserLevel::Administrator,
+        }
+    }
+}
+
+/// Source of the users allowed to authenticate
+pub trait CredentialStore: Send + Sync + 'static {
+    /// Clear text password of the user, if it exists
+    fn password(&self, username: &str) -> Option<String>;
+
+    /// Level of an existing user, `User` by default
+    fn user_level(&self, _username: &str) -> UserLevel {
+        UserLevel::User
+    }
+}
+
+impl CredentialStore for HashMap<String, String> {
+    fn password(&self, username: &str) -> Option<String> {
+        self.get(username).cloned()
+    }
+}
+
+impl CredentialStore for HashMap<String, (String, UserLevel)> {
+    fn password(&self, username: &str) -> Option<String> {
+        self.get(username).map(|(p, _)| p.clone())
+    }
+
+    fn user_level(&self, username: &str) -> UserLevel {
+        self.get(username)
+            .map(|(_, l)| *l)
+            .unwrap_or(UserLevel::Anonymous)
+    }
+}
+
+/// Name of the user the request was authenticated as, added to the request
+/// extensions by the authentication mechanisms.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthenticatedUser(pub String);
+
+/// `ter:NotAuthorized` fault returned for failed authentications
+pub fn not_authorized() -> SoapFault {
+    OnvifFault::NotAuthorized.into()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Password {
+    Text(String),
+    /// Base64 of SHA-1(nonce + created + password)
+    Digest(String),
+}
+
+/// `wsse:UsernameToken` of a `wsse:Security` header block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsernameToken {
+    pub username: String,
+    pub password: Password,
+    pub nonce: Option<Vec<u8>>,
+    pub created: Option<String>,
+}
+
+impl UsernameToken {
+    pub fn from_security_header(security: &Element) -> Option<Self> {
+        let token = security.get_child(("UsernameToken", WSSE_NAMESPACE))?;
+        let text = |e: &Element| e.get_text().map(|t| t.trim().to_string());
+        let username = token
+            .get_child(("Username", WSSE_NAMESPACE))
+            .and_then(text)?;
+        let password_elem = token.get_child(("Password", WSSE_NAMESPACE))?;
+        let password_value = text(password_elem).unwrap_or_default();
+        let password = match password_elem.attributes.get("Type").map(String::as_str) {
+            Some(PASSWORD_DIGEST) => Password::Digest(password_value),
+            None | Some(PASSWORD_TEXT) => Password::Text(password_value),
+            Some(_) => return None,
+        };
+        let nonce = match token.get_child(("Nonce", WSSE_NAMESPACE)).and_then(text) {
+            Some(nonce) => Some(STANDARD.decode(nonce).ok()?),
+            None => None,
+        };
+        let created = token.get_child(("Created", WSU_NAMESPACE)).and_then(text);
+        Some(Self {
+            username,
+            password,
+            nonce,
+            created,
+        })
+    }
+
+    /// Check the token against the clear text password of the user
+    pub fn verify(&self, password: &str) -> bool {
+        match &self.password {
+            Password::Text(p) => constant_time_eq(p.as_bytes(), password.as_bytes()),
+            Password::Digest(digest) => {
+                let mut hasher = Sha1::new();
+                hasher.update(self.nonce.as_deref().unwrap_or_default());
+                hasher.update(self.created.as_deref().unwrap_or_default());
+                hasher.update(password);
+                let expected = STANDARD.encode(hasher.finalize());
+                constant_time_eq(digest.as_bytes(), expected.as_bytes())
+            }
+        }
+    }
+}
+
+/// Text replacing the redacted credentials
+pub const REDACTED: &str = "***";
+
+/// Replace the passwords and nonces of the `wsse:Security` headers of an
+/// envelope with [`REDACTED`]
+pub fn redact_credentials(envelope: &mut Element) {
+    fn redact(elem: &mut Element) {
+        for child in elem.children.iter_mut().filter_map(|c| c.as_mut_element()) {
+            match (child.namespace.as_deref(), child.name.as_str()) {
+                (Some(WSSE_NAMESPACE), "Password" | "Nonce") => {
+                    child.children = vec![XMLNode::Text(REDACTED.to_string())]
+                }
+                _ => redact(child),
+            }
+        }
+    }
+    let headers = envelope
+        .children
+        .iter_mut()
+        .filter_map(|c| c.as_mut_element())
+        .filter(|e| e.name == "Header");
+    for header in headers {
+        header
+            .children
+            .iter_mut()
+            .filter_map(|c| c.as_mut_element())
+            .filter(|e| e.name == "Security" && e.namespace.as_deref() == Some(WSSE_NAMESPACE))
+            .for_each(redact);
+    }
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Parse an `xsd:dateTime` with an explicit offset, as used by `wsu:Created`
+pub(crate) fn parse_date_time(value: &str) -> Option<SystemTime> {
+    let (date, time) = value.trim().split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+
+    let (time, offset) = match time.strip_suffix('Z') {
+        Some(time) => (time, 0),
+        None => {
+            let at = time.rfind(['+', '-'])?;
+            let (h, m) = time[at + 1..].split_once(':')?;
+            let offset = h.parse::<i64>().ok()? * 3600 + m.parse::<i64>().ok()? * 60;
+            let sign = if time.as_bytes()[at] == b'-' { -1 } else { 1 };
+            (&time[..at], sign * offset)
+        }
+    };
+    let mut time = time.splitn(3, ':');
+    let (hour, minute) = (
+        time.next()?.parse::<i64>().ok()?,
+        time.next()?.parse::<i64>().ok()?,
+    );
+    let seconds: f64 = time.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+
+    // Days since the epoch of the proleptic Gregorian calendar date
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let secs = days * 86400 + hour * 3600 + minute * 60 - offset;
+    let time = Duration::try_from_secs_f64(secs as f64 + seconds).ok()?;
+    Some(UNIX_EPOCH + time)
+}
+
+pub(crate) type Clock = dyn Fn() -> SystemTime + Send + Sync;
+
+/// Header processor validating WS-Security UsernameTokens, the authenticated
+/// user is added to the request extensions as an [`AuthenticatedUser`].
+///
+/// Digest tokens must carry a nonce and a creation time within the allowed
+/// clock skew, and nonces can't be reused within that window. Use
+/// [`RequireAuthentication`] to reject requests without any token.
+///
+/// Requests with an invalid token are marked [`PreAuthOnly`]: clients with a
+/// wrong clock can't compute valid digests before getting the device time.
+#[derive(Clone)]
+pub struct WsSecurity<C> {
+    store: Arc<C>,
+    max_skew: Duration,
+    nonces: Arc<Mutex<HashMap<Vec<u8>, SystemTime>>>,
+    clock: Arc<Clock>,
+}
+
+impl<C: CredentialStore> WsSecurity<C> {
+    pub fn new(store: C) -> Self {
+        Self::from_shared(Arc::new(store))
+    }
+
+    /// Use a credential store shared with other authentication mechanisms
+    pub fn from_shared(store: Arc<C>) -> Self {
+        Self {
+            store,
+            max_skew: Duration::from_secs(300),
+            nonces: Default::default(),
+            clock: Arc::new(SystemTime::now),
+        }
+    }
+
+    /// Maximum difference between the token creation time and the local
+    /// clock, 5 minutes by default.
+    pub fn with_max_skew(mut self, max_skew: Duration) -> Self {
+        self.max_skew = max_skew;
+        self
+    }
+
+    /// Replace the clock tokens are checked against
+    pub fn with_clock<F>(mut self, clock: F) -> Self
+    where
+        F: Fn() -> SystemTime + Send + Sync + 'static,
+    {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Validate a token, returning the name of the authenticated user
+    pub fn authenticate(&self, token: &UsernameToken) -> Result<String, SoapFault> {
+        let now = (self.clock)();
+        if let Some(created) = &token.created {
+            let created = parse_date_time(created).ok_or_else(not_authorized)?;
+            let skew = now
+                .duration_since(created)
+                .or_else(|_| created.duration_since(now))
+                .unwrap_or_default();
+            if skew > self.max_skew {
+                return Err(not_authorized());
+            }
+        }
+        match self.store.password(&token.username) {
+            Some(password) if token.verify(&password) => (),
+            _ => return Err(not_authorized()),
+        }
+        // Only record the nonces of valid tokens, so that they can't be burnt
+        if let Password::Digest(_) = token.password {
+            let (Some(nonce), Some(_)) = (&token.nonce, &token.created) else {
+                return Err(not_authorized());
+            };
+            let mut nonces = self.nonces.lock().unwrap();
+            nonces.retain(|_, expiry| *expiry > now);
+            if nonces.contains_key(nonce) {
+                return Err(not_authorized());
+            }
+            nonces.insert(nonce.clone(), now + 2 * self.max_skew);
+        }
+        Ok(token.username.clone())
+    }
+}
+
+impl<C: CredentialStore> HeaderProcessor for WsSecurity<C> {
+    fn process(&self, header: &Element, request: &mut SoapRequest) -> Result<(), SoapFault> {
+        let user = UsernameToken::from_security_header(header)
+            .ok_or_else(not_authorized)
+            .and_then(|token| self.authenticate(&token));
+        match user {
+            Ok(user) => {
+                request.extensions.insert(AuthenticatedUser(user));
+            }
+            Err(_) => {
+                tracing::debug!("Invalid token, only PreAuth operations allowed");
+                request.extensions.insert(PreAuthOnly);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Layer rejecting the operations called without an [`AuthenticatedUser`]
+/// with a `ter:NotAuthorized` fault, except the `PreAuth` ones.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RequireAuthentication;
+
+impl Layer<BoxedSoapHandlerService> for RequireAuthentication {
+    type Service = RequireAuthenticationService;
+
+    fn layer(&self, inner: BoxedSoapHandlerService) -> Self::Service {
+        RequireAuthenticationService { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequireAuthenticationService {
+    inner: BoxedSoapHandlerService,
+}
+
+impl Service<SoapRequest> for RequireAuthenticationService {
+    type Response = SoapMessage;
+    type Error = SoapFault;
+    type Future = Pin<Box<dyn Future<Output = Result<SoapMessage, SoapFault>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: SoapRequest) -> Self::Future {
+        if req.extensions.get::<AuthenticatedUser>().is_none()
+            && access_class(&req) != Some(AccessClass::PreAuth)
+        {
+            return Box::pin(futures::future::ready(Err(not_authorized())));
+        }
+        self.inner.call(req)
+    }
+}
+
+/// Layer checking the level of the [`AuthenticatedUser`] against the access
+/// class of the operation, answering `ter:NotAuthorized` when it is too low.
+/// Operations without declared access class get the default one,
+/// `ReadSystem` unless changed, or `PreAuth` for the [`PRE_AUTH_OPERATIONS`].
+pub struct AccessControl<C> {
+    store: Arc<C>,
+    default_class: AccessClass,
+}
+
+impl<C> Clone for AccessControl<C> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            default_class: self.default_class,
+        }
+    }
+}
+
+impl<C: CredentialStore> AccessControl<C> {
+    pub fn new(store: Arc<C>) -> Self {
+        Self {
+            store,
+            default_class: AccessClass::ReadSystem,
+        }
+    }
+
+    pub fn with_default_class(mut self, class: AccessClass) -> Self {
+        self.default_class = class;
+        self
+    }
+
+    fn allowed(&self, req: &SoapRequest) -> bool {
+        let class = access_class(req).unwrap_or(self.default_class);
+        let level = match req.extensions.get::<AuthenticatedUser>() {
+            Some(user) => self.store.user_level(&user.0),
+            None => UserLevel::Anonymous,
+        };
+        level >= class.required_level()
+    }
+}
+
+impl<C: CredentialStore> Layer<BoxedSoapHandlerService> for AccessControl<C> {
+    type Service = AccessControlService<C>;
+
+    fn layer(&self, inner: BoxedSoapHandlerService) -> Self::Service {
+        AccessControlService {
+            inner,
+            policy: self.clone(),
+        }
+    }
+}
+
+pub struct AccessControlService<C> {
+    inner: BoxedSoapHandlerService,
+    policy: AccessControl<C>,
+}
+
+impl<C> Clone for AccessControlService<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            policy: self.policy.clone(),
+        }
+    }
+}
+
+impl<C: CredentialStore> Service<SoapRequest> for AccessControlService<C> {
+    type Response = SoapMessage;
+    type Error = SoapFault;
+    type Future = Pin<Box<dyn Future<Output = Result<SoapMessage, SoapFault>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: SoapRequest) -> Self::Future {
+        if !self.policy.allowed(&req) {
+            return Box::pin(futures::future::ready(Err(not_authorized())));
+        }
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{router::SoapRouter, ws_addressing::WSA_NAMESPACE};
+    use axum::{body::Body, http::Request};
+
+    const CREATED: &str = "2024-03-01T10:00:00Z";
+
+    fn digest(nonce: &[u8], created: &str, password: &str) -> String {
+        let mut hasher = Sha1::new();
+        hasher.update(nonce);
+        hasher.update(created);
+        hasher.update(password);
+        STANDARD.encode(hasher.finalize())
+    }
+
+    fn security() -> WsSecurity<HashMap<String, String>> {
+        let users = HashMap::from([("admin".to_string(), "secret".to_string())]);
+        WsSecurity::new(users).with_clock(|| parse_date_time("2024-03-01T10:01:00Z").unwrap())
+    }
+
+    fn token(password: Password, nonce: &[u8], created: &str) -> UsernameToken {
+        UsernameToken {
+            username: "admin".to_string(),
+            password,
+            nonce: Some(nonce.to_vec()),
+            created: Some(created.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_parse_date_time() {
+        assert_eq!(parse_date_time("1970-01-01T00:00:00Z"), Some(UNIX_EPOCH));
+        assert_eq!(
+            parse_date_time("2024-03-01T10:00:00.500Z"),
+            Some(UNIX_EPOCH + Duration::from_millis(1709287200500))
+        );
+        assert_eq!(
+            parse_date_time("2024-03-01T11:00:00+01:00"),
+            parse_date_time(CREATED)
+        );
+        assert_eq!(parse_date_time("2024-13-01T10:00:00Z"), None);
+        assert_eq!(parse_date_time("yesterday"), None);
+    }
+
+    #[test]
+    fn test_username_token() {
+        let security = security();
+        let ok = token(
+            Password::Digest(digest(b"nonce1", CREATED, "secret")),
+            b"nonce1",
+            CREATED,
+        );
+        assert_eq!(security.authenticate(&ok).unwrap(), "admin");
+        // Replayed nonce
+        assert!(security.authenticate(&ok).is_err());
+
+        let wrong = token(
+            Password::Digest(digest(b"nonce2", CREATED, "guess")),
+            b"nonce2",
+            CREATED,
+        );
+        assert!(security.authenticate(&wrong).is_err());
+
+        let old = "2024-03-01T09:00:00Z";
+        let expired = token(
+            Password::Digest(digest(b"nonce3", old, "secret")),
+            b"nonce3",
+            old,
+        );
+        assert!(security.authenticate(&expired).is_err());
+
+        let text = token(Password::Text("secret".to_string()), b"nonce4", CREATED);
+        assert!(security.authenticate(&text).is_ok());
+        let text = token(Password::Text("secre".to_string()), b"nonce5", CREATED);
+        assert!(security.authenticate(&text).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ws_security_multiple_operations() {
+        let ok = || async move { Ok(SoapMessage::new()) };
+        let mut router = SoapRouter::new(())
+            .add_header_processor(
+                WSSE_NAMESPACE.to_string(),
+                "Security".to_string(),
+                security(),
+            )
+            .add_operation("http://www.example.org".to_string(), "A".to_string(), ok)
+            .add_operation("http://www.example.org".to_string(), "B".to_string(), ok)
+            .route_layer(RequireAuthentication);
+
+        // The nonce is only recorded once for the envelope
+        let nonce = b"multi-nonce";
+        let raw = format!(
+            r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                <env:Header>
+                    <wsse:Security xmlns:wsse="{WSSE_NAMESPACE}" xmlns:wsu="{WSU_NAMESPACE}">
+                        <wsse:UsernameToken>
+                            <wsse:Username>admin</wsse:Username>
+                            <wsse:Password Type="{PASSWORD_DIGEST}">{}</wsse:Password>
+                            <wsse:Nonce>{}</wsse:Nonce>
+                            <wsu:Created>{CREATED}</wsu:Created>
+                        </wsse:UsernameToken>
+                    </wsse:Security>
+                </env:Header>
+                <env:Body><m:A/><m:B/></env:Body>
+            </env:Envelope>"#,
+            digest(nonce, CREATED, "secret"),
+            STANDARD.encode(nonce)
+        );
+        for authorized in [true, false] {
+            let req: Request<Body> = Request::builder()
+                .uri("/")
+                .body(raw.clone().into())
+                .unwrap();
+            let resp = router.call(req).await.unwrap();
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let msg = SoapMessage::from(Element::parse(body.as_ref()).unwrap());
+            let fault = msg
+                .get_body()
+                .get_child(("Fault", "http://www.w3.org/2003/05/soap-envelope"));
+            assert_eq!(fault.is_none(), authorized);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ws_security_router() {
+        let mut router = SoapRouter::new(())
+            .add_header_processor(
+                WSSE_NAMESPACE.to_string(),
+                "Security".to_string(),
+                security(),
+            )
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Test".to_string(),
+                || async move { Ok(SoapMessage::new()) },
+            )
+            .route_layer(RequireAuthentication);
+
+        let nonce = b"router-nonce";
+        let header = format!(
+            r#"<wsse:Security env:mustUnderstand="true" xmlns:wsse="{WSSE_NAMESPACE}" xmlns:wsu="{WSU_NAMESPACE}">
+                <wsse:UsernameToken>
+                    <wsse:Username>admin</wsse:Username>
+                    <wsse:Password Type="{PASSWORD_DIGEST}">{}</wsse:Password>
+                    <wsse:Nonce>{}</wsse:Nonce>
+                    <wsu:Created>{CREATED}</wsu:Created>
+                </wsse:UsernameToken>
+            </wsse:Security>"#,
+            digest(nonce, CREATED, "secret"),
+            STANDARD.encode(nonce)
+        );
+        for (header, authorized) in [(header.as_str(), true), ("", false)] {
+            let raw = format!(
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                    <env:Header>{}</env:Header>
+                    <env:Body><m:Test/></env:Body>
+                </env:Envelope>"#,
+                header
+            );
+            let req: Request<Body> = Request::builder().uri("/").body(raw.into()).unwrap();
+            let resp = router.call(req).await.unwrap();
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let msg = SoapMessage::from(Element::parse(body.as_ref()).unwrap());
+            let fault = msg
+                .get_body()
+                .get_child(("Fault", "http://www.w3.org/2003/05/soap-envelope"));
+            assert_eq!(fault.is_none(), authorized);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_access_control() {
+        let users = Arc::new(HashMap::from([
+            (
+                "admin".to_string(),
+                ("admin".to_string(), UserLevel::Administrator),
+            ),
+            (
+                "operator".to_string(),
+                ("operator".to_string(), UserLevel::Operator),
+            ),
+        ]));
+        let ns = "http://www.example.org".to_string();
+        let ok = || async move { Ok(SoapMessage::new()) };
+        let mut router = SoapRouter::new(())
+            .add_header_processor(
+                WSSE_NAMESPACE.to_string(),
+                "Security".to_string(),
+                WsSecurity::from_shared(users.clone()),
+            )
+            .add_operation_with_access(ns.clone(), "GetTime".to_string(), AccessClass::PreAuth, ok)
+            .add_operation_with_access(ns.clone(), "Move".to_string(), AccessClass::Actuate, ok)
+            .add_operation_with_access(
+                ns.clone(),
+                "Reboot".to_string(),
+                AccessClass::Unrecoverable,
+                ok,
+            )
+            .add_operation(ns.clone(), "GetInfo".to_string(), ok)
+            .route_layer(AccessControl::new(users));
+
+        for (user, op, allowed) in [
+            (None, "GetTime", true),
+            (None, "GetInfo", false),
+            (None, "Move", false),
+            (Some("operator"), "GetInfo", true),
+            (Some("operator"), "Move", true),
+            (Some("operator"), "Reboot", false),
+            (Some("admin"), "Reboot", true),
+        ] {
+            let header = user
+                .map(|u| {
+                    format!(
+                        r#"<wsse:Security xmlns:wsse="{WSSE_NAMESPACE}"><wsse:UsernameToken>
+                            <wsse:Username>{u}</wsse:Username>
+                            <wsse:Password Type="{PASSWORD_TEXT}">{u}</wsse:Password>
+                        </wsse:UsernameToken></wsse:Security>"#
+                    )
+                })
+                .unwrap_or_default();
+            let raw = format!(
+                r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:m="http://www.example.org">
+                    <env:Header>{header}</env:Header>
+                    <env:Body><m:{op}/></env:Body>
+                </env:Envelope>"#
+            );
+            let req: Request<Body> = Request::builder().uri("/").body(raw.into()).unwrap();
+            let resp = router.call(req).await.unwrap();
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let msg = SoapMessage::from(Element::parse(body.as_ref()).unwrap());
+            let fault = msg
+                .get_body()
+                .get_child(("Fault", "http://www.w3.org/2003/05/soap-envelope"));
+            assert_eq!(fault.is_none(), allowed, "{:?} {}", user, op);
+        }
+    }
+
+    /// Call `router` without fault
+    async fn authorized(router: &mut SoapRouter<()>, header: &str, body: &str) -> bool {
+        let raw = format!(
+            r#"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:tds="{DEVICE_NAMESPACE}">
+                <env:Header>{header}</env:Header>
+                <env:Body>{body}</env:Body>
+            </env:Envelope>"#
+        );
+        let req: Request<Body> = Request::builder().uri("/").body(raw.into()).unwrap();
+        let resp = router.call(req).await.unwrap();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let msg = SoapMessage::from(Element::parse(body.as_ref()).unwrap());
+        msg.get_body()
+            .get_child(("Fault", "http://www.w3.org/2003/05/soap-envelope"))
+            .is_none()
+    }
+
+    #[tokio::test]
+    async fn test_pre_auth() {
+        let users = Arc::new(HashMap::from([(
+            "admin".to_string(),
+            ("admin".to_string(), UserLevel::Administrator),
+        )]));
+        let ok = || async move { Ok(SoapMessage::new()) };
+        let device = SoapRouter::new(())
+            .add_header_processor(
+                WSSE_NAMESPACE.to_string(),
+                "Security".to_string(),
+                WsSecurity::from_shared(users.clone()),
+            )
+            .add_operation(
+                DEVICE_NAMESPACE.to_string(),
+                "GetSystemDateAndTime".to_string(),
+                ok,
+            )
+            .add_operation(DEVICE_NAMESPACE.to_string(), "GetUsers".to_string(), ok)
+            .add_operation_with_access(
+                DEVICE_NAMESPACE.to_string(),
+                "GetHostname".to_string(),
+                AccessClass::ReadSystem,
+                ok,
+            )
+            .add_action_route("urn:example:Protected".to_string(), ok)
+            .add_action_route_with_access(
+                "urn:example:Public".to_string(),
+                AccessClass::PreAuth,
+                ok,
+            );
+        let mut bare = device.clone();
+        let mut router = device
+            .route_layer(AccessControl::new(users))
+            .route_layer(RequireAuthentication);
+
+        let token = |password: &str| {
+            format!(
+                r#"<wsse:Security xmlns:wsse="{WSSE_NAMESPACE}"><wsse:UsernameToken>
+                    <wsse:Username>admin</wsse:Username>
+                    <wsse:Password Type="{PASSWORD_TEXT}">{password}</wsse:Password>
+                </wsse:UsernameToken></wsse:Security>"#
+            )
+        };
+        let action = |action: &str| {
+            format!(r#"<wsa:Action xmlns:wsa="{WSA_NAMESPACE}">{action}</wsa:Action>"#)
+        };
+        let (guess, admin) = (token("guess"), token("admin"));
+        let (protected, public) = (
+            action("urn:example:Protected"),
+            action("urn:example:Public"),
+        );
+        for (header, body, allowed) in [
+            ("", "<tds:GetSystemDateAndTime/>", true),
+            (&guess, "<tds:GetSystemDateAndTime/>", true),
+            ("", "<tds:GetUsers/>", false),
+            (&guess, "<tds:GetUsers/>", false),
+            (&admin, "<tds:GetUsers/>", true),
+            // Declared access class wins over the pre-auth list
+            ("", "<tds:GetHostname/>", false),
+            // The action route is dispatched, whatever the body
+            (&protected, "<tds:GetSystemDateAndTime/>", false),
+            (&public, "<tds:GetUsers/>", true),
+        ] {
+            assert_eq!(
+                authorized(&mut router, header, body).await,
+                allowed,
+                "{} {}",
+                header,
+                body
+            );
+        }
+
+        // Rejected credentials are enforced by the router itself
+        for (body, allowed) in [
+            ("<tds:GetSystemDateAndTime/>", true),
+            ("<tds:GetSystemDateAndTime/><tds:GetUsers/>", false),
+        ] {
+            assert_eq!(
+                authorized(&mut bare, &guess, body).await,
+                allowed,
+                "{}",
+                body
+            );
+        }
+        let header = format!("{guess}{protected}");
+        assert!(!authorized(&mut bare, &header, "<tds:GetSystemDateAndTime/>").await);
+    }
+}
@@ -0,0 +1,233 @@
+use std::io::Read;
+
+use bytes::Bytes;
+use quick_xml::{
+    events::{BytesStart, Event},
+    name::{PrefixDeclaration, ResolveResult},
+    NsReader,
+};
+use xmltree::Element;
+
+/// Location of a body entry in the raw request envelope.
+///
+/// Typed deserialization reads the entry straight from the request buffer
+/// instead of writing the parsed element back out, the namespaces the entry
+/// inherits from `Envelope` and `Body` are added to its start tag on the fly.
+#[derive(Clone, Debug)]
+pub struct BodySource {
+    doc: Bytes,
+    start: usize,
+    name_end: usize,
+    end: usize,
+    declarations: Vec<u8>,
+    name: String,
+    namespace: Option<String>,
+}
+
+impl BodySource {
+    /// Whether the source is the given body entry
+    pub fn matches(&self, elem: &Element) -> bool {
+        elem.name == self.name && elem.namespace == self.namespace
+    }
+
+    /// The entry as a standalone document
+    pub fn reader(&self) -> impl Read + '_ {
+        self.doc[self.start..self.name_end]
+            .chain(self.declarations.as_slice())
+            .chain(&self.doc[self.name_end..self.end])
+    }
+}
+
+/// Locate the body entries of an envelope with a single pass of quick-xml
+/// events.
+///
+/// Returns nothing when the entries cannot be handed out as standalone
+/// documents (non UTF-8 encoding, DTD, malformed input), callers then fall
+/// back to the parsed elements.
+pub fn body_sources(doc: &Bytes) -> Vec<BodySource> {
+    scan(doc).unwrap_or_default()
+}
+
+fn scan(doc: &Bytes) -> Option<Vec<BodySource>> {
+    let mut reader = NsReader::from_reader(doc.as_ref());
+    let mut sources = vec![];
+    // Namespace declarations of Envelope then Body, later ones win
+    let mut inherited: Vec<(Vec<u8>, Vec<u8>)> = vec![];
+    let mut envelope_namespace = None;
+    let mut in_body = false;
+    let mut depth = 0usize;
+    let mut current: Option<BodySource> = None;
+    loop {
+        let position = reader.buffer_position();
+        let (ns, event) = reader.read_resolved_event().ok()?;
+        match event {
+            Event::Decl(decl) => {
+                if let Some(encoding) = decl.encoding() {
+                    if !encoding.ok()?.eq_ignore_ascii_case(b"utf-8") {
+                        return None;
+                    }
+                }
+            }
+            Event::DocType(_) => return None,
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                let empty = matches!(event, Event::Empty(_));
+                let namespace = match ns {
+                    ResolveResult::Bound(ns) => Some(String::from_utf8(ns.0.to_vec()).ok()?),
+                    _ => None,
+                };
+                match depth {
+                    0 => {
+                        inherited.extend(declarations(e)?);
+                        envelope_namespace = namespace;
+                    }
+                    1 if e.local_name().as_ref() == b"Body"
+                        && namespace.is_some()
+                        && namespace == envelope_namespace =>
+                    {
+                        inherited.extend(declarations(e)?);
+                        in_body = !empty;
+                    }
+                    2 if in_body => {
+                        let source = BodySource {
+                            doc: doc.clone(),
+                            start: position,
+                            name_end: position + 1 + e.name().as_ref().len(),
+                            end: reader.buffer_position(),
+                            declarations: render(&inherited, &declarations(e)?),
+                            name: String::from_utf8(e.local_name().as_ref().to_vec()).ok()?,
+                            namespace,
+                        };
+                        match empty {
+                            true => sources.push(source),
+                            false => current = Some(source),
+                        }
+                    }
+                    _ => {}
+                }
+                if !empty {
+                    depth += 1;
+                }
+            }
+            Event::End(_) => {
+                depth = depth.checked_sub(1)?;
+                match depth {
+                    1 => in_body = false,
+                    2 => {
+                        if let Some(mut source) = current.take() {
+                            source.end = reader.buffer_position();
+                            sources.push(source);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Some(sources)
+}
+
+/// Prefix (empty for the default namespace) and raw value of the `xmlns`
+/// attributes of a start tag
+fn declarations(e: &BytesStart) -> Option<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut found = vec![];
+    for attr in e.attributes() {
+        let attr = attr.ok()?;
+        let prefix = match attr.key.as_namespace_binding() {
+            Some(PrefixDeclaration::Default) => vec![],
+            Some(PrefixDeclaration::Named(prefix)) => prefix.to_vec(),
+            None => continue,
+        };
+        found.push((prefix, attr.value.into_owned()));
+    }
+    Some(found)
+}
+
+/// Inherited declarations not overridden by the entry, as attributes
+fn render(inherited: &[(Vec<u8>, Vec<u8>)], own: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+    let mut out = vec![];
+    for (index, (prefix, value)) in inherited.iter().enumerate() {
+        let shadowed = inherited[index + 1..].iter().any(|(p, _)| p == prefix)
+            || own.iter().any(|(p, _)| p == prefix);
+        if shadowed {
+            continue;
+        }
+        out.extend_from_slice(b" xmlns");
+        if !prefix.is_empty() {
+            out.push(b':');
+            out.extend_from_slice(prefix);
+        }
+        // Values are copied as found, quote them the way they were quoted
+        let quote = match value.contains(&b'"') {
+            true => b'\'',
+            false => b'"',
+        };
+        out.push(b'=');
+        out.push(quote);
+        out.extend_from_slice(value);
+        out.push(quote);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(source: &BodySource) -> String {
+        let mut out = String::new();
+        source.reader().read_to_string(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_body_sources() {
+        let doc = Bytes::from_static(
+            br#"<?xml version="1.0" encoding="UTF-8"?>
+<s:Envelope xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:a="urn:a">
+<s:Header><a:H/></s:Header>
+<s:Body xmlns:b="urn:b">
+<a:First x="1"><a:Inner/></a:First>
+<b:Second xmlns:a="urn:other"/>
+</s:Body>
+</s:Envelope>"#,
+        );
+        let sources = body_sources(&doc);
+        assert_eq!(sources.len(), 2);
+
+        assert_eq!(sources[0].name, "First");
+        assert_eq!(sources[0].namespace.as_deref(), Some("urn:a"));
+        assert_eq!(
+            read(&sources[0]),
+            r#"<a:First xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:a="urn:a" xmlns:b="urn:b" x="1"><a:Inner/></a:First>"#
+        );
+
+        // The entry redeclares a, the inherited declaration is left out
+        assert_eq!(sources[1].namespace.as_deref(), Some("urn:b"));
+        assert_eq!(
+            read(&sources[1]),
+            r#"<b:Second xmlns:s="http://www.w3.org/2003/05/soap-envelope" xmlns:b="urn:b" xmlns:a="urn:other"/>"#
+        );
+
+        let parsed = Element::parse(read(&sources[0]).as_bytes()).unwrap();
+        assert!(sources[0].matches(&parsed));
+        assert!(!sources[1].matches(&parsed));
+    }
+
+    #[test]
+    fn test_body_sources_fallback() {
+        for doc in [
+            // Malformed
+            &br#"<s:Envelope xmlns:s="urn:s"><s:Body><a></s:Body></s:Envelope>"#[..],
+            // Not UTF-8
+            br#"<?xml version="1.0" encoding="ISO-8859-1"?><s:Envelope xmlns:s="urn:s"><s:Body><a/></s:Body></s:Envelope>"#,
+            // Entities from a DTD
+            br#"<!DOCTYPE s:Envelope [<!ENTITY e "x">]><s:Envelope xmlns:s="urn:s"><s:Body><a>&e;</a></s:Body></s:Envelope>"#,
+        ] {
+            assert!(body_sources(&Bytes::from_static(doc)).is_empty());
+        }
+        let doc = br#"<s:Envelope xmlns:s="urn:s"><s:Body/></s:Envelope>"#;
+        assert!(body_sources(&Bytes::from_static(doc)).is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+use std::sync::Arc;
+
+use url::Url;
+
+use crate::{
+    extract::FromSoapRequest, fault::SoapFault, forwarded::RequestOrigin, router::SoapRequest,
+};
+
+/// Kind of the URIs handed out to clients
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UriKind {
+    /// Service endpoints, from XAddrs and WSDL locations
+    Service,
+    Stream,
+    Snapshot,
+    Subscription,
+    /// System URIs such as backups, logs or firmware upgrades
+    System,
+}
+
+/// Policy building every URI handed out to clients, from the origin of the
+/// request and the absolute path of the resource. Integrators can use it to
+/// add ports, path prefixes or signed tokens in a single place.
+pub trait UriBuilder: Send + Sync + 'static {
+    fn build(&self, origin: &RequestOrigin, kind: UriKind, path: &str) -> Option<Url>;
+}
+
+impl<F> UriBuilder for F
+where
+    F: Fn(&RequestOrigin, UriKind, &str) -> Option<Url> + Send + Sync + 'static,
+{
+    fn build(&self, origin: &RequestOrigin, kind: UriKind, path: &str) -> Option<Url> {
+        self(origin, kind, path)
+    }
+}
+
+/// Resolve the path against the origin of the request
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultUriBuilder;
+
+impl UriBuilder for DefaultUriBuilder {
+    fn build(&self, origin: &RequestOrigin, _kind: UriKind, path: &str) -> Option<Url> {
+        origin.base_url()?.join(path).ok()
+    }
+}
+
+/// URI builder of the router along with the origin of the request being
+/// handled
+#[derive(Clone)]
+pub struct Uris {
+    origin: Option<RequestOrigin>,
+    builder: Arc<dyn UriBuilder>,
+}
+
+impl Uris {
+    pub(crate) fn new(origin: Option<RequestOrigin>, builder: Arc<dyn UriBuilder>) -> Self {
+        Self { origin, builder }
+    }
+
+    /// `None` when the origin of the request is unknown or the builder
+    /// refused the path
+    pub fn build(&self, kind: UriKind, path: &str) -> Option<Url> {
+        self.builder.build(self.origin.as_ref()?, kind, path)
+    }
+}
+
+impl<S> FromSoapRequest<S> for Uris {
+    fn from_soap_request(req: &SoapRequest, _state: &S) -> Result<Self, SoapFault> {
+        Ok(req.extensions.get::<Uris>().cloned().unwrap_or_else(|| {
+            Uris::new(
+                req.extensions.get::<RequestOrigin>().cloned(),
+                Arc::new(DefaultUriBuilder),
+            )
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uri_builder() {
+        let origin = RequestOrigin {
+            scheme: "http".to_string(),
+            host: "192.168.1.2".to_string(),
+        };
+        let uris = Uris::new(Some(origin.clone()), Arc::new(DefaultUriBuilder));
+        assert_eq!(
+            uris.build(UriKind::Service, "/onvif/device_service")
+                .unwrap()
+                .as_str(),
+            "http://192.168.1.2/onvif/device_service"
+        );
+
+        let uris = Uris::new(
+            Some(origin),
+            Arc::new(|origin: &RequestOrigin, kind: UriKind, path: &str| {
+                let mut url = DefaultUriBuilder.build(origin, kind, &format!("/camera{}", path))?;
+                if kind == UriKind::Snapshot {
+                    url.set_port(Some(8081)).ok()?;
+                    url.set_query(Some("token=secret"));
+                }
+                Some(url)
+            }),
+        );
+        assert_eq!(
+            uris.build(UriKind::Snapshot, "/snapshot.jpg")
+                .unwrap()
+                .as_str(),
+            "http://192.168.1.2:8081/camera/snapshot.jpg?token=secret"
+        );
+        assert_eq!(
+            uris.build(UriKind::Service, "/onvif/device_service")
+                .unwrap()
+                .as_str(),
+            "http://192.168.1.2/camera/onvif/device_service"
+        );
+        assert!(Uris::new(None, Arc::new(DefaultUriBuilder))
+            .build(UriKind::Stream, "/stream")
+            .is_none());
+    }
+}
@@ -0,0 +1,113 @@
+use xmltree::{Element, XMLNode};
+
+pub use soap_core::version::{SoapVersion, SOAP11_NAMESPACE, SOAP12_NAMESPACE};
+
+/// Parsed `Content-Type` of a SOAP request
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContentType {
+    pub version: SoapVersion,
+    /// `action` parameter, SOAP 1.2 only
+    pub action: Option<String>,
+    pub charset: Option<String>,
+}
+
+impl ContentType {
+    /// Parse a `text/xml` or `application/soap+xml` media type, along with
+    /// its parameters.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split(';');
+        let version = match parts.next()?.trim().to_ascii_lowercase().as_str() {
+            "text/xml" => SoapVersion::Soap11,
+            "application/soap+xml" => SoapVersion::Soap12,
+            _ => return None,
+        };
+        let mut content_type = Self {
+            version,
+            action: None,
+            charset: None,
+        };
+        for param in parts {
+            let Some((name, value)) = param.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().trim_matches('"').to_string();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "action" if version == SoapVersion::Soap12 => content_type.action = Some(value),
+                "charset" => content_type.charset = Some(value.to_ascii_lowercase()),
+                _ => (),
+            }
+        }
+        Some(content_type)
+    }
+
+    /// True when the charset is absent or one this crate can decode
+    pub fn is_supported_charset(&self) -> bool {
+        matches!(
+            self.charset.as_deref(),
+            None | Some("utf-8") | Some("utf8") | Some("us-ascii")
+        )
+    }
+
+    /// `Content-Type` header value of a response for the given version
+    pub fn response(version: SoapVersion) -> String {
+        format!("{}; charset=utf-8", version.content_type())
+    }
+}
+
+/// Move all the elements of an envelope to the namespace of the given SOAP
+/// version, along with the matching namespace declarations, except the
+/// entries of SOAP 1.1 fault details.
+pub(crate) fn convert_envelope(mut elem: Element, to: SoapVersion) -> Element {
+    let from = match to {
+        SoapVersion::Soap11 => SOAP12_NAMESPACE,
+        SoapVersion::Soap12 => SOAP11_NAMESPACE,
+    };
+    if elem.namespace.as_deref() == Some(from) {
+        elem.namespace = Some(to.namespace().to_string());
+    }
+    if let Some(ns) = elem.namespaces.as_mut() {
+        ns.0.values_mut()
+            .filter(|uri| uri.as_str() == from)
+            .for_each(|uri| *uri = to.namespace().to_string());
+    }
+    // Entries of SOAP 1.1 fault details belong to the application
+    if elem.name == "detail" && elem.namespace.is_none() {
+        return elem;
+    }
+    elem.children = elem
+        .children
+        .into_iter()
+        .map(|c| match c {
+            XMLNode::Element(e) => XMLNode::Element(convert_envelope(e, to)),
+            c => c,
+        })
+        .collect();
+    elem
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_type() {
+        assert_eq!(
+            ContentType::parse(
+                r#"application/soap+xml; charset=UTF-8; action="http://www.example.org/Get""#
+            ),
+            Some(ContentType {
+                version: SoapVersion::Soap12,
+                action: Some("http://www.example.org/Get".to_string()),
+                charset: Some("utf-8".to_string()),
+            })
+        );
+        let content_type = ContentType::parse("Text/XML;charset=iso-8859-1").unwrap();
+        assert_eq!(content_type.version, SoapVersion::Soap11);
+        assert!(!content_type.is_supported_charset());
+        assert_eq!(
+            ContentType::parse("text/xml; action=x").unwrap().action,
+            None
+        );
+        assert_eq!(ContentType::parse("application/json"), None);
+    }
+}
@@ -0,0 +1,301 @@
+use axum::{http::header::CONTENT_TYPE, response::Response};
+use url::Url;
+use xmltree::{Element, XMLNode};
+
+use crate::{
+    extract::FromSoapRequest,
+    fault::{SoapFault, SoapFaultCode},
+    router::{SoapMessage, SoapRequest},
+    soap_client::SoapClient,
+    version::SoapVersion,
+};
+
+pub const WSA_NAMESPACE: &str = "http://www.w3.org/2005/08/addressing";
+pub const WSA_ANONYMOUS: &str = "http://www.w3.org/2005/08/addressing/anonymous";
+pub const WSA_NONE: &str = "http://www.w3.org/2005/08/addressing/none";
+pub const WSA_FAULT_ACTION: &str = "http://www.w3.org/2005/08/addressing/soap/fault";
+
+/// Header blocks processed by the router itself
+pub(crate) const UNDERSTOOD_HEADERS: &[&str] = &[
+    "Action",
+    "To",
+    "From",
+    "MessageID",
+    "RelatesTo",
+    "ReplyTo",
+    "FaultTo",
+];
+
+/// WS-Addressing message information headers of a request
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AddressingHeaders {
+    pub action: Option<String>,
+    pub to: Option<String>,
+    pub message_id: Option<String>,
+    /// Address of the ReplyTo endpoint reference
+    pub reply_to: Option<String>,
+    /// Address of the FaultTo endpoint reference
+    pub fault_to: Option<String>,
+}
+
+impl AddressingHeaders {
+    pub fn from_headers(headers: &Element) -> Self {
+        Self {
+            action: text(headers, "Action"),
+            to: text(headers, "To"),
+            message_id: text(headers, "MessageID"),
+            reply_to: headers
+                .get_child(("ReplyTo", WSA_NAMESPACE))
+                .and_then(|r| text(r, "Address")),
+            fault_to: headers
+                .get_child(("FaultTo", WSA_NAMESPACE))
+                .and_then(|r| text(r, "Address")),
+        }
+    }
+
+    /// True if the request uses WS-Addressing at all
+    pub fn is_present(&self) -> bool {
+        self.action.is_some() || self.message_id.is_some()
+    }
+}
+
+/// Which `wsa:ReplyTo` and `wsa:FaultTo` addresses a router accepts
+#[derive(Clone, Debug, Default)]
+pub enum AddressPolicy {
+    /// Only the anonymous and none addresses are accepted
+    #[default]
+    AnonymousOnly,
+    /// Non-anonymous addresses are accepted when they are under one of the
+    /// given base URLs (same scheme, host and port, path prefix)
+    AllowList(Vec<Url>),
+}
+
+impl AddressPolicy {
+    /// Return the `wsa:InvalidAddressingHeader` fault for the first response
+    /// endpoint of the request this policy refuses.
+    pub fn check(&self, headers: &AddressingHeaders) -> Result<(), SoapFault> {
+        for (name, address) in [
+            ("ReplyTo", &headers.reply_to),
+            ("FaultTo", &headers.fault_to),
+        ] {
+            let Some(address) = address else { continue };
+            if address == WSA_ANONYMOUS || address == WSA_NONE {
+                continue;
+            }
+            match self {
+                AddressPolicy::AnonymousOnly => {
+                    return Err(invalid_addressing_header(
+                        name,
+                        "OnlyAnonymousAddressSupported",
+                    ))
+                }
+                AddressPolicy::AllowList(allowed) => {
+                    let url = Url::parse(address)
+                        .map_err(|_| invalid_addressing_header(name, "InvalidAddress"))?;
+                    if !allowed.iter().any(|base| is_under(&url, base)) {
+                        return Err(invalid_addressing_header(name, "InvalidAddress"));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_under(url: &Url, base: &Url) -> bool {
+    url.scheme() == base.scheme()
+        && url.host_str() == base.host_str()
+        && url.port_or_known_default() == base.port_or_known_default()
+        && url.path().strip_prefix(base.path()).is_some_and(|rest| {
+            // Whole segments only, `/soap` doesn't allow `/soapevil`
+            base.path().ends_with('/') || rest.is_empty() || rest.starts_with('/')
+        })
+}
+
+fn invalid_addressing_header(header_name: &str, subcode: &str) -> SoapFault {
+    let wsa = Url::parse(WSA_NAMESPACE).unwrap();
+    let mut detail = header("ProblemHeaderQName", &format!("wsa:{}", header_name));
+    detail.prefix = Some("wsa".to_string());
+    SoapFault::builder(SoapFaultCode::Sender)
+        .subcode(wsa.clone(), "InvalidAddressingHeader")
+        .subcode(wsa, subcode)
+        .builtin_reason("InvalidAddressingHeader")
+        .detail(detail)
+        .build()
+}
+
+impl<S> FromSoapRequest<S> for AddressingHeaders {
+    fn from_soap_request(req: &SoapRequest, _state: &S) -> Result<Self, SoapFault> {
+        Ok(Self::from_headers(&req.headers))
+    }
+}
+
+fn text(parent: &Element, name: &str) -> Option<String> {
+    parent
+        .get_child((name, WSA_NAMESPACE))
+        .and_then(|e| e.get_text())
+        .map(|t| t.trim().to_string())
+}
+
+/// Build a WS-Addressing header block holding the given text
+pub fn header(name: &str, value: &str) -> Element {
+    let mut elem = Element::new(name);
+    elem.prefix = Some("wsa".to_string());
+    elem.namespace = Some(WSA_NAMESPACE.to_string());
+    let mut namespaces = xmltree::Namespace::empty();
+    namespaces.put("wsa", WSA_NAMESPACE);
+    elem.namespaces = Some(namespaces);
+    elem.children.push(XMLNode::Text(value.to_string()));
+    elem
+}
+
+/// Send a reply or a fault to a non-anonymous endpoint, the outcome is not
+/// reported to the original requester.
+pub(crate) async fn deliver(
+    address: String,
+    action: String,
+    version: SoapVersion,
+    message: Response,
+) {
+    // Only multipart messages keep their own content type
+    let content_type = message
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| v.starts_with("multipart/"))
+        .map(str::to_string);
+    let Ok(body) = hyper::body::to_bytes(message.into_body()).await else {
+        return;
+    };
+    let client = SoapClient::new().with_version(version);
+    let _ = client
+        .post_body(&address, &action, content_type, body.into())
+        .await;
+}
+
+/// Default action of the reply to a request action, following the WSDL
+/// naming of WS-Addressing: `.../GetDeviceInformationRequest` is answered with
+/// `.../GetDeviceInformationResponse`.
+fn reply_action(action: &str) -> String {
+    format!(
+        "{}Response",
+        action.strip_suffix("Request").unwrap_or(action)
+    )
+}
+
+/// Add the reply message information headers to a response: `wsa:Action`
+/// (unless the handler already set one through its
+/// [`ResponseHeaders`](crate::extract::ResponseHeaders)), `wsa:MessageID` and
+/// `wsa:RelatesTo`.
+pub(crate) fn stamp_reply(
+    msg: &mut SoapMessage,
+    request: &AddressingHeaders,
+    fault: bool,
+    message_id: String,
+) {
+    if !request.is_present() {
+        return;
+    }
+    let action = if fault {
+        Some(WSA_FAULT_ACTION.to_string())
+    } else {
+        request.action.as_deref().map(reply_action)
+    };
+    let headers = msg.get_mut_headers();
+    if let Some(action) = action {
+        if headers.get_child(("Action", WSA_NAMESPACE)).is_none() {
+            headers
+                .children
+                .push(XMLNode::Element(header("Action", &action)));
+        }
+    }
+    headers
+        .children
+        .push(XMLNode::Element(header("MessageID", &message_id)));
+    if let Some(id) = &request.message_id {
+        headers
+            .children
+            .push(XMLNode::Element(header("RelatesTo", id)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_reply_to(address: &str) -> AddressingHeaders {
+        AddressingHeaders {
+            reply_to: Some(address.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn subcode(fault: SoapFault) -> String {
+        fault.sub_codes().last().unwrap().1.clone()
+    }
+
+    #[test]
+    fn test_reply_action() {
+        assert_eq!(
+            reply_action("http://www.onvif.org/ver10/device/wsdl/GetDeviceInformationRequest"),
+            "http://www.onvif.org/ver10/device/wsdl/GetDeviceInformationResponse"
+        );
+        assert_eq!(
+            reply_action("http://www.example.org/Ping"),
+            "http://www.example.org/PingResponse"
+        );
+    }
+
+    #[test]
+    fn test_address_policy() {
+        let policy = AddressPolicy::default();
+        assert!(policy.check(&with_reply_to(WSA_ANONYMOUS)).is_ok());
+        assert!(policy.check(&with_reply_to(WSA_NONE)).is_ok());
+        assert_eq!(
+            subcode(
+                policy
+                    .check(&with_reply_to("http://client.example.org/reply"))
+                    .unwrap_err()
+            ),
+            "OnlyAnonymousAddressSupported"
+        );
+
+        let policy =
+            AddressPolicy::AllowList(vec![Url::parse("http://client.example.org/soap/").unwrap()]);
+        assert!(policy
+            .check(&with_reply_to("http://client.example.org/soap/reply"))
+            .is_ok());
+        for address in [
+            "http://client.example.org:8080/soap/reply",
+            "https://client.example.org/soap/reply",
+            "http://169.254.169.254/soap/",
+            "http://client.example.org/other",
+            "not an url",
+        ] {
+            assert_eq!(
+                subcode(policy.check(&with_reply_to(address)).unwrap_err()),
+                "InvalidAddress"
+            );
+        }
+
+        let policy =
+            AddressPolicy::AllowList(vec![Url::parse("http://client.example.org/soap").unwrap()]);
+        for (address, allowed) in [
+            ("http://client.example.org/soap", true),
+            ("http://client.example.org/soap/reply", true),
+            ("http://client.example.org/soapevil/reply", false),
+        ] {
+            assert_eq!(
+                policy.check(&with_reply_to(address)).is_ok(),
+                allowed,
+                "{}",
+                address
+            );
+        }
+        let headers = AddressingHeaders {
+            fault_to: Some("http://attacker.example.org/".to_string()),
+            ..with_reply_to("http://client.example.org/soap/reply")
+        };
+        assert!(policy.check(&headers).is_err());
+    }
+}
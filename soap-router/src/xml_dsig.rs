@@ -0,0 +1,668 @@
+//! Verification of XML signatures of the `wsse:Security` header, made with
+//! the key of an X.509 `wsse:BinarySecurityToken` as described by the
+//! WS-Security X.509 token profile. Only exclusive canonicalization and
+//! references to elements of the envelope are supported.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use tower::Layer;
+use tower_service::Service;
+use xmltree::Element;
+
+use crate::{
+    c14n::{canonicalize_element, canonicalize_id, EXC_C14N_ALGORITHM},
+    extract::RawEnvelope,
+    fault::SoapFault,
+    interceptor::HeaderProcessor,
+    router::{BoxedSoapHandlerService, SoapMessage, SoapRequest},
+    soap_security::{
+        constant_time_eq, not_authorized, parse_date_time, AuthenticatedUser, Clock,
+        WSSE_NAMESPACE, WSU_NAMESPACE,
+    },
+};
+
+pub const DS_NAMESPACE: &str = "http://www.w3.org/2000/09/xmldsig#";
+pub const SHA1_DIGEST: &str = "http://www.w3.org/2000/09/xmldsig#sha1";
+pub const SHA256_DIGEST: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+pub const RSA_SHA1_SIGNATURE: &str = "http://www.w3.org/2000/09/xmldsig#rsa-sha1";
+pub const RSA_SHA256_SIGNATURE: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+pub const X509_TOKEN: &str =
+    "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-x509-token-profile-1.0#X509v3";
+
+/// Largest number of `ds:Reference` of a signature, each one canonicalizes
+/// part of the envelope
+const MAX_REFERENCES: usize = 16;
+
+/// Cryptographic check of the signatures along with the trust decision on
+/// the certificates, typically backed by the trust anchors of a keystore.
+pub trait SignatureVerifier: Send + Sync + 'static {
+    /// Check that `signature` of `data` was made with the key of the DER
+    /// encoded `certificate` using the `ds:SignatureMethod` `algorithm`, and
+    /// that the certificate is trusted. Returns the name of the user the
+    /// certificate maps to.
+    fn verify(
+        &self,
+        certificate: &[u8],
+        algorithm: &str,
+        data: &[u8],
+        signature: &[u8],
+    ) -> Result<String, SoapFault>;
+}
+
+/// Signature of a request, added to the request extensions by
+/// [`RequireSignature`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifiedSignature {
+    pub user: String,
+    /// Identifiers of the signed elements
+    pub references: Vec<String>,
+    /// Whether the body of the envelope is one of them
+    pub body_signed: bool,
+}
+
+fn algorithm<'a>(parent: &'a Element, name: &str) -> Option<&'a str> {
+    parent
+        .get_child((name, DS_NAMESPACE))?
+        .attributes
+        .get("Algorithm")
+        .map(String::as_str)
+}
+
+/// `PrefixList` of the exclusive canonicalization parameters of `parent`
+fn inclusive_prefixes(parent: &Element) -> Vec<String> {
+    parent
+        .get_child(("InclusiveNamespaces", EXC_C14N_ALGORITHM))
+        .and_then(|e| e.attributes.get("PrefixList"))
+        .map(|l| l.split_whitespace().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+fn base64_text(elem: &Element) -> Option<Vec<u8>> {
+    let text: String = elem
+        .get_text()?
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    STANDARD.decode(text).ok()
+}
+
+fn digest(algorithm: &str, data: &[u8]) -> Option<Vec<u8>> {
+    match algorithm {
+        SHA1_DIGEST => Some(Sha1::digest(data).to_vec()),
+        SHA256_DIGEST => Some(Sha256::digest(data).to_vec()),
+        _ => None,
+    }
+}
+
+/// Whether `time` is within `max_skew` of `now`, in either direction
+fn within_skew(time: SystemTime, now: SystemTime, max_skew: Duration) -> bool {
+    now.duration_since(time)
+        .or_else(|_| time.duration_since(now))
+        .unwrap_or_default()
+        <= max_skew
+}
+
+/// Verify the signature of the `wsse:Security` header of an envelope, all
+/// its references must match and one of them must be the `wsu:Timestamp` of
+/// the header, created within `max_skew` of `now` and not expired. Failures
+/// are reported as `ter:NotAuthorized` faults.
+///
+/// Replayed signatures are only detected by [`RequireSignature`].
+pub fn verify_signature(
+    envelope: &[u8],
+    verifier: &dyn SignatureVerifier,
+    now: SystemTime,
+    max_skew: Duration,
+) -> Result<VerifiedSignature, SoapFault> {
+    verify_envelope(envelope, verifier, now, max_skew).map(|(signature, _)| signature)
+}
+
+/// Verified signature along with its `ds:SignatureValue`
+fn verify_envelope(
+    envelope: &[u8],
+    verifier: &dyn SignatureVerifier,
+    now: SystemTime,
+    max_skew: Duration,
+) -> Result<(VerifiedSignature, Vec<u8>), SoapFault> {
+    let root = Element::parse(envelope).map_err(|_| not_authorized())?;
+    let soap_namespace = root.namespace.as_deref().unwrap_or_default();
+    let security = root
+        .get_child(("Header", soap_namespace))
+        .and_then(|h| h.get_child(("Security", WSSE_NAMESPACE)))
+        .ok_or_else(not_authorized)?;
+    let signature = security
+        .get_child(("Signature", DS_NAMESPACE))
+        .ok_or_else(not_authorized)?;
+    let signed_info = signature
+        .get_child(("SignedInfo", DS_NAMESPACE))
+        .ok_or_else(not_authorized)?;
+    let canonicalization = signed_info
+        .get_child(("CanonicalizationMethod", DS_NAMESPACE))
+        .filter(|c| c.attributes.get("Algorithm").map(String::as_str) == Some(EXC_C14N_ALGORITHM))
+        .ok_or_else(not_authorized)?;
+    let signature_method = algorithm(signed_info, "SignatureMethod").ok_or_else(not_authorized)?;
+
+    let timestamp = security
+        .get_child(("Timestamp", WSU_NAMESPACE))
+        .ok_or_else(not_authorized)?;
+    let time = |name: &str| {
+        timestamp
+            .get_child((name, WSU_NAMESPACE))
+            .and_then(|t| t.get_text())
+            .map(|t| parse_date_time(&t).ok_or_else(not_authorized))
+            .transpose()
+    };
+    let created = time("Created")?.ok_or_else(not_authorized)?;
+    if !within_skew(created, now, max_skew) {
+        return Err(not_authorized());
+    }
+    if let Some(expires) = time("Expires")? {
+        if expires < created || (expires < now && !within_skew(expires, now, max_skew)) {
+            return Err(not_authorized());
+        }
+    }
+
+    let references: Vec<&Element> = signed_info
+        .children
+        .iter()
+        .filter_map(|c| c.as_element())
+        .filter(|e| e.name == "Reference" && e.namespace.as_deref() == Some(DS_NAMESPACE))
+        .collect();
+    if references.len() > MAX_REFERENCES {
+        return Err(not_authorized());
+    }
+    // Canonical forms of the body and of the timestamp for each list of
+    // inclusive prefixes, usually a single one
+    let mut signed_parts: HashMap<Vec<String>, (Option<String>, Option<String>)> = HashMap::new();
+    let mut ids = vec![];
+    let mut body_signed = false;
+    let mut timestamp_signed = false;
+    for reference in references {
+        let id = reference
+            .attributes
+            .get("URI")
+            .and_then(|u| u.strip_prefix('#'))
+            .ok_or_else(not_authorized)?;
+        let transforms: Vec<&Element> = reference
+            .get_child(("Transforms", DS_NAMESPACE))
+            .map(|t| t.children.iter().filter_map(|c| c.as_element()).collect())
+            .unwrap_or_default();
+        if transforms.is_empty()
+            || transforms.iter().any(|t| {
+                t.attributes.get("Algorithm").map(String::as_str) != Some(EXC_C14N_ALGORITHM)
+            })
+        {
+            return Err(not_authorized());
+        }
+        let prefixes: Vec<String> = transforms
+            .iter()
+            .flat_map(|t| inclusive_prefixes(t))
+            .collect();
+        let prefix_refs: Vec<&str> = prefixes.iter().map(String::as_str).collect();
+        let canonical = canonicalize_id(envelope, id, &prefix_refs)
+            .ok()
+            .flatten()
+            .ok_or_else(not_authorized)?;
+        let expected = algorithm(reference, "DigestMethod")
+            .and_then(|a| digest(a, canonical.as_bytes()))
+            .ok_or_else(not_authorized)?;
+        let value = reference
+            .get_child(("DigestValue", DS_NAMESPACE))
+            .and_then(base64_text)
+            .ok_or_else(not_authorized)?;
+        if !constant_time_eq(&value, &expected) {
+            return Err(not_authorized());
+        }
+        // Compare the canonical forms rather than the identifiers, the
+        // namespace of the attributes being lost once parsed
+        let (body, timestamp) = signed_parts.entry(prefixes.clone()).or_insert_with(|| {
+            let canonical = |namespace: &str, name: &str| {
+                canonicalize_element(envelope, namespace, name, &prefix_refs)
+                    .ok()
+                    .flatten()
+            };
+            (
+                canonical(soap_namespace, "Body"),
+                canonical(WSU_NAMESPACE, "Timestamp"),
+            )
+        });
+        body_signed |= body.as_ref() == Some(&canonical);
+        timestamp_signed |= timestamp.as_ref() == Some(&canonical);
+        ids.push(id.to_string());
+    }
+    if !timestamp_signed {
+        return Err(not_authorized());
+    }
+
+    let token_id = signature
+        .get_child(("KeyInfo", DS_NAMESPACE))
+        .and_then(|k| k.get_child(("SecurityTokenReference", WSSE_NAMESPACE)))
+        .and_then(|r| r.get_child(("Reference", WSSE_NAMESPACE)))
+        .and_then(|r| r.attributes.get("URI"))
+        .and_then(|u| u.strip_prefix('#'))
+        .ok_or_else(not_authorized)?;
+    let certificate = security
+        .children
+        .iter()
+        .filter_map(|c| c.as_element())
+        .find(|e| {
+            e.name == "BinarySecurityToken"
+                && e.namespace.as_deref() == Some(WSSE_NAMESPACE)
+                && e.attributes.get("Id").map(String::as_str) == Some(token_id)
+                && e.attributes.get("ValueType").map(String::as_str) == Some(X509_TOKEN)
+        })
+        .and_then(base64_text)
+        .ok_or_else(not_authorized)?;
+    let value = signature
+        .get_child(("SignatureValue", DS_NAMESPACE))
+        .and_then(base64_text)
+        .ok_or_else(not_authorized)?;
+    let prefixes = inclusive_prefixes(canonicalization);
+    let prefixes: Vec<&str> = prefixes.iter().map(String::as_str).collect();
+    let signed_info = canonicalize_element(envelope, DS_NAMESPACE, "SignedInfo", &prefixes)
+        .ok()
+        .flatten()
+        .ok_or_else(not_authorized)?;
+    let user = verifier.verify(
+        &certificate,
+        signature_method,
+        signed_info.as_bytes(),
+        &value,
+    )?;
+    Ok((
+        VerifiedSignature {
+            user,
+            references: ids,
+            body_signed,
+        },
+        value,
+    ))
+}
+
+/// Outcome of the verification of the signature of an envelope, left in the
+/// request extensions by the header processor for the layer of each operation
+#[derive(Clone, Debug)]
+struct SignatureCheck(Option<VerifiedSignature>);
+
+/// Layer rejecting the operations whose request body isn't signed with a
+/// trusted certificate with a `ter:NotAuthorized` fault. The
+/// [`AuthenticatedUser`] is the one the certificate maps to. Installing it
+/// with [`SoapRouter::require_signature`] after adding the operations to
+/// protect gates them individually.
+///
+/// The signature is verified once per envelope by the processor of the
+/// `wsse:Security` header, the layer only checks its outcome and answers a
+/// Receiver fault when the processor isn't installed.
+///
+/// [`SoapRouter::require_signature`]: crate::router::SoapRouter::require_signature
+pub struct RequireSignature<V> {
+    verifier: Arc<V>,
+    max_skew: Duration,
+    // Signature values seen within the skew window, to reject replays
+    seen: Arc<Mutex<HashMap<Vec<u8>, SystemTime>>>,
+    clock: Arc<Clock>,
+}
+
+impl<V> Clone for RequireSignature<V> {
+    fn clone(&self) -> Self {
+        Self {
+            verifier: self.verifier.clone(),
+            max_skew: self.max_skew,
+            seen: self.seen.clone(),
+            clock: self.clock.clone(),
+        }
+    }
+}
+
+impl<V: SignatureVerifier> RequireSignature<V> {
+    pub fn new(verifier: V) -> Self {
+        Self::from_shared(Arc::new(verifier))
+    }
+
+    pub fn from_shared(verifier: Arc<V>) -> Self {
+        Self {
+            verifier,
+            max_skew: Duration::from_secs(300),
+            seen: Default::default(),
+            clock: Arc::new(SystemTime::now),
+        }
+    }
+
+    /// Maximum difference between the `wsu:Timestamp` of the signature and
+    /// the local clock, 5 minutes by default as for
+    /// [`WsSecurity`](crate::soap_security::WsSecurity).
+    pub fn with_max_skew(mut self, max_skew: Duration) -> Self {
+        self.max_skew = max_skew;
+        self
+    }
+
+    /// Replace the clock timestamps are checked against
+    pub fn with_clock<F>(mut self, clock: F) -> Self
+    where
+        F: Fn() -> SystemTime + Send + Sync + 'static,
+    {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Verify the signature of an envelope with [`verify_signature`], and
+    /// reject signature values already seen within the skew window.
+    pub fn verify(&self, envelope: &[u8]) -> Result<VerifiedSignature, SoapFault> {
+        let now = (self.clock)();
+        let (signature, value) =
+            verify_envelope(envelope, self.verifier.as_ref(), now, self.max_skew)?;
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, expiry| *expiry > now);
+        if seen.contains_key(&value) {
+            return Err(not_authorized());
+        }
+        seen.insert(value, now + 2 * self.max_skew);
+        Ok(signature)
+    }
+}
+
+impl<V: SignatureVerifier> HeaderProcessor for RequireSignature<V> {
+    fn process(&self, _header: &Element, request: &mut SoapRequest) -> Result<(), SoapFault> {
+        // The signature is the one of the first Security header, whatever
+        // the number of blocks
+        if request.extensions.get::<SignatureCheck>().is_some() {
+            return Ok(());
+        }
+        // Operations not requiring a signature are still allowed
+        let signature = request
+            .extensions
+            .get::<RawEnvelope>()
+            .and_then(|raw| self.verify(&raw.0).ok());
+        request.extensions.insert(SignatureCheck(signature));
+        Ok(())
+    }
+}
+
+impl<V: SignatureVerifier> Layer<BoxedSoapHandlerService> for RequireSignature<V> {
+    type Service = RequireSignatureService<V>;
+
+    fn layer(&self, inner: BoxedSoapHandlerService) -> Self::Service {
+        RequireSignatureService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+pub struct RequireSignatureService<V> {
+    inner: BoxedSoapHandlerService,
+    layer: RequireSignature<V>,
+}
+
+impl<V> Clone for RequireSignatureService<V> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+impl<V: SignatureVerifier> Service<SoapRequest> for RequireSignatureService<V> {
+    type Response = SoapMessage;
+    type Error = SoapFault;
+    type Future = Pin<Box<dyn Future<Output = Result<SoapMessage, SoapFault>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: SoapRequest) -> Self::Future {
+        let check = req.extensions.get::<SignatureCheck>();
+        if check.is_none()
+            && req
+                .headers
+                .get_child(("Security", WSSE_NAMESPACE))
+                .is_some()
+        {
+            tracing::error!("RequireSignature layer installed without its header processor");
+            let fault = SoapFault::receiver("Signature verification not configured");
+            return Box::pin(futures::future::ready(Err(fault)));
+        }
+        match check.and_then(|check| check.0.clone()) {
+            Some(signature) if signature.body_signed => {
+                req.extensions
+                    .insert(AuthenticatedUser(signature.user.clone()));
+                req.extensions.insert(signature);
+                self.inner.call(req)
+            }
+            _ => Box::pin(futures::future::ready(Err(not_authorized()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+
+    use super::*;
+    use crate::router::SoapRouter;
+
+    /// Signatures are the SHA-256 of the certificate followed by the data,
+    /// only the `trusted` certificate is accepted
+    struct TestVerifier;
+
+    impl SignatureVerifier for TestVerifier {
+        fn verify(
+            &self,
+            certificate: &[u8],
+            algorithm: &str,
+            data: &[u8],
+            signature: &[u8],
+        ) -> Result<String, SoapFault> {
+            let expected = Sha256::digest([certificate, data].concat());
+            match certificate == b"trusted" && algorithm == RSA_SHA256_SIGNATURE {
+                true if signature == expected.as_slice() => Ok("camera-admin".to_string()),
+                _ => Err(not_authorized()),
+            }
+        }
+    }
+
+    const CREATED: &str = "2023-11-14T22:13:20Z";
+
+    fn now() -> SystemTime {
+        parse_date_time(CREATED).unwrap()
+    }
+
+    /// Envelope with its body and its timestamp signed with the given
+    /// certificate
+    fn signed_envelope(certificate: &[u8], body: &str) -> String {
+        signed_references(certificate, body, &["body", "ts"])
+    }
+
+    /// Envelope whose elements with the given identifiers are signed
+    fn signed_references(certificate: &[u8], body: &str, ids: &[&str]) -> String {
+        let envelope = |digests: &[String], signature: &str| {
+            let references: String = ids
+                .iter()
+                .zip(digests)
+                .map(|(id, digest)| {
+                    format!(
+                        r##"<ds:Reference URI="#{id}">
+                            <ds:Transforms><ds:Transform Algorithm="{EXC_C14N_ALGORITHM}"/></ds:Transforms>
+                            <ds:DigestMethod Algorithm="{SHA256_DIGEST}"/>
+                            <ds:DigestValue>{digest}</ds:DigestValue>
+                        </ds:Reference>"##
+                    )
+                })
+                .collect();
+            format!(
+                r##"<env:Envelope xmlns:env="http://www.w3.org/2003/05/soap-envelope" xmlns:wsse="{WSSE_NAMESPACE}" xmlns:wsu="{WSU_NAMESPACE}" xmlns:ds="{DS_NAMESPACE}" xmlns:m="http://www.example.org">
+                    <env:Header>
+                        <wsse:Security env:mustUnderstand="true">
+                            <wsu:Timestamp wsu:Id="ts"><wsu:Created>{CREATED}</wsu:Created></wsu:Timestamp>
+                            <wsse:BinarySecurityToken wsu:Id="cert" ValueType="{X509_TOKEN}">{}</wsse:BinarySecurityToken>
+                            <ds:Signature>
+                                <ds:SignedInfo>
+                                    <ds:CanonicalizationMethod Algorithm="{EXC_C14N_ALGORITHM}"/>
+                                    <ds:SignatureMethod Algorithm="{RSA_SHA256_SIGNATURE}"/>
+                                    {references}
+                                </ds:SignedInfo>
+                                <ds:SignatureValue>{signature}</ds:SignatureValue>
+                                <ds:KeyInfo><wsse:SecurityTokenReference><wsse:Reference URI="#cert"/></wsse:SecurityTokenReference></ds:KeyInfo>
+                            </ds:Signature>
+                        </wsse:Security>
+                    </env:Header>
+                    <env:Body wsu:Id="body">{body}</env:Body>
+                </env:Envelope>"##,
+                STANDARD.encode(certificate),
+            )
+        };
+        let unsigned = envelope(&vec![String::new(); ids.len()], "");
+        let digests: Vec<String> = ids
+            .iter()
+            .map(|id| {
+                let canonical = canonicalize_id(unsigned.as_bytes(), id, &[])
+                    .unwrap()
+                    .unwrap();
+                STANDARD.encode(Sha256::digest(canonical))
+            })
+            .collect();
+        let digested = envelope(&digests, "");
+        let signed_info =
+            canonicalize_element(digested.as_bytes(), DS_NAMESPACE, "SignedInfo", &[])
+                .unwrap()
+                .unwrap();
+        let signature = Sha256::digest([certificate, signed_info.as_bytes()].concat());
+        envelope(&digests, &STANDARD.encode(signature))
+    }
+
+    fn verify(envelope: &str) -> Result<VerifiedSignature, SoapFault> {
+        verify_signature(
+            envelope.as_bytes(),
+            &TestVerifier,
+            now(),
+            Duration::from_secs(300),
+        )
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let envelope = signed_envelope(b"trusted", "<m:Reboot/>");
+        assert_eq!(
+            verify(&envelope).unwrap(),
+            VerifiedSignature {
+                user: "camera-admin".to_string(),
+                references: vec!["body".to_string(), "ts".to_string()],
+                body_signed: true,
+            }
+        );
+
+        let tampered = envelope.replace("<m:Reboot/>", "<m:FactoryReset/>");
+        assert!(verify(&tampered).is_err());
+        let untrusted = signed_envelope(b"self-signed", "<m:Reboot/>");
+        assert!(verify(&untrusted).is_err());
+        // The signed body moved to a header, next to an unsigned one
+        let signed_body = r#"<env:Body wsu:Id="body"><m:Reboot/></env:Body>"#;
+        let wrapped = envelope
+            .replace(signed_body, "<env:Body><m:FactoryReset/></env:Body>")
+            .replace(
+                "</wsse:Security>",
+                &format!("</wsse:Security><m:Wrapper>{}</m:Wrapper>", signed_body),
+            );
+        assert!(!verify(&wrapped).unwrap().body_signed);
+
+        // Old or unsigned timestamps, and too many references
+        let late = now() + Duration::from_secs(600);
+        let stale = verify_signature(
+            envelope.as_bytes(),
+            &TestVerifier,
+            late,
+            Duration::from_secs(300),
+        );
+        assert!(stale.is_err());
+        assert!(verify(&signed_references(b"trusted", "<m:Reboot/>", &["body"])).is_err());
+        let ids = vec!["body"; MAX_REFERENCES].into_iter().chain(["ts"]);
+        let many = signed_references(b"trusted", "<m:Reboot/>", &ids.collect::<Vec<_>>());
+        assert!(verify(&many).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_require_signature() {
+        let ns = "http://www.example.org".to_string();
+        let whoami = |user: crate::extract::Extension<AuthenticatedUser>| async move {
+            let mut msg = SoapMessage::new();
+            let mut elem = Element::new("User");
+            elem.children
+                .push(xmltree::XMLNode::Text(user.0 .0.clone()));
+            msg.get_mut_body()
+                .children
+                .push(xmltree::XMLNode::Element(elem));
+            Ok(msg)
+        };
+        let mut router = SoapRouter::new(())
+            .add_operation(ns.clone(), "Reboot".to_string(), whoami)
+            .require_signature(RequireSignature::new(TestVerifier).with_clock(now))
+            .add_operation(ns, "GetTime".to_string(), || async {
+                Ok(SoapMessage::new())
+            });
+
+        for (envelope, user) in [
+            (
+                signed_envelope(b"trusted", "<m:Reboot/>"),
+                Some("camera-admin"),
+            ),
+            (signed_envelope(b"untrusted", "<m:Reboot/>"), None),
+            (
+                signed_envelope(b"trusted", "<m:Reboot/>").replace(" wsu:Id=\"body\"", ""),
+                None,
+            ),
+            (signed_envelope(b"untrusted", "<m:GetTime/>"), Some("")),
+            // Replayed signature
+            (signed_envelope(b"trusted", "<m:Reboot/>"), None),
+            // Verified once for all the operations of the envelope
+            (
+                signed_envelope(b"trusted", "<m:Reboot/><m:Reboot/>"),
+                Some("camera-admin"),
+            ),
+            (signed_envelope(b"trusted", "<m:Reboot/><m:Reboot/>"), None),
+        ] {
+            let req: Request<Body> = Request::builder().uri("/").body(envelope.into()).unwrap();
+            let resp = router.call(req).await.unwrap();
+            let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let msg = SoapMessage::from(Element::parse(body.as_ref()).unwrap());
+            let fault = msg
+                .get_body()
+                .get_child(("Fault", "http://www.w3.org/2003/05/soap-envelope"));
+            assert_eq!(fault.is_none(), user.is_some());
+            if let Some(user) = user.filter(|u| !u.is_empty()) {
+                assert_eq!(
+                    msg.get_body()
+                        .get_child("User")
+                        .and_then(|u| u.get_text())
+                        .as_deref(),
+                    Some(user)
+                );
+            }
+        }
+
+        // The layer alone can't enforce signatures
+        let mut router = SoapRouter::new(())
+            .add_understood_header(WSSE_NAMESPACE.to_string(), "Security".to_string())
+            .add_operation(
+                "http://www.example.org".to_string(),
+                "Reboot".to_string(),
+                || async { Ok(SoapMessage::new()) },
+            )
+            .route_layer(RequireSignature::new(TestVerifier).with_clock(now));
+        let envelope = signed_envelope(b"trusted", "<m:Reboot/>");
+        let req: Request<Body> = Request::builder().uri("/").body(envelope.into()).unwrap();
+        let resp = router.call(req).await.unwrap();
+        assert_eq!(resp.status(), axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}